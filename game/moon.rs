@@ -1,60 +1,19 @@
 use core::f32;
 
-use engine::{
-    asset_manager::{
-        MeshId,
-        light::{Light, LightKind},
-        mesh::{Index, Primitive, Vertex},
-    },
-    game::Game,
-    render::{Camera, ForwardRenderer, RenderCommand},
-};
+use engine::prelude::*;
 use glam::{Vec2, Vec3};
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen(start)]
-pub async fn wasm_main() {
-    use engine::core::run;
-
-    console_error_panic_hook::set_once();
+pub fn wasm_main() {
+    engine::run(MoonGame::new(), WindowConfig::default()).unwrap_throw();
 }
 
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
-    use engine::app::App;
-    use winit::event_loop::{ControlFlow, EventLoop};
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        env_logger::init();
-    }
-    #[cfg(target_arch = "wasm32")]
-    {
-        console_log::init_with_level(log::Level::Info).unwrap_throw();
-    }
-
-    let world = hecs::World::new();
-
-    let game = MoonGame::new();
-
-    println!("here");
-
-    let mut app = App {
-        world,
-        renderer: None,
-        window: None,
-        game,
-    };
-
-    let event_loop = EventLoop::new().unwrap();
-    event_loop.set_control_flow(ControlFlow::Poll);
-
-    #[cfg(not(target_arch = "wasm32"))]
-    event_loop.run_app(&mut app).unwrap();
-
-    #[cfg(target_arch = "wasm32")]
-    event_loop.spawn_app(app);
+    engine::run(MoonGame::new(), WindowConfig::default()).unwrap();
 }
 
 struct MoonGame {
@@ -65,16 +24,32 @@ struct MoonGame {
 }
 impl Game for MoonGame {
     fn setup(&mut self, _world: &mut hecs::World, renderer: &mut ForwardRenderer) {
-        let id = renderer.asset.set_mesh(&self.mesh, "moon");
-        self.mesh_id = Some(id);
+        // one primitive, so the tiles share their edge vertices
+        match renderer
+            .asset
+            .set_mesh(&[Primitive::merge(&self.mesh)], "moon")
+        {
+            Ok(id) => self.mesh_id = Some(id),
+            Err(e) => eprintln!("moon: {}", e),
+        }
     }
-    fn update(&mut self, _world: &mut hecs::World, renderer: &mut ForwardRenderer) {
-        if let Some(id) = self.mesh_id {
-            let mesh_id = renderer.asset.get_mesh("");
-            let action = &[
-                //RenderCommand { mesh_id: id },
-                RenderCommand { mesh_id: mesh_id },
-            ];
+    fn update(
+        &mut self,
+        _world: &mut hecs::World,
+        renderer: &mut ForwardRenderer,
+        _window: &dyn WindowHandle,
+        time: &Time,
+    ) {
+        // swing the sun around the vertical axis once a minute
+        let angle = time.elapsed_seconds() * std::f32::consts::TAU / 60.0;
+        let dir = glam::Quat::from_rotation_y(angle) * Vec3::new(0.0, -1.0, 0.1);
+        self.sun.direction = dir.to_array();
+
+        if let Some(mesh_id) = self.mesh_id {
+            let action = &[RenderCommand {
+                mesh_id,
+                ..Default::default()
+            }];
 
             renderer.render(&[self.sun], &self.cam, action);
         }
@@ -87,11 +62,7 @@ impl MoonGame {
         let center_high_res_factor = 1.0_f32;
 
         let mut meshes: [Primitive; 9] = {
-            let empty = Primitive {
-                vertex: Vec::new(),
-                index: Vec::new(),
-                material: None,
-            };
+            let empty = Primitive::default();
             [
                 empty.clone(),
                 empty.clone(),
@@ -153,6 +124,7 @@ impl MoonGame {
             range: f32::INFINITY,
             inner_angle: 0.0,
             outer_angle: 0.0,
+            ..Default::default()
         };
 
         let cam = Camera {
@@ -230,7 +202,7 @@ impl MoonGame {
         Primitive {
             vertex,
             index,
-            material: None,
+            ..Default::default()
         }
     }
 }
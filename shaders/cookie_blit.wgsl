@@ -0,0 +1,26 @@
+// Copies a cookie texture into one layer of the cookie atlas, rescaling it to the layer size.
+
+@group(0) @binding(0)
+var t_source : texture_2d<f32>;
+@group(0) @binding(1)
+var s_source : sampler;
+
+struct VSOut {
+    @builtin(position) pos_clip : vec4<f32>,
+    @location(0) uv : vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vid: u32) -> VSOut {
+    // fullscreen triangle
+    let uv = vec2<f32>(f32((vid << 1u) & 2u), f32(vid & 2u));
+    var out : VSOut;
+    out.pos_clip = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VSOut) -> @location(0) vec4<f32> {
+    return textureSample(t_source, s_source, in.uv);
+}
@@ -0,0 +1,10 @@
+// shaders/depth.wgsl
+// Helpers for effects that read the depth target (fog, SSAO, deferred lighting).
+
+// Reconstructs the world position seen at `uv` (0..1, y down) from a raw depth value, using
+// camera.inv_view_proj passed as `inv_view_proj`.
+fn world_pos_from_depth(uv: vec2<f32>, depth: f32, inv_view_proj: mat4x4<f32>) -> vec3<f32> {
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let p = inv_view_proj * ndc;
+    return p.xyz / p.w;
+}
@@ -1,11 +1,17 @@
 // shaders/forward.wgsl
+// Variants are selected with #ifdef HAS_NORMAL_MAP / UNLIT / PREMULTIPLIED_ALPHA, see
+// render/shader_defs.rs.
 const MAX_LIGHTS : u32 = 16u;
 const PI : f32 = 3.14159265359;
+// Material.model, see MaterialModel in material.rs
+const MODEL_PBR  : u32 = 0u;
+const MODEL_TOON : u32 = 1u;
 
 struct Camera {
     view_proj : mat4x4<f32>,
     camera_pos: vec3<f32>,
     _pad0     : f32,
+    inv_view_proj : mat4x4<f32>,
 };
 
 //@group(0) @binding(0)
@@ -13,13 +19,17 @@ struct Camera {
 
 // ---- Lights ----
 struct GpuLight {
-    position   : vec3<f32>,  _pad0 : f32,
-    color      : vec3<f32>,  _pad1 : f32,
+    position   : vec3<f32>,  shadow_bias : f32,
+    color      : vec3<f32>,  shadow_strength : f32,
     direction  : vec3<f32>,  light_type : u32,
     range      : f32,
     inner_cos  : f32,
     outer_cos  : f32,
-    _pad2      : f32,
+    shadow_index : i32,
+    view_proj    : mat4x4<f32>,
+    cookie_index : i32,
+    enabled      : u32,
+    _pad3 : i32, _pad4 : i32,
 };
 
 struct LightBuffer {
@@ -44,6 +54,77 @@ var<storage, read> u_lights : LightBuffer;
 @group(0) @binding(2)
 var<uniform> u_lightParams : LightParams;
 
+// ---- Point Shadows ----
+// One cube per shadow-casting point light, storing distance / range.
+
+@group(0) @binding(3)
+var t_point_shadow : texture_depth_cube_array;
+
+@group(0) @binding(4)
+var s_shadow : sampler_comparison;
+
+// ---- Objects ----
+// Draws pass their object index as the first instance.
+struct Object {
+    model      : mat4x4<f32>,
+    light_mask : u32,
+};
+
+@group(0) @binding(5)
+var<storage, read> objects : array<Object>;
+
+// ---- Fog ----
+struct Fog {
+    color   : vec3<f32>,
+    density : f32,
+    start   : f32,
+    enabled : u32,
+};
+
+@group(0) @binding(6)
+var<uniform> fog : Fog;
+
+// ---- Spot Cookies ----
+// One layer per cookie texture, see render/cookies.rs.
+@group(0) @binding(7)
+var t_cookies : texture_2d_array<f32>;
+
+@group(0) @binding(8)
+var s_cookie : sampler;
+
+// ---- Light Clusters ----
+// Light lists per view-space cluster, filled by light_clusters.wgsl each frame.
+struct ClusterParams {
+    view         : mat4x4<f32>,
+    dims         : vec3<u32>,
+    max_lights   : u32,
+    near         : f32,
+    far          : f32,
+    tan_half_fov : vec2<f32>,
+};
+
+@group(0) @binding(9)
+var<uniform> clusters : ClusterParams;
+
+// per cluster: the light count, then max_lights light indices
+@group(0) @binding(10)
+var<storage, read> cluster_lights : array<u32>;
+
+fn cluster_index(pos_ws: vec3<f32>) -> u32 {
+    let dims = clusters.dims;
+    let clip = camera.view_proj * vec4<f32>(pos_ws, 1.0);
+    let ndc = clip.xy / clip.w;
+    let tile = clamp(
+        vec2<i32>(floor((ndc * 0.5 + 0.5) * vec2<f32>(dims.xy))),
+        vec2<i32>(0),
+        vec2<i32>(dims.xy) - 1,
+    );
+    let depth = -(clusters.view * vec4<f32>(pos_ws, 1.0)).z;
+    let slice = log(max(depth, clusters.near) / clusters.near) / log(clusters.far / clusters.near);
+    let z = clamp(i32(floor(slice * f32(dims.z))), 0, i32(dims.z) - 1);
+    return (u32(z) * dims.y + u32(tile.y)) * dims.x + u32(tile.x);
+}
+
 // ---- Materials ----
 struct Material {
     base_color_factor : vec4<f32>,
@@ -53,6 +134,10 @@ struct Material {
     roughness_factor  : f32,
     alpha_cutoff      : f32,
     double_sided      : u32,
+    normal_scale      : f32,
+    detail_tiling     : f32,
+    detail_strength   : f32,
+    model             : u32,
 };
 
 @group(2) @binding(0)
@@ -68,29 +153,18 @@ struct MaterialParams {
 var<uniform> material_params : MaterialParams;
 
 // ---- PBR Textures ----
-@group(1) @binding(0)
-var t_base_color: texture_2d<f32>;
-@group(1) @binding(1)
-var s_base_color: sampler;
-@group(1) @binding(2)
-var t_metallic_roughness: texture_2d<f32>;
-@group(1) @binding(3)
-var s_metallic_roughness: sampler;
-@group(1) @binding(4)
-var t_normal: texture_2d<f32>;
-@group(1) @binding(5)
-var s_normal: sampler;
-@group(1) @binding(6)
-var t_emissive: texture_2d<f32>;
-@group(1) @binding(7)
-var s_emissive: sampler;
-
+// Group 1 and the sample_* helpers are appended from textures_bindless.wgsl or
+// textures_bound.wgsl depending on what the adapter supports.
 
 // ---- Vertex I/O ----
 struct VSIn {
     @location(0) position : vec3<f32>,
     @location(1) uv       : vec2<f32>,
+#ifdef PACKED_VERTICES
+    @location(2) normal_oct : vec2<f32>, // octahedral, see packing.rs
+#else
     @location(2) normal   : vec3<f32>,
+#endif
     @location(3) tangent  : vec4<f32>, // Added for normal mapping
 };
 
@@ -98,30 +172,41 @@ struct VSOut {
     @builtin(position) pos_clip : vec4<f32>,
     @location(0) uv         : vec2<f32>,
     @location(1) pos_ws     : vec3<f32>,
-    @location(2) t_ws       : vec3<f32>, // Tangent
-    @location(3) b_ws       : vec3<f32>, // Bitangent
-    @location(4) n_ws       : vec3<f32>, // Normal
+    @location(2) t_ws       : vec4<f32>, // Tangent, w is the bitangent sign
+    @location(3) n_ws       : vec3<f32>, // Normal
+    @location(4) @interpolate(flat) light_mask : u32,
 };
 
+#ifdef PACKED_VERTICES
+fn oct_decode(e: vec2<f32>) -> vec3<f32> {
+    var n = vec3<f32>(e, 1.0 - abs(e.x) - abs(e.y));
+    let t = max(-n.z, 0.0);
+    n.x -= select(-t, t, n.x >= 0.0);
+    n.y -= select(-t, t, n.y >= 0.0);
+    return normalize(n);
+}
+#endif
+
 @vertex
-fn vs_main(in: VSIn) -> VSOut {
+fn vs_main(in: VSIn, @builtin(instance_index) object_index: u32) -> VSOut {
     var out: VSOut;
-    out.pos_clip = camera.view_proj * vec4<f32>(in.position, 1.0);
+    let model  = objects[object_index].model;
+    let pos_ws = (model * vec4<f32>(in.position, 1.0)).xyz;
+    out.pos_clip = camera.view_proj * vec4<f32>(pos_ws, 1.0);
     out.uv       = in.uv;
-    out.pos_ws   = in.position;
-
-    // NOTE: Assumes world space == object space. If a model transform is used,
-    // it must be applied to normal, tangent, and bitangent as well.
-    let n = normalize(in.normal);
-    let t = normalize(in.tangent.xyz);
-    // Gram-Schmidt re-orthogonalize T with respect to N
-    let t_ortho = normalize(t - dot(t, n) * n);
-    // Calculate bitangent using handedness from tangent.w
-    let b = cross(n, t_ortho) * in.tangent.w;
-
-    out.n_ws = n;
-    out.t_ws = t_ortho;
-    out.b_ws = b;
+    out.pos_ws   = pos_ws;
+    out.light_mask = objects[object_index].light_mask;
+
+    // NOTE: The upper 3x3 is only a correct normal transform for uniform scale.
+    let normal_mat = mat3x3<f32>(model[0].xyz, model[1].xyz, model[2].xyz);
+#ifdef PACKED_VERTICES
+    let normal = oct_decode(in.normal_oct);
+#else
+    let normal = in.normal;
+#endif
+    // the tangent frame is completed per fragment, after interpolation
+    out.n_ws = normalize(normal_mat * normal);
+    out.t_ws = vec4<f32>(normalize(normal_mat * in.tangent.xyz), in.tangent.w);
 
     return out;
 }
@@ -165,6 +250,72 @@ fn geometry_smith(N: vec3<f32>, V: vec3<f32>, L: vec3<f32>, roughness: f32) -> f
     return ggx1 * ggx2;
 }
 
+// Metallic-roughness BRDF times N.L, for one light
+fn shade_pbr(N: vec3<f32>, V: vec3<f32>, L: vec3<f32>, albedo: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32> {
+    let H = normalize(V + L);
+    let NDF = distribution_ggx(N, H, roughness);
+    let G   = geometry_smith(N, V, L, roughness);
+
+    // F0: dielectric default 0.04, lerp to albedo for metals
+    var F0 = vec3<f32>(0.04);
+    F0 = F0 + (albedo - F0) * metallic; // mix() is equivalent
+
+    let cosHV = max(dot(H, V), 0.0);
+    let F = fresnel_schlick(cosHV, F0);
+
+    let NdotV = max(dot(N, V), 0.0);
+    let NdotL = max(dot(N, L), 0.0);
+
+    let numerator   = NDF * G * F;
+    let denominator = max(4.0 * NdotV * NdotL, 1e-6);
+    let specular    = numerator / denominator;
+
+    let kS = F;
+    let kD = (vec3<f32>(1.0) - kS) * (1.0 - metallic);
+
+    let diffuse = kD * albedo / PI;
+
+    return (diffuse + specular) * NdotL;
+}
+
+// Cel shading: two flat diffuse bands and a hard highlight that widens with roughness
+fn shade_toon(N: vec3<f32>, V: vec3<f32>, L: vec3<f32>, albedo: vec3<f32>, roughness: f32) -> vec3<f32> {
+    let NdotL = dot(N, L);
+    let band = select(select(0.0, 0.5, NdotL > 0.0), 1.0, NdotL > 0.5);
+    let NdotH = dot(N, normalize(V + L));
+    let highlight = select(0.0, 0.5, NdotL > 0.0 && NdotH > 1.0 - 0.05 * roughness);
+    return albedo / PI * band + vec3<f32>(highlight);
+}
+
+fn point_shadow(Ld: GpuLight, pos_ws: vec3<f32>) -> f32 {
+    if (Ld.shadow_index < 0) {
+        return 1.0;
+    }
+    let toFrag = pos_ws - Ld.position;
+    let depth  = length(toFrag) / max(Ld.range, 1e-3);
+    let lit = textureSampleCompareLevel(t_point_shadow, s_shadow, toFrag, Ld.shadow_index, depth - Ld.shadow_bias);
+    return mix(1.0, lit, Ld.shadow_strength);
+}
+
+// Projects the fragment into the spot cone and reads the light's cookie there.
+fn spot_cookie(Ld: GpuLight, pos_ws: vec3<f32>) -> vec3<f32> {
+    if (Ld.cookie_index < 0) {
+        return vec3<f32>(1.0);
+    }
+    let clip = Ld.view_proj * vec4<f32>(pos_ws, 1.0);
+    if (clip.w <= 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let uv = clip.xy / clip.w * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5);
+    return textureSampleLevel(t_cookies, s_cookie, uv, Ld.cookie_index, 0.0).rgb;
+}
+
+// Exponential fog by distance past fog.start; must match fog_factor in render/fog.rs
+fn fog_factor(dist: f32) -> f32 {
+    let d = max(dist - fog.start, 0.0);
+    return 1.0 - exp(-fog.density * d);
+}
+
 // Simple Reinhard tonemap
 fn tonemap_reinhard(x: vec3<f32>) -> vec3<f32> {
     return x / (x + vec3<f32>(1.0));
@@ -178,42 +329,74 @@ fn fs_main(in: VSOut) -> @location(0) vec4<f32> {
 
     // --- Material Properties from Textures and Factors ---
     // Albedo
-    let base_color_sample = textureSample(t_base_color, s_base_color, in.uv);
-    let albedo = base_color_sample.rgb * mat.base_color_factor.rgb;
+    let base_color_sample = sample_base_color(in.uv);
+#ifdef PREMULTIPLIED_ALPHA
+    // filtered premultiplied; shading wants the straight color, the output is premultiplied
+    let straight_rgb = min(base_color_sample.rgb / max(base_color_sample.a, 1e-4), vec3<f32>(1.0));
+    let base_albedo = straight_rgb * mat.base_color_factor.rgb;
+#else
+    let base_albedo = base_color_sample.rgb * mat.base_color_factor.rgb;
+#endif
+    // tiled independently of the base color; white, or a strength of 0, changes nothing
+    let detail_sample = sample_detail(in.uv * mat.detail_tiling);
+    let albedo = base_albedo * mix(vec3<f32>(1.0), detail_sample.rgb, mat.detail_strength);
 
     // Metallic and Roughness (glTF standard: B channel=metallic, G channel=roughness)
-    let metallic_roughness_sample = textureSample(t_metallic_roughness, s_metallic_roughness, in.uv);
+    let metallic_roughness_sample = sample_metallic_roughness(in.uv);
     let metallic = metallic_roughness_sample.b * mat.metallic_factor;
     let roughness = clamp(metallic_roughness_sample.g * mat.roughness_factor, 0.02, 1.0);
 
     // Normal Mapping
-    let tbn = mat3x3<f32>(normalize(in.t_ws), normalize(in.b_ws), normalize(in.n_ws));
-    var normal_map_sample = textureSample(t_normal, s_normal, in.uv).rgb;
+#ifdef HAS_NORMAL_MAP
+    // interpolation denormalizes the frame and skews T off N, so Gram-Schmidt it again and
+    // derive B from the handedness
+    let n_geom = normalize(in.n_ws);
+    let t = normalize(in.t_ws.xyz - dot(in.t_ws.xyz, n_geom) * n_geom);
+    let b = cross(n_geom, t) * select(1.0, -1.0, in.t_ws.w < 0.0);
+    let tbn = mat3x3<f32>(t, b, n_geom);
     // Unpack from [0, 1] range to [-1, 1] range
-    normal_map_sample = normalize(normal_map_sample * 2.0 - 1.0);
-    let N = normalize(tbn * normal_map_sample);
+    var normal_ts = sample_normal(in.uv).rgb * 2.0 - 1.0;
+    normal_ts = vec3<f32>(normal_ts.xy * mat.normal_scale, normal_ts.z);
+    let N = normalize(tbn * normal_ts);
+#else
+    let N = normalize(in.n_ws);
+#endif
 
     // Emissive
-    let emissive_sample = textureSample(t_emissive, s_emissive, in.uv);
+    let emissive_sample = sample_emissive(in.uv);
     let emissive = mat.emissive_factor * emissive_sample.rgb;
 
+#ifdef UNLIT
+    var color = albedo + emissive;
+#else
     // --- PBR Lighting Calculation ---
     // View direction
     let V = normalize(camera.camera_pos - in.pos_ws);
     var Lo = vec3<f32>(0.0);
 
-    let count = min(u_lightParams.count, MAX_LIGHTS);
-    for (var i: u32 = 0u; i < count; i = i + 1u) {
+    // only the lights assigned to this fragment's cluster
+    let base = cluster_index(in.pos_ws) * (clusters.max_lights + 1u);
+    let count = min(cluster_lights[base], clusters.max_lights);
+    for (var j: u32 = 0u; j < count; j = j + 1u) {
+        let i = min(cluster_lights[base + 1u + j], MAX_LIGHTS - 1u);
+        // lights out of range of this object, culled on the CPU
+        if ((in.light_mask & (1u << i)) == 0u) {
+            continue;
+        }
         let Ld = u_lights.lights[i];
+        if (Ld.enabled == 0u) {
+            continue;
+        }
 
         var L : vec3<f32>;
         var att : f32 = 1.0;
+        var light_color = Ld.color;
 
         if (Ld.light_type == 0u) { // Point
             let toL  = Ld.position - in.pos_ws;
             let dist = length(toL);
             L        = normalize(toL);
-            att      = range_atten(dist, Ld.range);
+            att      = range_atten(dist, Ld.range) * point_shadow(Ld, in.pos_ws);
         } else if (Ld.light_type == 1u) { // Directional
             L = normalize(-Ld.direction);
         } else { // Spot
@@ -223,40 +406,37 @@ fn fs_main(in: VSOut) -> @location(0) vec4<f32> {
             let spotC = dot(-L, normalize(Ld.direction));
             let cone = saturate((spotC - Ld.outer_cos) / max(Ld.inner_cos - Ld.outer_cos, 1e-4));
             att      = range_atten(dist, Ld.range) * cone;
+            light_color = light_color * spot_cookie(Ld, in.pos_ws);
         }
 
-        let H = normalize(V + L);
-        let NDF = distribution_ggx(N, H, roughness);
-        let G   = geometry_smith(N, V, L, roughness);
-
-        // F0: dielectric default 0.04, lerp to albedo for metals
-        var F0 = vec3<f32>(0.04);
-        F0 = F0 + (albedo - F0) * metallic; // mix() is equivalent
-
-        let cosHV = max(dot(H, V), 0.0);
-        let F = fresnel_schlick(cosHV, F0);
-
-        let NdotV = max(dot(N, V), 0.0);
-        let NdotL = max(dot(N, L), 0.0);
-
-        let numerator   = NDF * G * F;
-        let denominator = max(4.0 * NdotV * NdotL, 1e-6);
-        let specular    = numerator / denominator;
-
-        let kS = F;
-        let kD = (vec3<f32>(1.0) - kS) * (1.0 - metallic);
-
-        let diffuse = kD * albedo / PI;
-
-        Lo += (diffuse + specular) * Ld.color * NdotL * att;
+        var shaded : vec3<f32>;
+        if (mat.model == MODEL_TOON) {
+            shaded = shade_toon(N, V, L, albedo, roughness);
+        } else {
+            shaded = shade_pbr(N, V, L, albedo, metallic, roughness);
+        }
+        Lo += shaded * light_color * att;
     }
 
 
     // --- Final Color Composition ---
-    // HDR accumulation -> simple tonemap -> gamma correction
+    // HDR accumulation -> simple tonemap; the sRGB target applies the gamma encode
     var color = Lo + emissive;
+#endif
+#ifndef LINEAR_OUTPUT
     color = tonemap_reinhard(color);
+#endif
+    if (fog.enabled != 0u) {
+        color = mix(color, fog.color, fog_factor(length(camera.camera_pos - in.pos_ws)));
+    }
+#ifdef ENCODE_SRGB
     color = pow(max(color, vec3<f32>(0.0)), vec3<f32>(1.0 / 2.2));
-
-    return vec4<f32>(color, base_color_sample.a * mat.base_color_factor.a);
+#endif
+
+    let alpha = base_color_sample.a * mat.base_color_factor.a;
+#ifdef PREMULTIPLIED_ALPHA
+    return vec4<f32>(color * alpha, alpha);
+#else
+    return vec4<f32>(color, alpha);
+#endif
 }
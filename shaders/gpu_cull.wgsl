@@ -0,0 +1,56 @@
+// Frustum culls instance transforms against the mesh bounds and compacts the visible ones
+// into the object buffer; the visible count becomes the indirect draws' instance count.
+// Must match render/gpu_cull.rs and transform_aabb in render/frustum.rs.
+
+struct Object {
+    model      : mat4x4<f32>,
+    light_mask : u32,
+};
+
+struct CullParams {
+    // (normal, distance) with normals pointing inside
+    planes   : array<vec4<f32>, 6>,
+    aabb_min : vec4<f32>,
+    aabb_max : vec4<f32>,
+    count    : u32,
+    _pad0    : u32,
+    _pad1    : u32,
+    _pad2    : u32,
+};
+
+@group(0) @binding(0)
+var<storage, read> instances : array<Object>;
+@group(0) @binding(1)
+var<storage, read_write> visible : array<Object>;
+@group(0) @binding(2)
+var<storage, read_write> visible_count : atomic<u32>;
+@group(0) @binding(3)
+var<uniform> params : CullParams;
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let i = gid.x;
+    if (i >= params.count) {
+        return;
+    }
+    let m = instances[i].model;
+
+    // world-space AABB of the transformed local box
+    let center = (params.aabb_min.xyz + params.aabb_max.xyz) * 0.5;
+    let extent = (params.aabb_max.xyz - params.aabb_min.xyz) * 0.5;
+    let world_center = (m * vec4<f32>(center, 1.0)).xyz;
+    let world_extent = abs(m[0].xyz) * extent.x + abs(m[1].xyz) * extent.y + abs(m[2].xyz) * extent.z;
+    let wmin = world_center - world_extent;
+    let wmax = world_center + world_extent;
+
+    for (var p = 0u; p < 6u; p = p + 1u) {
+        let plane = params.planes[p];
+        let corner = select(wmin, wmax, plane.xyz >= vec3<f32>(0.0));
+        if (dot(plane.xyz, corner) + plane.w < 0.0) {
+            return;
+        }
+    }
+
+    let slot = atomicAdd(&visible_count, 1u);
+    visible[slot] = instances[i];
+}
@@ -0,0 +1,69 @@
+// Infinite ground grid: a fullscreen triangle whose fragments are intersected with the plane
+// y = height, drawn after the opaque meshes and faded out with distance from the camera.
+
+struct Grid {
+    view_proj     : mat4x4<f32>,
+    inv_view_proj : mat4x4<f32>,
+    camera_pos    : vec3<f32>,
+    cell_size     : f32,
+    color         : vec4<f32>,
+    fade_distance : f32,
+    height        : f32,
+    _pad          : vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> grid : Grid;
+
+struct VSOut {
+    @builtin(position) pos_clip : vec4<f32>,
+    @location(0) near_ws : vec3<f32>,
+    @location(1) far_ws  : vec3<f32>,
+};
+
+fn unproject(ndc: vec3<f32>) -> vec3<f32> {
+    let p = grid.inv_view_proj * vec4<f32>(ndc, 1.0);
+    return p.xyz / p.w;
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) vid: u32) -> VSOut {
+    let uv = vec2<f32>(f32((vid << 1u) & 2u), f32(vid & 2u));
+    let ndc = uv * 2.0 - 1.0;
+
+    var out: VSOut;
+    out.pos_clip = vec4<f32>(ndc, 0.0, 1.0);
+    out.near_ws = unproject(vec3<f32>(ndc, 0.0));
+    out.far_ws = unproject(vec3<f32>(ndc, 1.0));
+    return out;
+}
+
+struct FSOut {
+    @location(0) color : vec4<f32>,
+    @builtin(frag_depth) depth : f32,
+};
+
+@fragment
+fn fs_main(in: VSOut) -> FSOut {
+    let dir = in.far_ws - in.near_ws;
+    let t = (grid.height - in.near_ws.y) / dir.y;
+    let pos_ws = in.near_ws + t * dir;
+
+    // derivatives are taken before the discard below
+    let coord = pos_ws.xz / grid.cell_size;
+    let dist = abs(fract(coord - 0.5) - 0.5) / fwidth(coord);
+    let line = 1.0 - min(min(dist.x, dist.y), 1.0);
+
+    let fade = 1.0 - clamp(distance(pos_ws, grid.camera_pos) / grid.fade_distance, 0.0, 1.0);
+    let alpha = grid.color.a * line * fade;
+    if (t <= 0.0 || alpha <= 0.0) {
+        discard;
+    }
+
+    let clip = grid.view_proj * vec4<f32>(pos_ws, 1.0);
+
+    var out: FSOut;
+    out.color = vec4<f32>(grid.color.rgb, alpha);
+    out.depth = clip.z / clip.w;
+    return out;
+}
@@ -0,0 +1,92 @@
+// Assigns lights to the clusters of a view-space grid: x/y tiles across the screen and z
+// slices spaced exponentially from the near to the far plane. One invocation per cluster.
+// Must match render/clusters.rs and cluster_index in forward.wgsl.
+
+const MAX_LIGHTS : u32 = 16u;
+
+struct GpuLight {
+    position   : vec3<f32>,  shadow_bias : f32,
+    color      : vec3<f32>,  shadow_strength : f32,
+    direction  : vec3<f32>,  light_type : u32,
+    range      : f32,
+    inner_cos  : f32,
+    outer_cos  : f32,
+    shadow_index : i32,
+    view_proj    : mat4x4<f32>,
+    cookie_index : i32,
+    enabled      : u32,
+    _pad3 : i32, _pad4 : i32,
+};
+
+struct LightBuffer {
+    lights : array<GpuLight, MAX_LIGHTS>,
+};
+
+struct LightParams {
+    count : u32,
+};
+
+struct ClusterParams {
+    view         : mat4x4<f32>,
+    dims         : vec3<u32>,
+    max_lights   : u32,
+    near         : f32,
+    far          : f32,
+    // view-space x and y per unit of depth at the edge of the screen
+    tan_half_fov : vec2<f32>,
+};
+
+@group(0) @binding(0)
+var<storage, read> u_lights : LightBuffer;
+@group(0) @binding(1)
+var<uniform> u_lightParams : LightParams;
+@group(0) @binding(2)
+var<uniform> clusters : ClusterParams;
+// per cluster: the light count, then max_lights light indices
+@group(0) @binding(3)
+var<storage, read_write> cluster_lights : array<u32>;
+
+fn slice_depth(k: u32) -> f32 {
+    return clusters.near * pow(clusters.far / clusters.near, f32(k) / f32(clusters.dims.z));
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) gid: vec3<u32>) {
+    let dims = clusters.dims;
+    let index = gid.x;
+    if (index >= dims.x * dims.y * dims.z) {
+        return;
+    }
+    let x = index % dims.x;
+    let y = (index / dims.x) % dims.y;
+    let z = index / (dims.x * dims.y);
+
+    // view-space box around the tile's ndc rect between the slice's two depths
+    let ndc_min = vec2<f32>(f32(x), f32(y)) / vec2<f32>(dims.xy) * 2.0 - 1.0;
+    let ndc_max = vec2<f32>(f32(x + 1u), f32(y + 1u)) / vec2<f32>(dims.xy) * 2.0 - 1.0;
+    let a = ndc_min * clusters.tan_half_fov;
+    let b = ndc_max * clusters.tan_half_fov;
+    let d0 = slice_depth(z);
+    let d1 = slice_depth(z + 1u);
+    let box_min = vec3<f32>(min(min(a * d0, a * d1), min(b * d0, b * d1)), -d1);
+    let box_max = vec3<f32>(max(max(a * d0, a * d1), max(b * d0, b * d1)), -d0);
+
+    let base = index * (clusters.max_lights + 1u);
+    let count = min(u_lightParams.count, MAX_LIGHTS);
+    var n = 0u;
+    for (var i: u32 = 0u; i < count && n < clusters.max_lights; i = i + 1u) {
+        let Ld = u_lights.lights[i];
+        var hit = Ld.enabled != 0u;
+        // directional lights reach every cluster; spot lights are tested as spheres
+        if (hit && Ld.light_type != 1u) {
+            let p = (clusters.view * vec4<f32>(Ld.position, 1.0)).xyz;
+            let d = clamp(p, box_min, box_max) - p;
+            hit = dot(d, d) <= Ld.range * Ld.range;
+        }
+        if (hit) {
+            cluster_lights[base + 1u + n] = i;
+            n = n + 1u;
+        }
+    }
+    cluster_lights[base] = n;
+}
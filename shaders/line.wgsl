@@ -0,0 +1,80 @@
+// Thick debug lines. Each instance is one segment, expanded in the vertex shader into a
+// camera-facing quad of 6 vertices; width is either in pixels or in world units.
+
+struct Lines {
+    view_proj  : mat4x4<f32>,
+    camera_pos : vec3<f32>,
+    width      : f32,
+    viewport   : vec2<f32>,
+    // 0 = pixels, 1 = world units
+    mode       : u32,
+    _pad       : u32,
+};
+
+@group(0) @binding(0)
+var<uniform> lines : Lines;
+
+struct VSOut {
+    @builtin(position) pos_clip : vec4<f32>,
+    @location(0) color : vec4<f32>,
+    // -1 on one long edge of the quad, 1 on the other
+    @location(1) edge : f32,
+};
+
+// (t along the segment, side) per vertex of the two triangles
+const CORNERS = array<vec2<f32>, 6>(
+    vec2<f32>(0.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(0.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(0.0, 1.0),
+);
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vid: u32,
+    @location(0) a: vec3<f32>,
+    @location(1) b: vec3<f32>,
+    @location(2) color: vec4<f32>,
+) -> VSOut {
+    let corner = CORNERS[vid % 6u];
+    let t = corner.x;
+    let side = corner.y;
+
+    var pos_clip: vec4<f32>;
+    if (lines.mode == 1u) {
+        let p = mix(a, b, t);
+        let across = cross(b - a, p - lines.camera_pos);
+        let len = length(across);
+        let offset = select(vec3<f32>(0.0), across / len, len > 1e-6);
+        pos_clip = lines.view_proj * vec4<f32>(p + offset * side * 0.5 * lines.width, 1.0);
+    } else {
+        let clip_a = lines.view_proj * vec4<f32>(a, 1.0);
+        let clip_b = lines.view_proj * vec4<f32>(b, 1.0);
+        let screen_a = clip_a.xy / clip_a.w * lines.viewport;
+        let screen_b = clip_b.xy / clip_b.w * lines.viewport;
+        let d = screen_b - screen_a;
+        let len = length(d);
+        let dir = select(vec2<f32>(1.0, 0.0), d / len, len > 1e-6);
+        let normal = vec2<f32>(-dir.y, dir.x);
+
+        pos_clip = mix(clip_a, clip_b, t);
+        // ndc spans 2 units across the viewport, so half the width in pixels is width / viewport
+        let offset = normal * side * lines.width / lines.viewport;
+        pos_clip = vec4<f32>(pos_clip.xy + offset * pos_clip.w, pos_clip.zw);
+    }
+
+    var out: VSOut;
+    out.pos_clip = pos_clip;
+    out.color = color;
+    out.edge = side;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VSOut) -> @location(0) vec4<f32> {
+    // fades the outermost pixel on each side for a smooth edge
+    let coverage = clamp((1.0 - abs(in.edge)) / fwidth(in.edge), 0.0, 1.0);
+    return vec4<f32>(in.color.rgb, in.color.a * coverage);
+}
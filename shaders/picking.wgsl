@@ -0,0 +1,38 @@
+// Writes object index + 1 per pixel into an R32Uint target; 0 means nothing was drawn.
+
+struct Camera {
+    view_proj : mat4x4<f32>,
+    camera_pos: vec3<f32>,
+    _pad0     : f32,
+    inv_view_proj : mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> camera : Camera;
+
+struct Object {
+    model      : mat4x4<f32>,
+    light_mask : u32,
+};
+
+@group(0) @binding(5)
+var<storage, read> objects : array<Object>;
+
+struct VSOut {
+    @builtin(position) pos_clip : vec4<f32>,
+    @location(0) @interpolate(flat) id : u32,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, @builtin(instance_index) object_index: u32) -> VSOut {
+    var out: VSOut;
+    let pos_ws = objects[object_index].model * vec4<f32>(position, 1.0);
+    out.pos_clip = camera.view_proj * pos_ws;
+    out.id = object_index + 1u;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VSOut) -> @location(0) u32 {
+    return in.id;
+}
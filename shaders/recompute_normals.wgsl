@@ -0,0 +1,101 @@
+// Recomputes smooth per-vertex normals in place.
+// cs_clear zeroes the accumulators, cs_accumulate adds each triangle's unit face normal to its
+// three vertices, cs_resolve normalizes the sums into the vertex normal field.
+
+struct Params {
+    vertex_count: u32,
+    index_count: u32,
+    // 0 = non-indexed, 1 = u16, 2 = u32
+    index_mode: u32,
+    _pad: u32,
+};
+
+// Vertex is 12 floats: position(3) uv(2) normal(3) tangent(4)
+@group(0) @binding(0) var<storage, read_write> vertices: array<f32>;
+@group(0) @binding(1) var<storage, read> indices: array<u32>;
+// fixed point xyz sums, 3 per vertex (there are no float atomics)
+@group(0) @binding(2) var<storage, read_write> accum: array<atomic<i32>>;
+@group(0) @binding(3) var<uniform> params: Params;
+
+const VERTEX_STRIDE: u32 = 12u;
+const NORMAL_OFFSET: u32 = 5u;
+const FIXED_SCALE: f32 = 65536.0;
+
+fn load_index(i: u32) -> u32 {
+    if (params.index_mode == 1u) {
+        let word = indices[i / 2u];
+        return select(word & 0xffffu, word >> 16u, (i & 1u) == 1u);
+    }
+    if (params.index_mode == 2u) {
+        return indices[i];
+    }
+    return i;
+}
+
+fn load_position(v: u32) -> vec3<f32> {
+    let base = v * VERTEX_STRIDE;
+    return vec3<f32>(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+}
+
+@compute @workgroup_size(64)
+fn cs_clear(@builtin(global_invocation_id) id: vec3<u32>) {
+    let v = id.x;
+    if (v >= params.vertex_count) {
+        return;
+    }
+    atomicStore(&accum[v * 3u], 0);
+    atomicStore(&accum[v * 3u + 1u], 0);
+    atomicStore(&accum[v * 3u + 2u], 0);
+}
+
+@compute @workgroup_size(64)
+fn cs_accumulate(@builtin(global_invocation_id) id: vec3<u32>) {
+    let first = id.x * 3u;
+    if (first + 2u >= params.index_count) {
+        return;
+    }
+
+    let a = load_index(first);
+    let b = load_index(first + 1u);
+    let c = load_index(first + 2u);
+    if (a >= params.vertex_count || b >= params.vertex_count || c >= params.vertex_count) {
+        return;
+    }
+
+    let pa = load_position(a);
+    let n = cross(load_position(b) - pa, load_position(c) - pa);
+    let len = length(n);
+    if (len <= 1e-12) {
+        return;
+    }
+    let q = vec3<i32>(round(n / len * FIXED_SCALE));
+
+    for (var k = 0u; k < 3u; k = k + 1u) {
+        let v = select(select(c, b, k == 1u), a, k == 0u);
+        atomicAdd(&accum[v * 3u], q.x);
+        atomicAdd(&accum[v * 3u + 1u], q.y);
+        atomicAdd(&accum[v * 3u + 2u], q.z);
+    }
+}
+
+@compute @workgroup_size(64)
+fn cs_resolve(@builtin(global_invocation_id) id: vec3<u32>) {
+    let v = id.x;
+    if (v >= params.vertex_count) {
+        return;
+    }
+
+    let sum = vec3<f32>(
+        f32(atomicLoad(&accum[v * 3u])),
+        f32(atomicLoad(&accum[v * 3u + 1u])),
+        f32(atomicLoad(&accum[v * 3u + 2u])),
+    );
+    let len = length(sum);
+    // vertices not referenced by any triangle keep an up normal
+    let n = select(vec3<f32>(0.0, 1.0, 0.0), sum / len, len > 0.0);
+
+    let base = v * VERTEX_STRIDE + NORMAL_OFFSET;
+    vertices[base] = n.x;
+    vertices[base + 1u] = n.y;
+    vertices[base + 2u] = n.z;
+}
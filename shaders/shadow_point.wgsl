@@ -0,0 +1,41 @@
+// shaders/shadow_point.wgsl
+struct ShadowFace {
+    view_proj : mat4x4<f32>,
+    light_pos : vec3<f32>,
+    range     : f32,
+};
+
+@group(0) @binding(0)
+var<uniform> face : ShadowFace;
+
+struct Object {
+    model      : mat4x4<f32>,
+    light_mask : u32,
+};
+
+@group(1) @binding(0)
+var<storage, read> objects : array<Object>;
+
+struct VSOut {
+    @builtin(position) pos_clip : vec4<f32>,
+    @location(0) pos_ws         : vec3<f32>,
+};
+
+@vertex
+fn vs_main(
+    @location(0) position : vec3<f32>,
+    @builtin(instance_index) object_index : u32,
+) -> VSOut {
+    var out: VSOut;
+    let pos_ws   = (objects[object_index].model * vec4<f32>(position, 1.0)).xyz;
+    out.pos_clip = face.view_proj * vec4<f32>(pos_ws, 1.0);
+    out.pos_ws   = pos_ws;
+    return out;
+}
+
+// Store linear distance to the light instead of projected depth so every
+// face of the cube compares against the same metric.
+@fragment
+fn fs_main(in: VSOut) -> @builtin(frag_depth) f32 {
+    return length(in.pos_ws - face.light_pos) / max(face.range, 1e-3);
+}
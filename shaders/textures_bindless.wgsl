@@ -0,0 +1,46 @@
+// shaders/textures_bindless.wgsl
+// Every texture and sampler lives in one binding array, indexed per material.
+struct MaterialTextures {
+    base_color                 : u32,
+    base_color_sampler         : u32,
+    metallic_roughness         : u32,
+    metallic_roughness_sampler : u32,
+    normal                     : u32,
+    normal_sampler             : u32,
+    emissive                   : u32,
+    emissive_sampler           : u32,
+    detail                     : u32,
+    detail_sampler             : u32,
+};
+
+@group(1) @binding(0)
+var textures: binding_array<texture_2d<f32>>;
+@group(1) @binding(1)
+var samplers: binding_array<sampler>;
+@group(1) @binding(2)
+var<storage, read> material_textures : array<MaterialTextures>;
+
+fn sample_base_color(uv: vec2<f32>) -> vec4<f32> {
+    let mt = material_textures[material_params.id];
+    return textureSample(textures[mt.base_color], samplers[mt.base_color_sampler], uv);
+}
+
+fn sample_metallic_roughness(uv: vec2<f32>) -> vec4<f32> {
+    let mt = material_textures[material_params.id];
+    return textureSample(textures[mt.metallic_roughness], samplers[mt.metallic_roughness_sampler], uv);
+}
+
+fn sample_normal(uv: vec2<f32>) -> vec4<f32> {
+    let mt = material_textures[material_params.id];
+    return textureSample(textures[mt.normal], samplers[mt.normal_sampler], uv);
+}
+
+fn sample_emissive(uv: vec2<f32>) -> vec4<f32> {
+    let mt = material_textures[material_params.id];
+    return textureSample(textures[mt.emissive], samplers[mt.emissive_sampler], uv);
+}
+
+fn sample_detail(uv: vec2<f32>) -> vec4<f32> {
+    let mt = material_textures[material_params.id];
+    return textureSample(textures[mt.detail], samplers[mt.detail_sampler], uv);
+}
@@ -0,0 +1,43 @@
+// shaders/textures_bound.wgsl
+// Fallback when binding arrays aren't supported: the material's textures are
+// rebound to group 1 before every draw.
+@group(1) @binding(0)
+var t_base_color: texture_2d<f32>;
+@group(1) @binding(1)
+var s_base_color: sampler;
+@group(1) @binding(2)
+var t_metallic_roughness: texture_2d<f32>;
+@group(1) @binding(3)
+var s_metallic_roughness: sampler;
+@group(1) @binding(4)
+var t_normal: texture_2d<f32>;
+@group(1) @binding(5)
+var s_normal: sampler;
+@group(1) @binding(6)
+var t_emissive: texture_2d<f32>;
+@group(1) @binding(7)
+var s_emissive: sampler;
+@group(1) @binding(8)
+var t_detail: texture_2d<f32>;
+@group(1) @binding(9)
+var s_detail: sampler;
+
+fn sample_base_color(uv: vec2<f32>) -> vec4<f32> {
+    return textureSample(t_base_color, s_base_color, uv);
+}
+
+fn sample_metallic_roughness(uv: vec2<f32>) -> vec4<f32> {
+    return textureSample(t_metallic_roughness, s_metallic_roughness, uv);
+}
+
+fn sample_normal(uv: vec2<f32>) -> vec4<f32> {
+    return textureSample(t_normal, s_normal, uv);
+}
+
+fn sample_emissive(uv: vec2<f32>) -> vec4<f32> {
+    return textureSample(t_emissive, s_emissive, uv);
+}
+
+fn sample_detail(uv: vec2<f32>) -> vec4<f32> {
+    return textureSample(t_detail, s_detail, uv);
+}
@@ -1,25 +1,183 @@
 use std::sync::Arc;
 
+use anyhow::Result;
 use hecs::World;
 use winit::{
-    application::ApplicationHandler, event::WindowEvent, event_loop::ActiveEventLoop,
+    application::ApplicationHandler,
+    dpi::{LogicalSize, PhysicalSize},
+    event::{ElementState, WindowEvent},
+    event_loop::{ActiveEventLoop, ControlFlow, EventLoop, EventLoopProxy},
+    keyboard::{KeyCode, PhysicalKey},
     window::Window,
 };
 
-use crate::{game::Game, render::ForwardRenderer};
+use crate::{
+    core::time::Time,
+    game::{Game, WindowHandle},
+    render::{ForwardRenderer, capture::Screenshot, gpu::GpuConfig, stats},
+};
+
+#[derive(Clone, Debug)]
+pub struct WindowConfig {
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+    // id of the <canvas> element to render into on the web
+    pub canvas_id: String,
+    // saves the next frame to screenshot-<unix millis>.png in the working directory
+    pub screenshot_key: Option<KeyCode>,
+    pub gpu: GpuConfig,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "My Game".to_string(),
+            width: 1280,
+            height: 720,
+            canvas_id: "my-canvas".to_string(),
+            screenshot_key: Some(KeyCode::F12),
+            gpu: GpuConfig::default(),
+        }
+    }
+}
+
+// Results handed back to the event loop by work that finishes outside of it.
+pub enum UserEvent {
+    // the renderer `resumed` creates asynchronously on the web
+    RendererReady(ForwardRenderer),
+}
 
-pub struct App<G: Game> {
+pub struct App {
     pub window: Option<Arc<Window>>,
     pub world: World,
     pub renderer: Option<ForwardRenderer>,
-    pub game: G,
+    pub game: Box<dyn Game>,
+    pub config: WindowConfig,
+    // latest Resized size, applied once before the next frame
+    pub pending_resize: Option<PhysicalSize<u32>>,
+    // Game::setup has run; it runs once, the first time a renderer is available
+    pub did_setup: bool,
+    pub time: Time,
+    // set by run; the web build needs it to hand the renderer back to the event loop
+    pub proxy: Option<EventLoopProxy<UserEvent>>,
 }
-impl<G: Game> ApplicationHandler for App<G> {
+
+impl App {
+    pub fn new<G: Game + 'static>(game: G, config: WindowConfig) -> Self {
+        Self {
+            window: None,
+            world: World::new(),
+            renderer: None,
+            game: Box::new(game),
+            config,
+            pending_resize: None,
+            did_setup: false,
+            time: Time::default(),
+            proxy: None,
+        }
+    }
+
+    // On the web the renderer is created asynchronously and only arrives as a
+    // UserEvent::RendererReady some time after `resumed`, so this is tried again then.
+    pub fn ensure_setup(&mut self) {
+        if self.did_setup {
+            return;
+        }
+        if let Some(renderer) = self.renderer.as_mut() {
+            self.game.setup(&mut self.world, renderer);
+            self.did_setup = true;
+        }
+    }
+
+    // Handles the app's own key bindings; repeats are filtered out by the caller.
+    pub fn key_pressed(&mut self, key: PhysicalKey) {
+        if self.config.screenshot_key.map(PhysicalKey::Code) == Some(key)
+            && let Some(renderer) = self.renderer.as_mut()
+        {
+            renderer.capture_frame();
+        }
+    }
+
+    // Updates the renderer's scale factor and reconfigures the surface to `size`, the
+    // window's physical size at the new scale, before the next frame.
+    pub fn scale_factor_changed(&mut self, scale_factor: f64, size: PhysicalSize<u32>) {
+        if let Some(renderer) = self.renderer.as_mut() {
+            renderer.set_scale_factor(scale_factor);
+        }
+        self.pending_resize = Some(size);
+    }
+
+    // One RedrawRequested: applies the pending resize, ticks the clock to `now` (seconds,
+    // see stats::now_seconds), runs Game::update and saves a requested screenshot. Does
+    // nothing until there is a renderer.
+    pub fn redraw(&mut self, window: &dyn WindowHandle, now: f64) {
+        self.ensure_setup();
+        let Some(renderer) = self.renderer.as_mut() else {
+            return;
+        };
+        if let Some(size) = self.pending_resize.take() {
+            renderer.resize(size.width, size.height);
+        }
+        self.time.tick(now);
+        self.game
+            .update(&mut self.world, renderer, window, &self.time);
+        if let Some(shot) = renderer.take_capture() {
+            save_screenshot(&shot);
+        }
+    }
+}
+
+// Builds the world, app and event loop and runs `game` until the window closes.
+pub fn run<G: Game + 'static>(game: G, config: WindowConfig) -> Result<()> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let _ = env_logger::try_init();
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        console_error_panic_hook::set_once();
+    }
+
+    let event_loop = EventLoop::<UserEvent>::with_user_event().build()?;
+    event_loop.set_control_flow(ControlFlow::Poll);
+
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let mut app = App::new(game, config);
+        app.proxy = Some(event_loop.create_proxy());
+        event_loop.run_app(&mut app)?;
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        use winit::platform::web::EventLoopExtWebSys;
+        let mut app = App::new(game, config);
+        app.proxy = Some(event_loop.create_proxy());
+        event_loop.spawn_app(app);
+    }
+
+    Ok(())
+}
+
+fn save_screenshot(shot: &Screenshot) {
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis());
+    let path = format!("screenshot-{}.png", millis);
+    match shot.save_png(&path) {
+        Ok(()) => eprintln!("saved {}", path),
+        Err(e) => eprintln!("screenshot: failed to save {}: {}", path, e),
+    }
+}
+
+impl ApplicationHandler<UserEvent> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         if self.window.is_none() {
             #[cfg(not(target_arch = "wasm32"))]
-            let window_attributes =
-                winit::window::WindowAttributes::default().with_title("My Game");
+            let window_attributes = winit::window::WindowAttributes::default()
+                .with_title(self.config.title.as_str())
+                .with_inner_size(LogicalSize::new(self.config.width, self.config.height));
 
             #[cfg(target_arch = "wasm32")]
             let window_attributes = {
@@ -31,13 +189,14 @@ impl<G: Game> ApplicationHandler for App<G> {
                     .unwrap()
                     .document()
                     .unwrap()
-                    .get_element_by_id("my-canvas")
+                    .get_element_by_id(&self.config.canvas_id)
                     .unwrap()
                     .dyn_into::<HtmlCanvasElement>()
                     .unwrap();
 
                 winit::window::WindowAttributes::default()
-                    .with_title("My Game")
+                    .with_title(self.config.title.as_str())
+                    .with_inner_size(LogicalSize::new(self.config.width, self.config.height))
                     .with_canvas(Some(canvas))
             };
 
@@ -45,30 +204,34 @@ impl<G: Game> ApplicationHandler for App<G> {
 
             #[cfg(not(target_arch = "wasm32"))]
             {
-                let renderer = pollster::block_on(ForwardRenderer::new(&window))
-                    .expect("Failed to create renderer");
+                let renderer =
+                    pollster::block_on(ForwardRenderer::with_config(&window, self.config.gpu))
+                        .expect("Failed to create renderer");
                 self.renderer = Some(renderer);
-                self.game
-                    .update(&mut self.world, self.renderer.as_mut().unwrap());
             }
 
             #[cfg(target_arch = "wasm32")]
             {
                 use wasm_bindgen_futures::spawn_local;
                 let window_clone = window.clone();
-                let renderer_slot = &mut self.renderer;
+                let gpu_config = self.config.gpu;
+                let proxy = self.proxy.clone().expect("App::proxy is set by run");
 
+                // the future can't borrow self, so the renderer comes back through user_event
                 spawn_local(async move {
-                    let renderer = ForwardRenderer::new(&window_clone)
+                    let renderer = ForwardRenderer::with_config(&window_clone, gpu_config)
                         .await
                         .expect("Failed to create renderer");
-                    *renderer_slot = Some(renderer);
+                    if proxy
+                        .send_event(UserEvent::RendererReady(renderer))
+                        .is_err()
+                    {
+                        eprintln!("renderer: event loop closed before the renderer was ready");
+                    }
                 });
             }
             self.window = Some(window);
-            if let Some(renderer) = self.renderer.as_mut() {
-                self.game.setup(&mut self.world, renderer);
-            }
+            self.ensure_setup();
         }
     }
 
@@ -81,51 +244,43 @@ impl<G: Game> ApplicationHandler for App<G> {
         match event {
             WindowEvent::CloseRequested => event_loop.exit(),
             WindowEvent::RedrawRequested => {
-                let Some(window) = self.window.as_ref() else {
-                    return;
-                };
-
-                if _window_id != window.id() {
+                if self.window.as_ref().map(|w| w.id()) != Some(_window_id) {
                     return;
                 }
-                if let Some(renderer) = self.renderer.as_mut() {
-                    self.game.update(&mut self.world, renderer);
-                    //let asset = &mut renderer.asset;
-                    //let mesh_id = asset.get_mesh("meshes/cube.glb#0");
-                    //
-                    //let spotlight = Light {
-                    //    kind: LightKind::Spot,
-                    //    position: [0.0, 3.0, 0.0],
-                    //    direction: [0.0, -1.0, 0.0],
-                    //    color: [0.8, 0.8, 1.0],
-                    //    range: 20.0,
-                    //    inner_angle: 0.4,
-                    //    outer_angle: 0.7,
-                    //    ..Default::default()
-                    //};
-                    //
-                    //let cam = Camera {
-                    //    eye: glam::Vec3::new(0.0, 1.5, 5.0),
-                    //    target: glam::Vec3::new(0.0, 0.0, 0.0),
-                    //    up: glam::Vec3::new(0.0, 1.0, 0.0),
-                    //    fov_y_radians: 60.0_f32.to_radians(),
-                    //    z_near: 0.1,
-                    //    z_far: 1000.0,
-                    //    aspect: 16.0 / 9.0,
-                    //};
-                    //
-                    //renderer.render(&[spotlight], &cam, &[RenderCommand { mesh_id }]);
+                let Some(window) = self.window.clone() else {
+                    return;
+                };
+                self.redraw(window.as_ref(), stats::now_seconds());
+            }
+            WindowEvent::KeyboardInput { event, .. } => {
+                if event.state == ElementState::Pressed && !event.repeat {
+                    self.key_pressed(event.physical_key);
                 }
             }
+            // a drag-resize fires many of these per frame; only the last one matters
             WindowEvent::Resized(size) => {
-                if let Some(renderer) = self.renderer.as_mut() {
-                    renderer.resize(size.width, size.height);
+                self.pending_resize = Some(size);
+            }
+            // the physical size changes with the scale; winit usually follows up with a
+            // Resized, but not when the platform keeps the old size
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                if let Some(window) = self.window.clone() {
+                    self.scale_factor_changed(scale_factor, window.inner_size());
                 }
             }
             _ => {}
         }
     }
 
+    fn user_event(&mut self, _event_loop: &ActiveEventLoop, event: UserEvent) {
+        match event {
+            UserEvent::RendererReady(renderer) => {
+                self.renderer = Some(renderer);
+                self.ensure_setup();
+            }
+        }
+    }
+
     fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
         if let Some(window) = &self.window {
             window.request_redraw();
@@ -134,3 +289,132 @@ impl<G: Game> ApplicationHandler for App<G> {
 
     fn new_events(&mut self, _: &ActiveEventLoop, _: winit::event::StartCause) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::render::test_support;
+    use winit::{error::ExternalError, window::CursorGrabMode};
+
+    // Counts the calls App makes into the game.
+    #[derive(Clone, Default)]
+    struct CountingGame {
+        setups: Rc<Cell<usize>>,
+        updates: Rc<Cell<usize>>,
+    }
+
+    impl Game for CountingGame {
+        fn setup(&mut self, _world: &mut World, _renderer: &mut ForwardRenderer) {
+            self.setups.set(self.setups.get() + 1);
+        }
+        fn update(
+            &mut self,
+            _world: &mut World,
+            _renderer: &mut ForwardRenderer,
+            _window: &dyn WindowHandle,
+            _time: &Time,
+        ) {
+            self.updates.set(self.updates.get() + 1);
+        }
+    }
+
+    struct FakeWindow;
+
+    impl WindowHandle for FakeWindow {
+        fn inner_size(&self) -> PhysicalSize<u32> {
+            PhysicalSize::new(64, 64)
+        }
+        fn scale_factor(&self) -> f64 {
+            1.0
+        }
+        fn set_cursor_visible(&self, _visible: bool) {}
+        fn set_cursor_grab(&self, _mode: CursorGrabMode) -> Result<(), ExternalError> {
+            Ok(())
+        }
+    }
+
+    // An app whose renderer is already up, as after `resumed`.
+    fn app(game: CountingGame) -> Option<App> {
+        let mut app = App::new(game, WindowConfig::default());
+        app.renderer = Some(test_support::renderer(64, 64)?);
+        Some(app)
+    }
+
+    #[test]
+    fn update_runs_once_per_redraw() {
+        let game = CountingGame::default();
+        let Some(mut app) = app(game.clone()) else {
+            return;
+        };
+        for frame in 1..=5 {
+            app.redraw(&FakeWindow, frame as f64 / 60.0);
+            assert_eq!(game.updates.get(), frame);
+        }
+        assert_eq!(app.time.frame, 5);
+
+        // no renderer yet, nothing to update
+        let game = CountingGame::default();
+        let mut app = App::new(game.clone(), WindowConfig::default());
+        app.redraw(&FakeWindow, 0.0);
+        assert_eq!(game.updates.get(), 0);
+    }
+
+    #[test]
+    fn setup_runs_once_when_the_renderer_arrives() {
+        let Some(renderer) = test_support::renderer(64, 64) else {
+            return;
+        };
+        let game = CountingGame::default();
+        let mut app = App::new(game.clone(), WindowConfig::default());
+
+        // the web build redraws before the renderer is ready
+        app.redraw(&FakeWindow, 0.0);
+        app.ensure_setup();
+        assert_eq!(game.setups.get(), 0);
+
+        // what user_event does with UserEvent::RendererReady
+        app.renderer = Some(renderer);
+        app.ensure_setup();
+        for frame in 1..=10 {
+            app.redraw(&FakeWindow, frame as f64 / 60.0);
+        }
+        assert_eq!(game.setups.get(), 1);
+        assert_eq!(game.updates.get(), 10);
+    }
+
+    #[test]
+    fn scale_factor_change_reconfigures_the_surface() {
+        let Some(mut app) = app(CountingGame::default()) else {
+            return;
+        };
+        app.scale_factor_changed(2.0, PhysicalSize::new(128, 96));
+        app.redraw(&FakeWindow, 0.0);
+
+        let renderer = app.renderer.as_ref().unwrap();
+        assert_eq!(renderer.scale_factor(), 2.0);
+        let config = &renderer.context.config;
+        assert_eq!((config.width, config.height), (128, 96));
+        assert!(app.pending_resize.is_none());
+    }
+
+    #[test]
+    fn screenshot_key_requests_a_capture() {
+        let Some(mut app) = app(CountingGame::default()) else {
+            return;
+        };
+        let requested = |app: &App| app.renderer.as_ref().unwrap().capture_requested;
+
+        app.key_pressed(PhysicalKey::Code(KeyCode::KeyA));
+        assert!(!requested(&app));
+        app.key_pressed(PhysicalKey::Code(KeyCode::F12));
+        assert!(requested(&app));
+
+        app.config.screenshot_key = None;
+        app.renderer.as_mut().unwrap().capture_requested = false;
+        app.key_pressed(PhysicalKey::Code(KeyCode::F12));
+        assert!(!requested(&app));
+    }
+}
@@ -1,13 +1,15 @@
 use std::{collections::HashMap, sync::Arc};
 
+pub mod export;
 pub mod importer;
 pub mod light;
 pub mod material;
 pub mod mesh;
+pub mod packing;
 pub mod texture;
 
 use importer::GltfImporter;
-use material::{MAX_MAT, MaterialUniform};
+use material::{FIRST_FREE_MAT, MAX_MAT, MaterialFeatures, MaterialUniform};
 use slotmap::{SlotMap, new_key_type};
 
 use crate::asset_manager::{
@@ -21,6 +23,20 @@ new_key_type! {
     pub struct SamplerId;
 }
 
+// For logs: the kind of asset, then slot index and version, e.g. "mesh:3v1".
+macro_rules! display_key {
+    ($id:ty, $kind:literal) => {
+        impl std::fmt::Display for $id {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, concat!($kind, ":{:?}"), slotmap::Key::data(self))
+            }
+        }
+    };
+}
+display_key!(MeshId, "mesh");
+display_key!(TextureId, "texture");
+display_key!(SamplerId, "sampler");
+
 pub struct AssetManager {
     pub importer: GltfImporter,
 
@@ -30,13 +46,23 @@ pub struct AssetManager {
     pub meshes_by_name: HashMap<String, MeshId>,
 
     pub meshes: SlotMap<MeshId, mesh::Mesh>,
+    // magenta cube handed out by get_mesh_or_error when a load fails
+    pub error_mesh: MeshId,
 
     pub mat_buffer: wgpu::Buffer,
     pub mat_free: Vec<usize>,
     pub mat_by_name: HashMap<String, MaterialId>,
     pub tex_by_mat: Vec<TextureGroup>,
+    pub features_by_mat: Vec<MaterialFeatures>,
+    pub default_material: MaterialUniform,
+    // CPU copy of mat_buffer; slots listed in mat_dirty are uploaded by flush_materials
+    pub mat_uniforms: Vec<MaterialUniform>,
+    pub mat_dirty: Vec<usize>,
 
     pub tex_by_key: HashMap<TextureKey, TextureId>,
+    // base color textures of materials loaded while set are premultiplied, see
+    // set_premultiply_alpha
+    pub premultiply_alpha: bool,
     pub textures: SlotMap<TextureId, GpuTexture>,
 
     pub sampler_default: SamplerId,
@@ -46,6 +72,9 @@ pub struct AssetManager {
     pub color_tex_default: TextureId,
     pub data_tex_default: TextureId,
     pub depth_tex_default: TextureId,
+
+    // bumped whenever textures, samplers or material texture groups change
+    pub tex_revision: u64,
 }
 
 impl AssetManager {
@@ -56,7 +85,10 @@ impl AssetManager {
         let mat_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Material Buffer"),
             size: mat_buffer_size,
-            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            // COPY_SRC for read_buffer
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
 
@@ -95,18 +127,21 @@ impl AssetManager {
             tex_view: color_tex.create_view(&wgpu::TextureViewDescriptor::default()),
             tex: color_tex,
             sampler: sampler_default,
+            source: None,
         });
 
         let data_tex_default = textures.insert(GpuTexture {
             tex_view: data_tex.create_view(&wgpu::TextureViewDescriptor::default()),
             tex: data_tex,
             sampler: sampler_default,
+            source: None,
         });
 
         let depth_tex_default = textures.insert(GpuTexture {
             tex_view: depth_tex.create_view(&wgpu::TextureViewDescriptor::default()),
             tex: depth_tex,
             sampler: sampler_default,
+            source: None,
         });
 
         let tex_by_mat = vec![
@@ -115,49 +150,474 @@ impl AssetManager {
                 emissive: color_tex_default,
                 metallic_roughness: data_tex_default,
                 normal: data_tex_default,
+                detail: color_tex_default,
             };
             MAX_MAT
         ];
 
-        Self {
+        let mut assets = Self {
             importer: GltfImporter::new(),
             device,
             queue,
             meshes_by_name: HashMap::new(),
             meshes: SlotMap::with_key(),
+            error_mesh: MeshId::default(),
             mat_buffer,
-            mat_free: (1..MAX_MAT).rev().collect(),
+            mat_free: (FIRST_FREE_MAT..MAX_MAT).rev().collect(),
             mat_by_name: HashMap::new(),
             tex_by_key: HashMap::new(),
+            premultiply_alpha: false,
             textures,
             tex_by_mat,
+            features_by_mat: vec![MaterialFeatures::default(); MAX_MAT],
+            default_material: default_uniform,
+            mat_uniforms: vec![default_uniform; MAX_MAT],
+            mat_dirty: Vec::new(),
             sampler_by_name: HashMap::new(),
             samplers,
             sampler_default,
             color_tex_default,
             data_tex_default,
             depth_tex_default,
+            tex_revision: 0,
+        };
+        assets.create_error_mesh();
+        assets
+    }
+
+    // Drops every loaded mesh, material, texture and sampler, e.g. when switching levels.
+    // The defaults created by new (slot 0, with any set_default_material override, and the
+    // error mesh) stay valid. Mesh, texture and sampler ids handed out before the call stop
+    // resolving, but MaterialIds are plain slot indices that the next materials reuse: drop
+    // every MaterialId, and despawn the entities holding one, before calling this.
+    pub fn clear(&mut self) {
+        let error_mesh = self.error_mesh;
+        self.meshes.retain(|id, _| id == error_mesh);
+        self.meshes_by_name.retain(|_, &mut id| id == error_mesh);
+
+        self.mat_by_name.clear();
+        self.mat_free = (FIRST_FREE_MAT..MAX_MAT).rev().collect();
+        let defaults = TextureGroup {
+            base_color: self.color_tex_default,
+            emissive: self.color_tex_default,
+            metallic_roughness: self.data_tex_default,
+            normal: self.data_tex_default,
+            detail: self.color_tex_default,
+        };
+        self.tex_by_mat[FIRST_FREE_MAT..].fill(defaults);
+        self.features_by_mat[FIRST_FREE_MAT..].fill(MaterialFeatures::default());
+        for idx in FIRST_FREE_MAT..MAX_MAT {
+            self.write_material(idx, MaterialUniform::default());
         }
+
+        let keep = [
+            self.color_tex_default,
+            self.data_tex_default,
+            self.depth_tex_default,
+        ];
+        self.textures.retain(|id, _| keep.contains(&id));
+        self.tex_by_key.clear();
+
+        let sampler_default = self.sampler_default;
+        self.samplers.retain(|id, _| id == sampler_default);
+        self.sampler_by_name.clear();
+
+        self.tex_revision += 1;
+    }
+    // What the loaded meshes, textures and the material buffer take on the GPU, summed
+    // from their sizes. Drivers pad and align allocations, so the real usage is higher.
+    pub fn estimated_gpu_memory_bytes(&self) -> u64 {
+        let meshes: u64 = self
+            .meshes
+            .values()
+            .map(|m| m.vertex_buf.size() + m.index_buf.as_ref().map_or(0, |b| b.size()))
+            .sum();
+        let textures: u64 = self
+            .textures
+            .values()
+            .map(|t| texture::texture_bytes(&t.tex))
+            .sum();
+        meshes + textures + self.mat_buffer.size()
     }
-    fn split_key<'a>(key: &'a str) -> (&'a str, Option<&'a str>) {
+
+    fn split_key<'a>(key: &'a str) -> Result<(&'a str, Option<&'a str>), AssetError> {
         let mut it = key.splitn(2, '#');
         let path = it.next().unwrap_or(key);
         let selector = it.next();
-        (path, selector)
+
+        // an empty path would otherwise reach gltf::import and panic there
+        if path.trim().is_empty() || selector.is_some_and(|s| s.is_empty()) {
+            return Err(AssetError::InvalidKey(key.to_string()));
+        }
+        Ok((path, selector))
     }
-    fn split_path<'a>(key: &'a str) -> Result<(&'a str, usize), ()> {
+    // Like split_key, but the selector is required. It may be an index or a name.
+    fn split_path<'a>(key: &'a str) -> Result<(&'a str, &'a str), SplitPathError> {
         let mut it = key.splitn(2, '#');
         let path = it.next().unwrap();
 
-        let selector_str = it.next().ok_or(())?;
-
-        let selector = selector_str.parse::<usize>().map_err(|_| ())?;
+        let selector = it.next().ok_or(SplitPathError::MissingSeparator)?;
+        if selector.is_empty() {
+            return Err(SplitPathError::InvalidSelector);
+        }
 
         Ok((path, selector))
     }
 }
+#[derive(Debug)]
+pub enum AssetError {
+    InvalidKey(String),
+    InvalidPath(String, SplitPathError),
+    MeshNotFound(MeshId),
+    TextureNotFound(TextureId),
+    // generated rather than loaded by get_texture, so there is no file to reload
+    TextureNotReloadable(TextureId),
+    // the file couldn't be read or parsed, or doesn't contain what the key selects
+    LoadFailed {
+        path: String,
+        reason: String,
+    },
+    PrimitiveOutOfRange {
+        mesh: String,
+        index: usize,
+        count: usize,
+    },
+    // the format can't be uploaded from CPU data, or the device lacks its features
+    UnsupportedTextureFormat(wgpu::TextureFormat),
+    TextureDataMismatch {
+        format: wgpu::TextureFormat,
+        expected: usize,
+        actual: usize,
+    },
+    // past the MAX_MAT material slots
+    MaterialOutOfRange(MaterialId),
+    // rewrites reuse the mesh's buffers, so the (vertex, index) counts must stay the same
+    MeshDataMismatch {
+        mesh: String,
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+}
+
+impl std::fmt::Display for AssetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssetError::InvalidKey(key) => {
+                write!(
+                    f,
+                    "invalid asset key '{}', expected path or path#selector",
+                    key
+                )
+            }
+            AssetError::InvalidPath(key, err) => {
+                write!(f, "invalid asset key '{}': {}", key, err)
+            }
+            AssetError::MeshNotFound(id) => write!(f, "{} not found", id),
+            AssetError::TextureNotFound(id) => write!(f, "{} not found", id),
+            AssetError::TextureNotReloadable(id) => {
+                write!(f, "{} has no source file to reload", id)
+            }
+            AssetError::LoadFailed { path, reason } => {
+                write!(f, "failed to load '{}': {}", path, reason)
+            }
+            AssetError::PrimitiveOutOfRange { mesh, index, count } => {
+                write!(
+                    f,
+                    "primitive index {} out of range for mesh '{}' with {} primitives",
+                    index, mesh, count
+                )
+            }
+            AssetError::UnsupportedTextureFormat(format) => {
+                write!(f, "texture format {:?} is not supported", format)
+            }
+            AssetError::TextureDataMismatch {
+                format,
+                expected,
+                actual,
+            } => {
+                write!(
+                    f,
+                    "texture data is {} bytes, but {:?} at that size needs {}",
+                    actual, format, expected
+                )
+            }
+            AssetError::MaterialOutOfRange(id) => {
+                write!(
+                    f,
+                    "{} out of range, there are {} material slots",
+                    id, MAX_MAT
+                )
+            }
+            AssetError::MeshDataMismatch {
+                mesh,
+                expected,
+                actual,
+            } => {
+                write!(
+                    f,
+                    "rewrite of mesh '{}' has {} vertices and {} indices, but it was created with {} and {}",
+                    mesh, actual.0, actual.1, expected.0, expected.1
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssetError {}
+
 #[derive(Debug)]
 pub enum SplitPathError {
     MissingSeparator,
     InvalidSelector,
 }
+
+impl std::fmt::Display for SplitPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SplitPathError::MissingSeparator => {
+                write!(f, "missing '#', expected path#index or path#name")
+            }
+            SplitPathError::InvalidSelector => {
+                write!(f, "empty selector, expected path#index or path#name")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SplitPathError {}
+
+#[cfg(test)]
+pub mod test_support {
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+
+    use super::AssetManager;
+
+    // An AssetManager on whatever adapter is available; None skips the test on machines
+    // without one.
+    pub fn assets() -> Option<AssetManager> {
+        let instance = wgpu::Instance::default();
+        let adapter =
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+                .ok()?;
+        let (device, queue) =
+            pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default())).ok()?;
+        Some(AssetManager::new(Arc::new(device), Arc::new(queue)))
+    }
+
+    // Scratch directory for files a test loads from disk, removed again when dropped.
+    pub struct TempDir(PathBuf);
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    // Empty, even if a crashed run left the directory behind.
+    pub fn temp_dir(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("engine-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    // A glTF with one external image, `image.png`, behind a texture named "albedo".
+    pub fn write_texture_gltf(dir: &std::path::Path, rgba: [u8; 4]) -> String {
+        write_png(dir, rgba);
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "images": [{ "uri": "image.png" }],
+            "textures": [{ "source": 0, "name": "albedo" }]
+        }"#;
+        let path = dir.join("texture.gltf");
+        std::fs::write(&path, json).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    // Overwrites the 2x2 `image.png` with a single color.
+    pub fn write_png(dir: &std::path::Path, rgba: [u8; 4]) {
+        let pixels: Vec<u8> = rgba.repeat(4);
+        image::save_buffer(
+            dir.join("image.png"),
+            &pixels,
+            2,
+            2,
+            image::ColorType::Rgba8,
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mesh::Primitive;
+    use super::texture::{TextureSlot, texture_bytes};
+    use super::{AssetError, AssetManager, SplitPathError, test_support};
+
+    #[test]
+    fn temp_dir_is_removed_on_drop() {
+        let dir = test_support::temp_dir("guard");
+        std::fs::write(dir.join("file"), b"x").unwrap();
+        let path = dir.to_path_buf();
+        assert!(path.join("file").exists());
+        drop(dir);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn split_path_reports_what_is_wrong_with_a_key() {
+        assert!(matches!(
+            AssetManager::split_path("foo"),
+            Err(SplitPathError::MissingSeparator)
+        ));
+        assert!(matches!(
+            AssetManager::split_path("foo#"),
+            Err(SplitPathError::InvalidSelector)
+        ));
+        // names are selectors too; an unknown one fails once the file is read
+        assert!(matches!(
+            AssetManager::split_path("foo#x"),
+            Ok(("foo", "x"))
+        ));
+        assert!(matches!(
+            AssetManager::split_path("foo#0"),
+            Ok(("foo", "0"))
+        ));
+
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        assert!(matches!(
+            assets.get_texture("foo", TextureSlot::BaseColor),
+            Err(AssetError::InvalidPath(_, SplitPathError::MissingSeparator))
+        ));
+        assert!(matches!(
+            assets.get_sampler("foo#"),
+            Err(AssetError::InvalidPath(_, SplitPathError::InvalidSelector))
+        ));
+    }
+
+    #[test]
+    fn memory_estimate_sums_known_asset_sizes() {
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        let base = assets.estimated_gpu_memory_bytes();
+
+        // 24 full vertices of 48 bytes and 36 u16 indices
+        assets.set_mesh(&[Primitive::cube(1.0)], "cube").unwrap();
+        assert_eq!(assets.estimated_gpu_memory_bytes(), base + 24 * 48 + 36 * 2);
+
+        // one 2x2 RGBA8 level
+        let dir = test_support::temp_dir("memory");
+        let path = test_support::write_texture_gltf(&dir, [0, 255, 0, 255]);
+        assets
+            .get_texture(&format!("{}#albedo", path), TextureSlot::BaseColor)
+            .unwrap();
+        assert_eq!(
+            assets.estimated_gpu_memory_bytes(),
+            base + 24 * 48 + 36 * 2 + 16
+        );
+
+        assets.clear();
+        assert_eq!(assets.estimated_gpu_memory_bytes(), base);
+    }
+
+    #[test]
+    fn texture_bytes_counts_every_mip_and_layer() {
+        let Some(assets) = test_support::assets() else {
+            return;
+        };
+        let texture = |format, mips, layers| {
+            assets.device.create_texture(&wgpu::TextureDescriptor {
+                label: None,
+                size: wgpu::Extent3d {
+                    width: 4,
+                    height: 4,
+                    depth_or_array_layers: layers,
+                },
+                mip_level_count: mips,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+        };
+        assert_eq!(
+            texture_bytes(&texture(wgpu::TextureFormat::Rgba8Unorm, 1, 1)),
+            64
+        );
+        // 4x4, 2x2 and 1x1 levels of two layers
+        assert_eq!(
+            texture_bytes(&texture(wgpu::TextureFormat::Rgba8Unorm, 3, 2)),
+            (64 + 16 + 4) * 2
+        );
+        assert_eq!(
+            texture_bytes(&texture(wgpu::TextureFormat::Rg16Float, 1, 1)),
+            64
+        );
+    }
+
+    #[test]
+    fn clear_drops_loaded_assets_and_keeps_the_defaults() {
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        let dir = test_support::temp_dir("clear");
+        test_support::write_png(&dir, [255, 0, 0, 255]);
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "images": [{ "uri": "image.png" }],
+            "samplers": [{ "name": "tiling" }],
+            "textures": [{ "source": 0, "sampler": 0, "name": "albedo" }],
+            "materials": [{
+                "name": "painted",
+                "pbrMetallicRoughness": { "baseColorTexture": { "index": 0 } }
+            }]
+        }"#;
+        let path = dir.join("scene.gltf");
+        std::fs::write(&path, json).unwrap();
+        let path = path.to_str().unwrap();
+
+        let mesh = assets.set_mesh(&[Primitive::cube(1.0)], "cube").unwrap();
+        let material = assets.get_material(&format!("{}#painted", path)).unwrap();
+        let sampler = assets.get_sampler(&format!("{}#tiling", path)).unwrap();
+        assert!(!assets.tex_by_key.is_empty());
+
+        assets.clear();
+
+        assert!(assets.mesh(mesh).is_none());
+        assert!(!assets.contains_mesh("cube"));
+        assert!(assets.mat_by_name.is_empty());
+        assert!(assets.tex_by_key.is_empty());
+        assert!(assets.sampler_by_name.is_empty());
+        assert!(!assets.samplers.contains_key(sampler));
+
+        assert!(assets.mesh(assets.error_mesh).is_some());
+        assert_eq!(assets.meshes.len(), 1);
+        assert!(assets.samplers.contains_key(assets.sampler_default));
+        assert_eq!(assets.samplers.len(), 1);
+        for tex in [
+            assets.color_tex_default,
+            assets.data_tex_default,
+            assets.depth_tex_default,
+        ] {
+            assert!(assets.textures.contains_key(tex));
+        }
+        assert_eq!(assets.textures.len(), 3);
+
+        // material slots aren't versioned: the next material takes the cleared one's slot
+        let again = assets.get_material(&format!("{}#painted", path)).unwrap();
+        assert_eq!(again, material);
+    }
+}
@@ -0,0 +1,58 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use super::{AssetManager, MeshId, mesh::strip_to_list};
+
+impl AssetManager {
+    // Writes a mesh as a Wavefront .obj with positions, uvs and normals. Materials are not
+    // exported. Non-indexed meshes are written as consecutive vertex triples.
+    pub fn export_mesh(&self, mesh_id: MeshId, path: impl AsRef<Path>) -> io::Result<()> {
+        let (vertices, indices) = self.read_mesh_data(mesh_id).ok_or_else(|| {
+            io::Error::other(format!("export_mesh: could not read mesh {:?}", mesh_id))
+        })?;
+
+        let mut out = BufWriter::new(File::create(path)?);
+        if let Some(name) = self.meshes.get(mesh_id).and_then(|m| m.name.as_deref()) {
+            writeln!(out, "o {}", name)?;
+        }
+
+        for v in &vertices {
+            let [x, y, z] = v.position;
+            writeln!(out, "v {} {} {}", x, y, z)?;
+        }
+        // obj texture coordinates start at the bottom left, glTF ones at the top left
+        for v in &vertices {
+            let [s, t] = v.uv;
+            writeln!(out, "vt {} {}", s, 1.0 - t)?;
+        }
+        for v in &vertices {
+            let [x, y, z] = v.normal;
+            writeln!(out, "vn {} {} {}", x, y, z)?;
+        }
+
+        let triangles: Vec<u32> = match self.meshes.get(mesh_id) {
+            _ if indices.is_empty() => (0..vertices.len() as u32).collect(),
+            Some(mesh) => mesh
+                .primitives
+                .iter()
+                .flat_map(|p| {
+                    let range =
+                        &indices[p.first_index as usize..(p.first_index + p.index_count) as usize];
+                    match p.topology {
+                        wgpu::PrimitiveTopology::TriangleStrip => strip_to_list(range),
+                        _ => range.to_vec(),
+                    }
+                })
+                .collect(),
+            None => indices,
+        };
+        for tri in triangles.chunks_exact(3) {
+            // obj indices are 1-based
+            let [a, b, c] = [tri[0] + 1, tri[1] + 1, tri[2] + 1];
+            writeln!(out, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+
+        out.flush()
+    }
+}
@@ -1,15 +1,171 @@
 use super::{
+    AssetError,
     material::Material,
-    mesh::{Index, Primitive, Vertex},
+    mesh::{Index, Primitive, Vertex, strip_to_list},
 };
 use crate::asset_manager::texture::{AddressMode, FilterMode, Sampler, Texture};
+use glam::Vec3;
 use gltf::{self, Gltf, import, mesh::Mode};
 
-pub struct GltfImporter;
+pub struct GltfImporter {
+    // flip triangles whose winding disagrees with their vertex normals
+    pub fix_winding: bool,
+    // rotate meshes authored Z-up into this engine's Y-up, see z_up_to_y_up
+    pub convert_z_up_to_y_up: bool,
+}
+
+// Reorders each triangle to counter-clockwise relative to its averaged vertex normal.
+// Degenerate triangles and ones with no usable normals are left alone. Returns how many
+// triangles were flipped.
+pub fn fix_winding(vertices: &[Vertex], indices: &mut [Index]) -> usize {
+    let mut flipped = 0;
+    for tri in indices.iter_mut() {
+        let [a, b, c] = tri.idx.map(|i| &vertices[i as usize]);
+        let pa = Vec3::from(a.position);
+        let face = (Vec3::from(b.position) - pa).cross(Vec3::from(c.position) - pa);
+        let normal = Vec3::from(a.normal) + Vec3::from(b.normal) + Vec3::from(c.normal);
+
+        if face.dot(normal) < 0.0 {
+            tri.idx.swap(1, 2);
+            flipped += 1;
+        }
+    }
+    flipped
+}
+
+// Groups a triangle list's indices into triangles. Also returns the trailing indices that
+// don't make up a whole triangle, which a well-formed list doesn't have.
+pub fn list_triangles(indices: &[u32]) -> (Vec<Index>, &[u32]) {
+    let chunks = indices.chunks_exact(3);
+    let rest = chunks.remainder();
+    let tris = chunks
+        .map(|tri| Index {
+            idx: [tri[0], tri[1], tri[2]],
+        })
+        .collect();
+    (tris, rest)
+}
+
+// Unrolls a triangle strip into triangles. A `restart` index ends the current strip and
+// starts a new one after it, as primitive restart does on the GPU.
+pub fn strip_triangles(strip: &[u32], restart: u32) -> Vec<Index> {
+    let list: Vec<u32> = strip
+        .split(|&i| i == restart)
+        .flat_map(strip_to_list)
+        .collect();
+    list_triangles(&list).0
+}
+
+// Every primitive is expanded to the full Vertex layout so primitives with different
+// attribute sets can share one vertex buffer. An attribute that is absent, or whose count
+// doesn't match POSITION, is replaced by `default` for every vertex:
+// normal (0, 1, 0), uv (0, 0), tangent generated from the uvs.
+fn attribute_or<T: Copy>(
+    read: Option<Vec<T>>,
+    count: usize,
+    default: T,
+    name: &str,
+    path: &str,
+) -> (Vec<T>, bool) {
+    match read {
+        Some(values) if values.len() == count => (values, true),
+        Some(values) => {
+            eprintln!(
+                "importer: {} has {} values but POSITION has {} in '{}'; using defaults",
+                name,
+                values.len(),
+                count,
+                path
+            );
+            (vec![default; count], false)
+        }
+        None => (vec![default; count], false),
+    }
+}
+
+// Rotates -90 degrees about X, taking +Z up to +Y up and +Y forward to -Z. A rotation, so
+// winding and tangent handedness are unchanged.
+pub fn z_up_to_y_up(v: [f32; 3]) -> [f32; 3] {
+    [v[0], v[2], -v[1]]
+}
+
+// glTF stores the bitangent sign in tangent.w as exactly 1 or -1, but some exporters write 0
+// or garbage there. Snaps every w to the sign it leans towards, +1 for 0 and NaN, and returns
+// how many were not already +-1.
+pub fn fix_tangent_handedness(tangents: &mut [[f32; 4]]) -> usize {
+    let mut fixed = 0;
+    for t in tangents {
+        if (t[3].abs() - 1.0).abs() > 1e-3 || t[3].is_nan() {
+            fixed += 1;
+        }
+        t[3] = if t[3] < 0.0 { -1.0 } else { 1.0 };
+    }
+    fixed
+}
+
+// Any unit vector perpendicular to `n` (Duff et al., "Building an Orthonormal Basis,
+// Revisited"). Used where uvs can't define a tangent direction.
+pub fn orthonormal_tangent(n: Vec3) -> Vec3 {
+    let sign = 1.0f32.copysign(n.z);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    Vec3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x)
+}
+
+// Fills in tangents from the uv layout: each triangle's uv gradient is accumulated on its
+// vertices, then made orthogonal to the normal. Vertices whose triangles all have
+// degenerate uvs (or no uvs at all) get an arbitrary tangent perpendicular to the normal.
+pub fn generate_tangents(vertices: &mut [Vertex], indices: &[Index]) {
+    let mut tan = vec![Vec3::ZERO; vertices.len()];
+    let mut bitan = vec![Vec3::ZERO; vertices.len()];
+
+    for tri in indices {
+        let [a, b, c] = tri.idx.map(|i| &vertices[i as usize]);
+        let e1 = Vec3::from(b.position) - Vec3::from(a.position);
+        let e2 = Vec3::from(c.position) - Vec3::from(a.position);
+        let (du1, dv1) = (b.uv[0] - a.uv[0], b.uv[1] - a.uv[1]);
+        let (du2, dv2) = (c.uv[0] - a.uv[0], c.uv[1] - a.uv[1]);
+
+        let det = du1 * dv2 - du2 * dv1;
+        if det.abs() <= f32::EPSILON {
+            continue;
+        }
+        let r = 1.0 / det;
+        let t = (e1 * dv2 - e2 * dv1) * r;
+        let bt = (e2 * du1 - e1 * du2) * r;
+        if !t.is_finite() || !bt.is_finite() {
+            continue;
+        }
+        for i in tri.idx {
+            tan[i as usize] += t;
+            bitan[i as usize] += bt;
+        }
+    }
+
+    for (i, v) in vertices.iter_mut().enumerate() {
+        let n = Vec3::from(v.normal).normalize_or(Vec3::Y);
+        let t = tan[i] - n * n.dot(tan[i]);
+        let (t, w) = match t.try_normalize() {
+            Some(t) => {
+                let w = if n.cross(t).dot(bitan[i]) < 0.0 {
+                    -1.0
+                } else {
+                    1.0
+                };
+                (t, w)
+            }
+            None => (orthonormal_tangent(n), 1.0),
+        };
+        v.tangent = [t.x, t.y, t.z, w];
+    }
+}
 
 impl GltfImporter {
     pub fn new() -> Self {
-        Self
+        Self {
+            fix_winding: false,
+            convert_z_up_to_y_up: false,
+        }
     }
 
     //fn split_key<'a>(key: &'a str) -> (&'a str, Option<&'a str>) {
@@ -19,19 +175,29 @@ impl GltfImporter {
     //    (path, selector)
     //}
 
-    fn select_mesh<'a>(doc: &'a gltf::Document, sel: Option<&str>, path: &str) -> gltf::Mesh<'a> {
+    fn select_mesh<'a>(
+        doc: &'a gltf::Document,
+        sel: Option<&str>,
+        path: &str,
+    ) -> Result<gltf::Mesh<'a>, AssetError> {
+        let failed = |reason: String| AssetError::LoadFailed {
+            path: path.to_string(),
+            reason,
+        };
         if let Some(s) = sel {
             if let Ok(idx) = s.parse::<usize>() {
                 doc.meshes()
                     .nth(idx)
-                    .unwrap_or_else(|| panic!("Mesh index {idx} not found in '{path}'"))
+                    .ok_or_else(|| failed(format!("mesh index {idx} not found")))
             } else {
                 doc.meshes()
                     .find(|m| m.name().map(|n| n == s).unwrap_or(false))
-                    .unwrap_or_else(|| panic!("Mesh named '{s}' not found in '{path}'"))
+                    .ok_or_else(|| failed(format!("mesh named '{s}' not found")))
             }
         } else {
-            doc.meshes().next().expect("No meshes in glTF file")
+            doc.meshes()
+                .next()
+                .ok_or_else(|| failed("no meshes in glTF file".to_string()))
         }
     }
 
@@ -55,47 +221,128 @@ impl GltfImporter {
         }
     }
 
-    pub fn load_mesh(&mut self, path: &str, selector: Option<&str>) -> Vec<Primitive> {
-        let (doc, buffers, _images) = gltf::import(path).expect("Failed to load glTF file");
-        let mesh = Self::select_mesh(&doc, selector, path);
+    fn select_texture<'a>(
+        doc: &'a gltf::Document,
+        sel: &str,
+        path: &str,
+    ) -> Result<gltf::Texture<'a>, AssetError> {
+        let failed = |reason: String| AssetError::LoadFailed {
+            path: path.to_string(),
+            reason,
+        };
+        if let Ok(idx) = sel.parse::<usize>() {
+            doc.textures()
+                .nth(idx)
+                .ok_or_else(|| failed(format!("texture index {idx} not found")))
+        } else {
+            // textures are often unnamed, so also accept the name of their image
+            doc.textures()
+                .find(|t| t.name() == Some(sel) || t.source().name() == Some(sel))
+                .ok_or_else(|| failed(format!("texture named '{sel}' not found")))
+        }
+    }
+
+    fn select_sampler<'a>(
+        doc: &'a gltf::Document,
+        sel: &str,
+        path: &str,
+    ) -> Result<gltf::texture::Sampler<'a>, AssetError> {
+        let failed = |reason: String| AssetError::LoadFailed {
+            path: path.to_string(),
+            reason,
+        };
+        if let Ok(idx) = sel.parse::<usize>() {
+            doc.samplers()
+                .nth(idx)
+                .ok_or_else(|| failed(format!("sampler index {idx} not found")))
+        } else {
+            doc.samplers()
+                .find(|s| s.name() == Some(sel))
+                .ok_or_else(|| failed(format!("sampler named '{sel}' not found")))
+        }
+    }
+
+    pub fn load_mesh(
+        &mut self,
+        path: &str,
+        selector: Option<&str>,
+    ) -> Result<Vec<Primitive>, AssetError> {
+        let failed = |reason: String| AssetError::LoadFailed {
+            path: path.to_string(),
+            reason,
+        };
+        let (doc, buffers, _images) = gltf::import(path).map_err(|e| failed(e.to_string()))?;
+        let mesh = Self::select_mesh(&doc, selector, path)?;
 
         let mut out: Vec<Primitive> = Vec::new();
 
         for prim in mesh.primitives() {
-            if prim.mode() != Mode::Triangles {
-                panic!("Unsupported primitive mode: {:?}", prim.mode());
+            let mode = prim.mode();
+            if mode != Mode::Triangles && mode != Mode::TriangleStrip {
+                return Err(failed(format!("unsupported primitive mode {:?}", mode)));
             }
 
             let reader = prim.reader(|buffer| Some(&buffers[buffer.index()].0[..]));
 
             let positions: Vec<[f32; 3]> = reader
                 .read_positions()
-                .expect("Primitive missing POSITION attribute")
+                .ok_or_else(|| failed("primitive has no POSITION attribute".to_string()))?
                 .collect();
 
-            let normals: Vec<[f32; 3]> = reader
-                .read_normals()
-                .map(|it| it.collect())
-                .unwrap_or_else(|| vec![[0.0, 1.0, 0.0]; positions.len()]);
-
-            let uvs: Vec<[f32; 2]> = reader
-                .read_tex_coords(0)
-                .map(|tc| tc.into_f32().collect())
-                .unwrap_or_else(|| vec![[0.0, 0.0]; positions.len()]);
-
-            let tangents: Vec<[f32; 4]> = reader
-                .read_tangents()
-                .map(|it| it.collect())
-                .unwrap_or_else(|| vec![[1.0, 0.0, 0.0, 1.0]; positions.len()]);
-
-            let indices: Vec<u32> = match reader.read_indices() {
-                Some(gltf::mesh::util::ReadIndices::U8(i)) => i.map(|v| v as u32).collect(),
-                Some(gltf::mesh::util::ReadIndices::U16(i)) => i.map(|v| v as u32).collect(),
-                Some(gltf::mesh::util::ReadIndices::U32(i)) => i.collect(),
-                None => (0u32..positions.len() as u32).collect(),
+            let count = positions.len();
+            let (normals, has_normals) = attribute_or(
+                reader.read_normals().map(|it| it.collect()),
+                count,
+                [0.0, 1.0, 0.0],
+                "NORMAL",
+                path,
+            );
+            let (uvs, _) = attribute_or(
+                reader.read_tex_coords(0).map(|tc| tc.into_f32().collect()),
+                count,
+                [0.0, 0.0],
+                "TEXCOORD_0",
+                path,
+            );
+            let (mut tangents, has_tangents) = attribute_or(
+                reader.read_tangents().map(|it| it.collect()),
+                count,
+                [1.0, 0.0, 0.0, 1.0],
+                "TANGENT",
+                path,
+            );
+            let fixed = fix_tangent_handedness(&mut tangents);
+            if fixed > 0 {
+                eprintln!(
+                    "importer: {} of {} tangents in '{}' had a handedness other than +-1",
+                    fixed, count, path
+                );
+            }
+
+            // the restart value is all bits set in the accessor's own index type
+            let (indices, restart): (Vec<u32>, u32) = match reader.read_indices() {
+                Some(gltf::mesh::util::ReadIndices::U8(i)) => {
+                    (i.map(|v| v as u32).collect(), u8::MAX as u32)
+                }
+                Some(gltf::mesh::util::ReadIndices::U16(i)) => {
+                    (i.map(|v| v as u32).collect(), u16::MAX as u32)
+                }
+                Some(gltf::mesh::util::ReadIndices::U32(i)) => (i.collect(), u32::MAX),
+                None => ((0u32..positions.len() as u32).collect(), u32::MAX),
             };
+            // checked before anything below indexes the vertices with them
+            let is_restart = |i: u32| mode == Mode::TriangleStrip && i == restart;
+            if let Some(&bad) = indices
+                .iter()
+                .find(|&&i| !is_restart(i) && i as usize >= count)
+            {
+                return Err(failed(format!(
+                    "index {} is past the primitive's {} vertices",
+                    bad, count
+                )));
+            }
 
-            let vertices = (0..positions.len())
+            let mut vertices = (0..positions.len())
                 .map(|i| Vertex {
                     position: positions[i],
                     uv: uvs[i],
@@ -104,23 +351,57 @@ impl GltfImporter {
                 })
                 .collect::<Vec<_>>();
 
-            let tri_indices = indices
-                .chunks(3)
-                .filter(|tri| tri.len() == 3)
-                .map(|tri| Index {
-                    idx: [tri[0], tri[1], tri[2]],
-                })
-                .collect::<Vec<_>>();
+            if self.convert_z_up_to_y_up {
+                for v in &mut vertices {
+                    v.position = z_up_to_y_up(v.position);
+                    v.normal = z_up_to_y_up(v.normal);
+                    let [x, y, z] = z_up_to_y_up([v.tangent[0], v.tangent[1], v.tangent[2]]);
+                    v.tangent = [x, y, z, v.tangent[3]];
+                }
+            }
+
+            let mut tri_indices = if mode == Mode::TriangleStrip {
+                strip_triangles(&indices, restart)
+            } else {
+                let (tris, rest) = list_triangles(&indices);
+                if !rest.is_empty() {
+                    eprintln!(
+                        "importer: triangle list in '{}' has {} indices; dropping the trailing {}",
+                        path,
+                        indices.len(),
+                        rest.len()
+                    );
+                }
+                tris
+            };
+
+            // the placeholder normals carry no winding information
+            if self.fix_winding && has_normals {
+                let flipped = fix_winding(&vertices, &mut tri_indices);
+                if flipped > 0 {
+                    eprintln!(
+                        "importer: flipped {} of {} triangles in '{}' to match their normals",
+                        flipped,
+                        tri_indices.len(),
+                        path
+                    );
+                }
+            }
+
+            if !has_tangents {
+                generate_tangents(&mut vertices, &tri_indices);
+            }
 
             let material = prim.material().index();
             out.push(Primitive {
                 vertex: vertices,
                 index: tri_indices,
                 material,
+                ..Default::default()
             });
         }
 
-        out
+        Ok(out)
     }
 
     pub fn load_material(&mut self, path: &str, selector: Option<&str>) -> Material {
@@ -141,15 +422,21 @@ impl GltfImporter {
                 .metallic_roughness_texture()
                 .map(|info| info.texture().index()),
             normal_texture: material.normal_texture().map(|info| info.texture().index()),
+            normal_scale: material.normal_texture().map_or(1.0, |info| info.scale()),
             emissive_texture: material
                 .emissive_texture()
                 .map(|info| info.texture().index()),
+            unlit: material.unlit(),
         }
     }
 
-    pub fn load_texture(&mut self, path: &str, selector: usize) -> Texture {
-        let (doc, buffers, _images) = import(path).expect("Failed to import glTF");
-        let tex = doc.textures().nth(selector).expect("Invalid texture index");
+    pub fn load_texture(&mut self, path: &str, selector: &str) -> Result<Texture, AssetError> {
+        let failed = |reason: String| AssetError::LoadFailed {
+            path: path.to_string(),
+            reason,
+        };
+        let (doc, buffers, _images) = import(path).map_err(|e| failed(e.to_string()))?;
+        let tex = Self::select_texture(&doc, selector, path)?;
         let img = tex.source().source();
         let sampler_index = tex.sampler().index();
 
@@ -159,8 +446,8 @@ impl GltfImporter {
                 let start = view.offset();
                 let end = start + view.length();
                 let data = &buffer[start..end];
-                let dyn_img =
-                    image::load_from_memory(data).expect("Failed to decode embedded image");
+                let dyn_img = image::load_from_memory(data)
+                    .map_err(|e| failed(format!("embedded image: {}", e)))?;
                 let rgba = dyn_img.to_rgba8();
                 let (w, h) = rgba.dimensions();
                 // Get the raw Vec<u8> directly
@@ -172,7 +459,8 @@ impl GltfImporter {
                     .parent()
                     .unwrap_or(std::path::Path::new("."));
                 let img_path = parent.join(uri);
-                let dyn_img = image::open(img_path).expect("Failed to open external image");
+                let dyn_img = image::open(&img_path)
+                    .map_err(|e| failed(format!("{}: {}", img_path.display(), e)))?;
                 let rgba = dyn_img.to_rgba8();
                 let (w, h) = rgba.dimensions();
                 // Get the raw Vec<u8> directly
@@ -181,19 +469,19 @@ impl GltfImporter {
             }
         };
 
-        Texture {
+        Ok(Texture {
             pixels, // This is now a Vec<u8>
             width,
             height,
             sampler: sampler_index,
-        }
+        })
     }
-    pub fn load_sampler(&mut self, path: &str, selector: usize) -> Sampler {
-        let gltf = Gltf::open(path).expect("Failed to open glTF file");
-        let s = gltf
-            .samplers()
-            .nth(selector)
-            .expect("Sampler index out of range");
+    pub fn load_sampler(&mut self, path: &str, selector: &str) -> Result<Sampler, AssetError> {
+        let gltf = Gltf::open(path).map_err(|e| AssetError::LoadFailed {
+            path: path.to_string(),
+            reason: e.to_string(),
+        })?;
+        let s = Self::select_sampler(&gltf, selector, path)?;
 
         let wrap = |mode: gltf::texture::WrappingMode| match mode {
             gltf::texture::WrappingMode::ClampToEdge => AddressMode::ClampToEdge,
@@ -224,15 +512,73 @@ impl GltfImporter {
             None => (FilterMode::Linear, FilterMode::Nearest),
         };
 
-        let sampler = Sampler {
+        Ok(Sampler {
             address_mode_u: wrap(s.wrap_s()),
             address_mode_v: wrap(s.wrap_t()),
+            // glTF has no W; only matters if the sampler is reused for a 3D texture
             address_mode_w: AddressMode::ClampToEdge,
             mag_filter: mag,
             min_filter: min,
             mipmap_filter: mipmap,
-        };
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_manager::test_support;
+
+    // two textures over one image, so a name resolving to index 0 would be a false pass
+    fn write_named_textures(name: &str) -> (test_support::TempDir, String) {
+        let dir = test_support::temp_dir(name);
+        test_support::write_png(&dir, [255, 255, 255, 255]);
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "images": [{ "uri": "image.png" }],
+            "samplers": [{ "name": "tiling", "wrapS": 10497, "wrapT": 33071 }],
+            "textures": [
+                { "source": 0, "name": "normal" },
+                { "source": 0, "sampler": 0, "name": "albedo" }
+            ]
+        }"#;
+        let path = dir.join("named.gltf");
+        std::fs::write(&path, json).unwrap();
+        let path = path.to_str().unwrap().to_string();
+        (dir, path)
+    }
+
+    #[test]
+    fn texture_selector_resolves_names_and_indices() {
+        let (_dir, path) = write_named_textures("select-texture");
+        let (doc, _, _) = import(&path).unwrap();
+        let by_name = GltfImporter::select_texture(&doc, "albedo", &path).unwrap();
+        assert_eq!(by_name.index(), 1);
+        let by_index = GltfImporter::select_texture(&doc, "0", &path).unwrap();
+        assert_eq!(by_index.name(), Some("normal"));
+
+        let texture = GltfImporter::new().load_texture(&path, "albedo").unwrap();
+        assert_eq!(texture.sampler, Some(0));
+    }
 
-        sampler
+    #[test]
+    fn unknown_selectors_are_errors() {
+        let (_dir, path) = write_named_textures("select-unknown");
+        let mut importer = GltfImporter::new();
+        assert!(matches!(
+            importer.load_texture(&path, "albdeo"),
+            Err(AssetError::LoadFailed { .. })
+        ));
+        assert!(matches!(
+            importer.load_texture(&path, "7"),
+            Err(AssetError::LoadFailed { .. })
+        ));
+        assert!(matches!(
+            importer.load_sampler(&path, "missing"),
+            Err(AssetError::LoadFailed { .. })
+        ));
+        let sampler = importer.load_sampler(&path, "tiling").unwrap();
+        assert!(matches!(sampler.address_mode_u, AddressMode::Repeat));
+        assert!(matches!(sampler.address_mode_v, AddressMode::ClampToEdge));
     }
 }
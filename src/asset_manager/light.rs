@@ -1,4 +1,11 @@
+use glam::{Mat4, Vec3};
+
+use crate::asset_manager::TextureId;
+
 pub const MAX_LIGHTS: usize = 16;
+// near plane of the spot light projection used for cookies
+pub const SPOT_NEAR: f32 = 0.05;
+pub const DEFAULT_SHADOW_BIAS: f32 = 0.005;
 
 #[derive(Clone, Copy, Debug)]
 pub enum LightKind {
@@ -22,6 +29,17 @@ pub struct Light {
     pub inner_angle: f32,
 
     pub outer_angle: f32,
+
+    // spot lights only: texture projected along the cone, multiplied into the light color
+    pub cookie: Option<TextureId>,
+
+    // subtracted from the fragment's stored shadow depth (distance / range) before comparing
+    pub shadow_bias: f32,
+    // 0 ignores the shadow, 1 makes shadowed areas receive none of this light
+    pub shadow_strength: f32,
+
+    // disabled lights stay in the light buffer but light nothing and cast no shadows
+    pub enabled: bool,
 }
 
 impl Default for Light {
@@ -34,18 +52,40 @@ impl Default for Light {
             range: 10.0,
             inner_angle: 0.5, // ~30 deg
             outer_angle: 0.7, // ~40 deg
+            cookie: None,
+            shadow_bias: DEFAULT_SHADOW_BIAS,
+            shadow_strength: 1.0,
+            enabled: true,
         }
     }
 }
 
+impl Light {
+    // Projection from world space into the spot cone, used to look up the cookie. The
+    // frustum's field of view covers the outer angle.
+    pub fn spot_view_proj(&self) -> Mat4 {
+        let pos = Vec3::from_array(self.position);
+        let dir = Vec3::from_array(self.direction).normalize_or(Vec3::NEG_Y);
+        let up = if dir.y.abs() > 0.99 { Vec3::Z } else { Vec3::Y };
+
+        let fov = (2.0 * self.outer_angle).clamp(0.01, std::f32::consts::PI - 0.01);
+        let proj = if self.range.is_finite() {
+            Mat4::perspective_rh(fov, 1.0, SPOT_NEAR, self.range.max(SPOT_NEAR * 2.0))
+        } else {
+            Mat4::perspective_infinite_rh(fov, 1.0, SPOT_NEAR)
+        };
+        proj * Mat4::look_to_rh(pos, dir, up)
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct LightUniform {
     pub position: [f32; 3],
-    pub _pad0: f32,
+    pub shadow_bias: f32,
 
     pub color: [f32; 3],
-    pub _pad1: f32,
+    pub shadow_strength: f32,
 
     pub direction: [f32; 3],
     pub light_type: u32,
@@ -53,7 +93,12 @@ pub struct LightUniform {
     pub range: f32,
     pub inner_cos: f32,
     pub outer_cos: f32,
-    pub _pad2: f32,
+    pub shadow_index: i32,
+
+    pub view_proj: [[f32; 4]; 4],
+    pub cookie_index: i32,
+    pub enabled: u32,
+    pub _pad2: [i32; 2],
 }
 
 impl From<&Light> for LightUniform {
@@ -66,10 +111,10 @@ impl From<&Light> for LightUniform {
 
         Self {
             position: l.position,
-            _pad0: 0.0,
+            shadow_bias: l.shadow_bias,
 
             color: l.color,
-            _pad1: 0.0,
+            shadow_strength: l.shadow_strength.clamp(0.0, 1.0),
 
             direction: l.direction,
             light_type: kind,
@@ -77,7 +122,15 @@ impl From<&Light> for LightUniform {
             range: l.range,
             inner_cos: l.inner_angle.cos(),
             outer_cos: l.outer_angle.cos(),
-            _pad2: 0.0,
+            shadow_index: -1,
+
+            view_proj: match l.kind {
+                LightKind::Spot => l.spot_view_proj().to_cols_array_2d(),
+                _ => Mat4::IDENTITY.to_cols_array_2d(),
+            },
+            cookie_index: -1,
+            enabled: l.enabled as u32,
+            _pad2: [0; 2],
         }
     }
 }
@@ -88,3 +141,53 @@ pub struct LightParams {
     pub count: u32,
     pub _pad: [u32; 3],
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::test_support::*;
+
+    #[test]
+    fn disabled_lights_contribute_nothing() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let cube = cube(&mut renderer, "cube");
+        let target = renderer.create_offscreen_target(32, 32);
+        let cam = camera(1.0);
+        let commands = [draw(cube, Vec3::ZERO)];
+        let mut frame = |lights: &[Light]| {
+            renderer.render_to(&target, lights, &cam, &commands);
+            renderer.read_frame(&target).unwrap()
+        };
+
+        let lights = [
+            light(),
+            Light {
+                kind: LightKind::Directional,
+                direction: [0.0, 0.0, -1.0],
+                ..Default::default()
+            },
+            Light {
+                kind: LightKind::Spot,
+                position: [0.0, 0.0, 3.0],
+                direction: [0.0, 0.0, -1.0],
+                ..Default::default()
+            },
+        ];
+        let unlit = frame(&[]);
+        for light in lights {
+            let lit = frame(&[light]);
+            let off = frame(&[Light {
+                enabled: false,
+                ..light
+            }]);
+            assert!(lit != unlit, "{:?} lights nothing", light.kind);
+            assert!(
+                off == unlit,
+                "disabled {:?} still lights the cube",
+                light.kind
+            );
+        }
+    }
+}
@@ -1,11 +1,23 @@
-use crate::asset_manager::texture::TextureGroup;
+use std::ops::Range;
 
-use super::AssetManager;
+use crate::asset_manager::texture::{TextureGroup, TextureSlot};
+
+use super::{AssetError, AssetManager, TextureId};
 
 pub const MAX_MAT: usize = 1024;
+// unlit magenta, used by the error mesh; slot 0 is the default material
+pub const ERROR_MATERIAL: usize = 1;
+// first slot get_material can hand out
+pub const FIRST_FREE_MAT: usize = ERROR_MATERIAL + 1;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MaterialId(pub usize);
+
+impl std::fmt::Display for MaterialId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "material:{}", self.0)
+    }
+}
 impl From<usize> for MaterialId {
     fn from(v: usize) -> Self {
         MaterialId(v)
@@ -29,7 +41,63 @@ pub struct Material {
     pub base_color_texture: Option<usize>,
     pub metallic_roughness_texture: Option<usize>,
     pub normal_texture: Option<usize>,
+    // scales the x and y of the normal map's tangent-space normals
+    pub normal_scale: f32,
     pub emissive_texture: Option<usize>,
+    pub unlit: bool,
+}
+
+// Lighting function forward.wgsl shades a material with, stored in MaterialUniform::model.
+// Unlike MaterialFeatures this is a runtime branch, so it doesn't add pipeline variants.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MaterialModel {
+    // glTF metallic-roughness
+    #[default]
+    Pbr,
+    // two flat diffuse bands and a hard highlight; metallic is ignored
+    Toon,
+}
+
+impl MaterialModel {
+    pub fn id(self) -> u32 {
+        match self {
+            MaterialModel::Pbr => 0,
+            MaterialModel::Toon => 1,
+        }
+    }
+
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0 => Some(MaterialModel::Pbr),
+            1 => Some(MaterialModel::Toon),
+            _ => None,
+        }
+    }
+}
+
+// Selects the forward shader variant a material is drawn with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MaterialFeatures {
+    pub normal_map: bool,
+    pub unlit: bool,
+    // base color texture has premultiplied alpha; also selects the premultiplied blend
+    pub premultiplied_alpha: bool,
+}
+
+impl MaterialFeatures {
+    pub fn shader_defs(&self) -> Vec<&'static str> {
+        let mut defs = Vec::new();
+        if self.normal_map {
+            defs.push("HAS_NORMAL_MAP");
+        }
+        if self.unlit {
+            defs.push("UNLIT");
+        }
+        if self.premultiplied_alpha {
+            defs.push("PREMULTIPLIED_ALPHA");
+        }
+        defs
+    }
 }
 
 #[repr(C)]
@@ -42,17 +110,29 @@ pub struct MaterialUniform {
     pub roughness_factor: f32,
     pub alpha_cutoff: f32,
     pub double_sided: u32,
+    pub normal_scale: f32,
+    // uv multiplier for the detail texture, see set_detail_texture
+    pub detail_tiling: f32,
+    // 0 leaves the base color alone, 1 multiplies the detail texture in fully
+    pub detail_strength: f32,
+    // MaterialModel::id
+    pub model: u32,
 }
+// Neutral gray dielectric, used for material slot 0 and for primitives without a material.
 impl Default for MaterialUniform {
     fn default() -> Self {
         Self {
-            base_color_factor: [0.0, 1.0, 0.5, 1.0],
-            emissive_factor: [0.0, 5.0, 2.5],
+            base_color_factor: [0.8, 0.8, 0.8, 1.0],
+            emissive_factor: [0.0, 0.0, 0.0],
             emissive_padding: 0.0,
-            metallic_factor: -1.0,
-            roughness_factor: -1.0,
-            alpha_cutoff: -1.0,
-            double_sided: 12345,
+            metallic_factor: 0.0,
+            roughness_factor: 1.0,
+            alpha_cutoff: 0.5,
+            double_sided: 0,
+            normal_scale: 1.0,
+            detail_tiling: 1.0,
+            detail_strength: 0.0,
+            model: MaterialModel::Pbr.id(),
         }
     }
 }
@@ -67,63 +147,40 @@ impl From<&Material> for MaterialUniform {
             roughness_factor: m.roughness_factor,
             alpha_cutoff: m.alpha_cutoff,
             double_sided: if m.double_sided { 1 } else { 0 },
+            normal_scale: m.normal_scale,
             ..Default::default()
         }
     }
 }
 impl AssetManager {
-    pub fn get_material(&mut self, name: &str) -> MaterialId {
+    pub fn get_material(&mut self, name: &str) -> Result<MaterialId, AssetError> {
         if let Some(&id) = self.mat_by_name.get(name) {
-            return id;
+            return Ok(id);
         }
 
-        let (path, selector) = Self::split_key(name);
+        let (path, selector) = Self::split_key(name)?;
 
         let material = self.importer.load_material(path, selector);
 
-        let base_color_tex = material
-            .base_color_texture
-            .map(|info| {
-                println!("base");
-                self.get_texture(
-                    &format!("{}#{}", path, info),
-                    wgpu::TextureFormat::Rgba8UnormSrgb, // Color data
-                )
-            })
-            .unwrap_or(self.color_tex_default);
-
-        let metallic_roughness_tex = material
-            .metallic_roughness_texture
-            .map(|info| {
-                println!("met");
-                self.get_texture(
-                    &format!("{}#{}", path, info),
-                    wgpu::TextureFormat::Rgba8Unorm, // Non-color data
-                )
-            })
-            .unwrap_or(self.data_tex_default);
-
-        let normal_tex = material
-            .normal_texture
-            .map(|info| {
-                println!("normal");
-                self.get_texture(
-                    &format!("{}#{}", path, info),
-                    wgpu::TextureFormat::Rgba8Unorm,
-                )
-            })
-            .unwrap_or(self.data_tex_default);
-
-        let emissive_tex = material
-            .emissive_texture
-            .map(|info| {
-                println!("emmisive");
-                self.get_texture(
-                    &format!("{}#{}", path, info),
-                    wgpu::TextureFormat::Rgba8UnormSrgb,
-                )
-            })
-            .unwrap_or(self.color_tex_default);
+        let mut slot_texture = |info: Option<usize>, slot: TextureSlot| {
+            let default = match slot {
+                TextureSlot::BaseColor | TextureSlot::Emissive | TextureSlot::Detail => {
+                    self.color_tex_default
+                }
+                TextureSlot::MetallicRoughness | TextureSlot::Normal => self.data_tex_default,
+            };
+            info.map(|info| self.get_texture(&format!("{}#{}", path, info), slot))
+                .transpose()
+                .map(|tex| tex.unwrap_or(default))
+        };
+
+        let base_color_tex = slot_texture(material.base_color_texture, TextureSlot::BaseColor)?;
+        let metallic_roughness_tex = slot_texture(
+            material.metallic_roughness_texture,
+            TextureSlot::MetallicRoughness,
+        )?;
+        let normal_tex = slot_texture(material.normal_texture, TextureSlot::Normal)?;
+        let emissive_tex = slot_texture(material.emissive_texture, TextureSlot::Emissive)?;
 
         let uniform: MaterialUniform = MaterialUniform {
             base_color_factor: material.base_color_factor,
@@ -133,6 +190,7 @@ impl AssetManager {
             emissive_padding: 0.0,
             alpha_cutoff: material.alpha_cutoff,
             double_sided: material.double_sided as u32,
+            normal_scale: material.normal_scale,
             ..Default::default()
         };
 
@@ -146,15 +204,135 @@ impl AssetManager {
             metallic_roughness: metallic_roughness_tex,
             normal: normal_tex,
             emissive: emissive_tex,
+            detail: self.color_tex_default,
+        };
+        self.features_by_mat[idx] = MaterialFeatures {
+            normal_map: material.normal_texture.is_some(),
+            unlit: material.unlit,
+            premultiplied_alpha: self.premultiply_alpha,
         };
+        self.tex_revision += 1;
 
-        let offset = (idx * std::mem::size_of::<MaterialUniform>()) as wgpu::BufferAddress;
-        self.queue
-            .write_buffer(&self.mat_buffer, offset, bytemuck::bytes_of(&uniform));
+        self.write_material(idx, uniform);
 
         self.mat_by_name.insert(name.to_string(), idx.into());
-        idx.into()
+        Ok(idx.into())
+    }
+
+    // Overrides how slot 0, the fallback material, looks.
+    pub fn set_default_material(&mut self, uniform: MaterialUniform) {
+        self.default_material = uniform;
+        self.write_material(0, uniform);
+    }
+
+    // Changes a loaded material's factors in place, e.g. to animate its emissive or base
+    // color. Takes effect on the next frame; the material's textures are unchanged.
+    pub fn update_material(
+        &mut self,
+        id: MaterialId,
+        uniform: MaterialUniform,
+    ) -> Result<(), AssetError> {
+        if id.0 >= MAX_MAT {
+            return Err(AssetError::MaterialOutOfRange(id));
+        }
+        if id.0 == 0 {
+            self.default_material = uniform;
+        }
+        self.write_material(id.0, uniform);
+        Ok(())
+    }
+
+    // Tiles `texture` over the material's base color, at uv * detail_tiling and blended in by
+    // detail_strength; set both with update_material. glTF has no detail slot, so loaded
+    // materials start with a white one. Give the texture a repeating sampler.
+    pub fn set_detail_texture(
+        &mut self,
+        id: MaterialId,
+        texture: TextureId,
+    ) -> Result<(), AssetError> {
+        let group = self
+            .tex_by_mat
+            .get_mut(id.0)
+            .ok_or(AssetError::MaterialOutOfRange(id))?;
+        if !self.textures.contains_key(texture) {
+            return Err(AssetError::TextureNotFound(texture));
+        }
+        group.detail = texture;
+        self.tex_revision += 1;
+        Ok(())
+    }
+
+    pub fn set_material_model(
+        &mut self,
+        id: MaterialId,
+        model: MaterialModel,
+    ) -> Result<(), AssetError> {
+        let &uniform = self
+            .material_uniform(id)
+            .ok_or(AssetError::MaterialOutOfRange(id))?;
+        let uniform = MaterialUniform {
+            model: model.id(),
+            ..uniform
+        };
+        self.update_material(id, uniform)
+    }
+
+    pub fn material_model(&self, id: MaterialId) -> Option<MaterialModel> {
+        MaterialModel::from_id(self.mat_uniforms.get(id.0)?.model)
     }
+
+    // Current factors of a material, as last written; a starting point for update_material.
+    pub fn material_uniform(&self, id: MaterialId) -> Option<&MaterialUniform> {
+        self.mat_uniforms.get(id.0)
+    }
+
+    // Stages a material slot; the GPU copy is updated by the next flush_materials.
+    pub fn write_material(&mut self, idx: usize, uniform: MaterialUniform) {
+        self.mat_uniforms[idx] = uniform;
+        self.mat_dirty.push(idx);
+    }
+
+    // Uploads every slot written since the last flush, one write per run of adjacent slots,
+    // so slots far apart don't drag the ones between them along. The renderer calls this at
+    // the start of each frame. Returns the uploaded slot ranges.
+    pub fn flush_materials(&mut self) -> Vec<Range<usize>> {
+        let ranges = coalesce_slots(&mut self.mat_dirty);
+        for range in &ranges {
+            let offset =
+                (range.start * std::mem::size_of::<MaterialUniform>()) as wgpu::BufferAddress;
+            self.queue.write_buffer(
+                &self.mat_buffer,
+                offset,
+                bytemuck::cast_slice(&self.mat_uniforms[range.clone()]),
+            );
+        }
+        ranges
+    }
+
+    pub fn contains_material(&self, name: &str) -> bool {
+        self.mat_by_name.contains_key(name)
+    }
+
+    // Named materials only; slot 0 is the built-in default and has no name.
+    pub fn iter_materials(&self) -> impl Iterator<Item = (&str, MaterialId)> {
+        self.mat_by_name
+            .iter()
+            .map(|(name, &id)| (name.as_str(), id))
+    }
+}
+
+// Sorts `slots` and merges them into ranges of adjacent slots, leaving it empty.
+pub fn coalesce_slots(slots: &mut Vec<usize>) -> Vec<Range<usize>> {
+    slots.sort_unstable();
+    slots.dedup();
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for idx in slots.drain(..) {
+        match ranges.last_mut() {
+            Some(r) if r.end == idx => r.end += 1,
+            _ => ranges.push(idx..idx + 1),
+        }
+    }
+    ranges
 }
 
 #[repr(C, align(16))]
@@ -163,3 +341,108 @@ pub struct MatId {
     pub id: u32,
     pub _pad: [u32; 63],
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_manager::test_support;
+
+    #[test]
+    fn coalesce_slots_merges_adjacent_slots() {
+        let mut slots = vec![5, 2, 3, 5];
+        assert_eq!(coalesce_slots(&mut slots), vec![2..4, 5..6]);
+        assert!(slots.is_empty());
+
+        assert_eq!(coalesce_slots(&mut Vec::new()), Vec::<Range<usize>>::new());
+    }
+
+    #[test]
+    fn flush_uploads_only_written_slots() {
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        assets.flush_materials();
+        for idx in [5, 2, 3, 5] {
+            assets.write_material(idx, MaterialUniform::default());
+        }
+        assert_eq!(assets.flush_materials(), vec![2..4, 5..6]);
+        assert!(assets.flush_materials().is_empty());
+    }
+
+    #[test]
+    fn material_model_ids_round_trip() {
+        for model in [MaterialModel::Pbr, MaterialModel::Toon] {
+            assert_eq!(MaterialModel::from_id(model.id()), Some(model));
+        }
+        assert_eq!(MaterialModel::Toon.id(), 1);
+        assert_eq!(MaterialModel::from_id(2), None);
+    }
+
+    #[test]
+    fn toon_material_carries_model_id_1() {
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        let id = MaterialId(FIRST_FREE_MAT);
+        assets.set_material_model(id, MaterialModel::Toon).unwrap();
+        assert_eq!(assets.material_uniform(id).unwrap().model, 1);
+        assert_eq!(assets.material_model(id), Some(MaterialModel::Toon));
+        assert_eq!(
+            assets.material_model(MaterialId(0)),
+            Some(MaterialModel::Pbr)
+        );
+
+        // out of range ids are reported, not indexed
+        let past_end = MaterialId(MAX_MAT);
+        assert!(matches!(
+            assets.set_material_model(past_end, MaterialModel::Toon),
+            Err(AssetError::MaterialOutOfRange(id)) if id == past_end
+        ));
+        assert!(matches!(
+            assets.update_material(past_end, MaterialUniform::default()),
+            Err(AssetError::MaterialOutOfRange(_))
+        ));
+        assert_eq!(assets.material_model(past_end), None);
+    }
+
+    #[test]
+    fn detail_texture_and_tiling_reach_the_material() {
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        let id = MaterialId(FIRST_FREE_MAT);
+        let dir = test_support::temp_dir("detail");
+        let path = test_support::write_texture_gltf(&dir, [128, 128, 128, 255]);
+        let detail = assets
+            .get_texture(&format!("{}#albedo", path), TextureSlot::Detail)
+            .unwrap();
+
+        let revision = assets.tex_revision;
+        assets.set_detail_texture(id, detail).unwrap();
+        assert_eq!(assets.tex_by_mat[id.0].detail, detail);
+        assert!(assets.tex_revision > revision);
+
+        let uniform = MaterialUniform {
+            detail_tiling: 8.0,
+            detail_strength: 0.5,
+            ..*assets.material_uniform(id).unwrap()
+        };
+        assets.update_material(id, uniform).unwrap();
+        assets.flush_materials();
+        let bytes = assets.read_buffer(&assets.mat_buffer).unwrap();
+        let size = std::mem::size_of::<MaterialUniform>();
+        let on_gpu: MaterialUniform = bytemuck::pod_read_unaligned(&bytes[id.0 * size..][..size]);
+        assert_eq!(on_gpu.detail_tiling, 8.0);
+        assert_eq!(on_gpu.detail_strength, 0.5);
+
+        assert!(matches!(
+            assets.set_detail_texture(MaterialId(MAX_MAT), detail),
+            Err(AssetError::MaterialOutOfRange(_))
+        ));
+        assert!(matches!(
+            assets.set_detail_texture(id, TextureId::default()),
+            Err(AssetError::TextureNotFound(_))
+        ));
+        assert_eq!(assets.tex_by_mat[id.0].detail, detail);
+    }
+}
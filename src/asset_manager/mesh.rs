@@ -1,9 +1,19 @@
-use super::{AssetManager, MeshId, material::MaterialId};
+use super::{
+    AssetError, AssetManager, MeshId,
+    material::{ERROR_MATERIAL, MaterialFeatures, MaterialId, MaterialUniform},
+    packing::VertexFormat,
+};
+
+use std::collections::HashMap;
 
 use bytemuck::{Pod, Zeroable};
+use glam::{Mat4, Vec3};
 use wgpu::util::DeviceExt;
 
 pub const MAX_OBJECTS: usize = 10000;
+// positions closer than this are one vertex after Primitive::merge
+pub const WELD_EPSILON: f32 = 1e-5;
+pub const ERROR_MESH: &str = "engine:error";
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug, Zeroable, Pod)]
@@ -39,12 +49,168 @@ pub struct Primitive {
     pub vertex: Vec<Vertex>,
     pub index: Vec<Index>,
     pub material: Option<usize>,
+    // TriangleList draws `index`; TriangleStrip draws `strip` instead, which takes about a
+    // third of the indices for grids, rings and disks
+    pub topology: wgpu::PrimitiveTopology,
+    pub strip: Vec<u32>,
+}
+
+impl Default for Primitive {
+    fn default() -> Self {
+        Self {
+            vertex: Vec::new(),
+            index: Vec::new(),
+            material: None,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip: Vec::new(),
+        }
+    }
+}
+
+impl Primitive {
+    // set_mesh and rewrite_mesh turn anything draw_indices can't flatten into a load error.
+    pub fn check_topology(&self, name: &str) -> Result<(), AssetError> {
+        match self.topology {
+            wgpu::PrimitiveTopology::TriangleList | wgpu::PrimitiveTopology::TriangleStrip => {
+                Ok(())
+            }
+            other => Err(AssetError::LoadFailed {
+                path: name.to_string(),
+                reason: format!("unsupported primitive topology {:?}", other),
+            }),
+        }
+    }
+
+    // The indices this primitive draws, relative to its own vertices.
+    pub fn draw_indices(&self) -> Vec<u32> {
+        match self.topology {
+            wgpu::PrimitiveTopology::TriangleList => {
+                self.index.iter().flat_map(|i| i.idx).collect()
+            }
+            wgpu::PrimitiveTopology::TriangleStrip => self.strip.clone(),
+            // set_mesh and rewrite_mesh check_topology first
+            other => panic!("unsupported primitive topology: {:?}", other),
+        }
+    }
+
+    // Axis-aligned cube centered on the origin, four vertices per face so each face has
+    // its own normal and full 0..1 uvs.
+    pub fn cube(half_extent: f32) -> Primitive {
+        let mut vertex = Vec::with_capacity(24);
+        let mut index = Vec::with_capacity(12);
+        for axis in 0..3 {
+            for sign in [1.0f32, -1.0] {
+                let mut n = Vec3::ZERO;
+                n[axis] = sign;
+                let mut t = Vec3::ZERO;
+                t[(axis + 1) % 3] = 1.0;
+                let b = n.cross(t);
+
+                let base = vertex.len() as u32;
+                for (u, v) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                    let p = (n + t * (u * 2.0 - 1.0) + b * (v * 2.0 - 1.0)) * half_extent;
+                    vertex.push(Vertex {
+                        position: p.to_array(),
+                        uv: [u, 1.0 - v],
+                        normal: n.to_array(),
+                        tangent: [t.x, t.y, t.z, 1.0],
+                    });
+                }
+                index.push(Index {
+                    idx: [base, base + 1, base + 2],
+                });
+                index.push(Index {
+                    idx: [base, base + 2, base + 3],
+                });
+            }
+        }
+        Primitive {
+            vertex,
+            index,
+            ..Default::default()
+        }
+    }
+
+    // Joins `primitives` into one triangle list, welding vertices at the same position (the
+    // shared edges of adjacent grid tiles, say). A welded vertex keeps the first copy's uv
+    // and tangent and the average of the copies' normals. Triangles that welding collapses
+    // are dropped. Takes the first primitive's material.
+    pub fn merge(primitives: &[Primitive]) -> Primitive {
+        let mut vertex: Vec<Vertex> = Vec::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+        let mut welded: HashMap<[i64; 3], u32> = HashMap::new();
+        let mut index = Vec::new();
+
+        for p in primitives {
+            let remap: Vec<u32> = p
+                .vertex
+                .iter()
+                .map(|v| {
+                    let key = v.position.map(|c| (c / WELD_EPSILON).round() as i64);
+                    let i = *welded.entry(key).or_insert_with(|| {
+                        vertex.push(*v);
+                        normals.push(Vec3::ZERO);
+                        (vertex.len() - 1) as u32
+                    });
+                    normals[i as usize] += Vec3::from(v.normal);
+                    i
+                })
+                .collect();
+
+            let list = match p.topology {
+                wgpu::PrimitiveTopology::TriangleStrip => strip_to_list(&p.strip),
+                _ => p.draw_indices(),
+            };
+            for tri in list.chunks_exact(3) {
+                let idx = [tri[0], tri[1], tri[2]].map(|i| remap[i as usize]);
+                if idx[0] != idx[1] && idx[1] != idx[2] && idx[0] != idx[2] {
+                    index.push(Index { idx });
+                }
+            }
+        }
+
+        for (v, n) in vertex.iter_mut().zip(normals) {
+            v.normal = n.normalize_or(Vec3::from(v.normal)).to_array();
+        }
+
+        let material = primitives.first().and_then(|p| p.material);
+        if primitives.iter().any(|p| p.material != material) {
+            eprintln!(
+                "merge: primitives have different materials; using {:?}",
+                material
+            );
+        }
+
+        Primitive {
+            vertex,
+            index,
+            material,
+            ..Default::default()
+        }
+    }
+}
+
+// Unrolls a triangle strip into a list, keeping every triangle's winding consistent with
+// the first.
+pub fn strip_to_list(strip: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(strip.len().saturating_sub(2) * 3);
+    for (i, w) in strip.windows(3).enumerate() {
+        if i % 2 == 0 {
+            out.extend_from_slice(&[w[0], w[1], w[2]]);
+        } else {
+            out.extend_from_slice(&[w[1], w[0], w[2]]);
+        }
+    }
+    out
 }
 
 pub struct PrimitiveRange {
     pub first_index: u32,
     pub index_count: u32,
     pub base_vertex: i32,
+    pub topology: wgpu::PrimitiveTopology,
+    // same for every primitive of a mesh; selects the pipeline's vertex layout
+    pub vertex_format: VertexFormat,
 
     pub aabb_min: [f32; 3],
     pub aabb_max: [f32; 3],
@@ -60,34 +226,148 @@ pub struct Mesh {
     pub index_format: Option<wgpu::IndexFormat>,
     pub vertex_count: u32,
     pub index_count: u32,
+    pub vertex_format: VertexFormat,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MeshOptions {
+    // keeps a u32 index buffer even when every index fits in u16, so a mesh that will
+    // later grow past 65535 vertices doesn't need a new index format
+    pub force_u32_indices: bool,
+    // Packed halves the vertex buffer at some precision cost in uvs, normals and tangents
+    pub vertex_format: VertexFormat,
+    pub extra_usage: MeshUsage,
+}
+
+// Usages added to both of a mesh's buffers, e.g. INDIRECT or UNIFORM for custom passes. The
+// buffers are always VERTEX/INDEX | COPY_DST | COPY_SRC | STORAGE; MAP_* can't be combined
+// with those and is dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshUsage(pub wgpu::BufferUsages);
+
+impl Default for MeshUsage {
+    fn default() -> Self {
+        Self(wgpu::BufferUsages::empty())
+    }
+}
+
+impl Mesh {
+    // Nothing to draw, e.g. set from no primitives. Every draw path skips it; without
+    // vertices its vertex buffer has zero size and couldn't be bound anyway.
+    pub fn is_empty(&self) -> bool {
+        self.vertex_count == 0 || self.index_count == 0
+    }
+
+    // Union of the primitive bounds in mesh space.
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for p in &self.primitives {
+            for k in 0..3 {
+                min[k] = min[k].min(p.aabb_min[k]);
+                max[k] = max[k].max(p.aabb_max[k]);
+            }
+        }
+        (min, max)
+    }
 }
 
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Zeroable, Pod)]
 pub struct ObjectUniform {
     pub model: [[f32; 4]; 4],
+    // bit i set when light i can reach the object, see render::objects::light_mask
+    pub light_mask: u32,
+    pub _pad: [u32; 3],
+}
+
+impl ObjectUniform {
+    pub fn new(model: Mat4, light_mask: u32) -> Self {
+        Self {
+            model: model.to_cols_array_2d(),
+            light_mask,
+            _pad: [0; 3],
+        }
+    }
 }
 
 impl AssetManager {
-    pub fn get_mesh(&mut self, name: &str) -> MeshId {
+    pub fn get_mesh(&mut self, name: &str) -> Result<MeshId, AssetError> {
         if let Some(&id) = self.meshes_by_name.get(name) {
-            return id;
+            return Ok(id);
         }
 
-        let (path, selector) = Self::split_key(name);
+        let (path, selector) = Self::split_key(name)?;
+
+        let primitives: Vec<Primitive> = self.importer.load_mesh(path, selector)?;
+
+        // set_mesh keeps one range per primitive in order, so primitive i gets materials[i].
+        // Primitives sharing a glTF material share its id through get_material's cache, and
+        // primitives without one use the default material 0 as glTF specifies. Resolved
+        // before set_mesh so a failed material load doesn't leave the mesh registered.
+        let materials = primitives
+            .iter()
+            .map(|prim| match prim.material {
+                Some(mat) => self.get_material(&format!("{}#{}", path, mat)),
+                None => Ok(0.into()),
+            })
+            .collect::<Result<Vec<MaterialId>, AssetError>>()?;
+
+        let id = self.set_mesh(&primitives, name)?;
+        for (idx, material) in materials.into_iter().enumerate() {
+            self.set_mat(id, idx, material)?;
+        }
+        Ok(id)
+    }
 
-        let primitives: Vec<Primitive> = self.importer.load_mesh(path, selector);
-        let id = self.set_mesh(&primitives, name);
-        for (idx, prim) in primitives.iter().enumerate() {
-            let material = if let Some(mat) = prim.material {
-                self.get_material(&format!("{}#{}", path, mat))
-            } else {
-                0.into()
-            };
-            self.set_mat(id, idx, material);
+    // Like get_mesh, but a key that fails to load logs the error and gets the error mesh, so
+    // the problem shows up on screen instead of stopping the game. Importer panics (a
+    // malformed texture, say) still propagate.
+    pub fn get_mesh_or_error(&mut self, name: &str) -> MeshId {
+        match self.get_mesh(name) {
+            Ok(id) => id,
+            Err(e) => {
+                eprintln!("get_mesh: {}; using the error mesh", e);
+                self.error_mesh
+            }
         }
-        id
     }
 
-    pub fn set_mesh(&mut self, primitives: &[Primitive], name: &str) -> MeshId {
+    pub fn create_error_mesh(&mut self) {
+        self.write_material(
+            ERROR_MATERIAL,
+            MaterialUniform {
+                base_color_factor: [1.0, 0.0, 1.0, 1.0],
+                ..Default::default()
+            },
+        );
+        self.features_by_mat[ERROR_MATERIAL] = MaterialFeatures {
+            unlit: true,
+            ..Default::default()
+        };
+        self.error_mesh = self
+            .set_mesh(&[Primitive::cube(0.25)], ERROR_MESH)
+            .expect("the error cube is well formed");
+        self.meshes[self.error_mesh].primitives[0].material = ERROR_MATERIAL.into();
+    }
+
+    // Unlike get_mesh this never triggers a load.
+    pub fn contains_mesh(&self, name: &str) -> bool {
+        self.meshes_by_name.contains_key(name)
+    }
+
+    pub fn set_mesh(&mut self, primitives: &[Primitive], name: &str) -> Result<MeshId, AssetError> {
+        self.set_mesh_with_options(primitives, name, MeshOptions::default())
+    }
+
+    // Fails, registering nothing, when a primitive indexes past its own vertices or isn't a
+    // triangle list or strip.
+    pub fn set_mesh_with_options(
+        &mut self,
+        primitives: &[Primitive],
+        name: &str,
+        options: MeshOptions,
+    ) -> Result<MeshId, AssetError> {
         let mut flat_vertices: Vec<Vertex> = Vec::new();
         let mut flat_indices_u32: Vec<u32> = Vec::new();
         let mut prim_ranges: Vec<PrimitiveRange> = Vec::new();
@@ -95,6 +375,7 @@ impl AssetManager {
         let mut base_vertex: u32 = 0;
 
         for prim in primitives {
+            prim.check_topology(name)?;
             let vcount = prim.vertex.len() as u32;
 
             flat_vertices.extend_from_slice(&prim.vertex);
@@ -114,17 +395,15 @@ impl AssetManager {
             }
 
             let first_index = flat_indices_u32.len() as u32;
-            let mut index_count = 0u32;
-
-            if !prim.index.is_empty() {
-                for index in &prim.index {
-                    let [a, b, c] = &index.idx;
-                    flat_indices_u32.push(base_vertex + a);
-                    flat_indices_u32.push(base_vertex + b);
-                    flat_indices_u32.push(base_vertex + c);
-                }
-                index_count = (prim.index.len() * 3) as u32;
+            let indices = prim.draw_indices();
+            if let Some(&bad) = indices.iter().find(|&&i| i >= vcount) {
+                return Err(AssetError::LoadFailed {
+                    path: name.to_string(),
+                    reason: format!("index {} is past the primitive's {} vertices", bad, vcount),
+                });
             }
+            let index_count = indices.len() as u32;
+            flat_indices_u32.extend(indices.iter().map(|i| base_vertex + i));
 
             //let material = if let Some(mat) = prim.material {
             //    self.get_material(&format!("{}#{}", path, mat))
@@ -135,6 +414,8 @@ impl AssetManager {
                 first_index,
                 index_count,
                 base_vertex: base_vertex as i32,
+                topology: prim.topology,
+                vertex_format: options.vertex_format,
                 aabb_min: min,
                 aabb_max: max,
                 material: 0.into(),
@@ -143,18 +424,36 @@ impl AssetManager {
             base_vertex += vcount;
         }
 
+        if base_vertex == 0 {
+            eprintln!("set_mesh: '{}' has no vertices; it will draw nothing", name);
+        }
+
+        let map_usage = wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::MAP_WRITE;
+        if options.extra_usage.0.intersects(map_usage) {
+            eprintln!("set_mesh: '{}' can't be mapped; ignoring MAP_* usage", name);
+        }
+        let extra_usage = options.extra_usage.0 - map_usage;
+
         let vertex_buf = self
             .device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("mesh:{}:vertex", name)),
-                contents: bytemuck::cast_slice(&flat_vertices),
-                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                contents: &options.vertex_format.encode(&flat_vertices),
+                usage: wgpu::BufferUsages::VERTEX
+                    | wgpu::BufferUsages::COPY_DST
+                    // lets recompute_normals_gpu write normals in place
+                    | wgpu::BufferUsages::STORAGE
+                    // read_mesh_data
+                    | wgpu::BufferUsages::COPY_SRC
+                    | extra_usage,
             });
 
         let (index_buf, index_format) = if flat_indices_u32.is_empty() {
             (None, None)
         } else {
-            let can_u16 = base_vertex <= 0x10000 && flat_indices_u32.iter().all(|&i| i < 0x10000);
+            let can_u16 = !options.force_u32_indices
+                && base_vertex <= 0x10000
+                && flat_indices_u32.iter().all(|&i| i < 0x10000);
 
             if can_u16 {
                 let inds_u16: Vec<u16> = flat_indices_u32.iter().map(|&i| i as u16).collect();
@@ -163,7 +462,11 @@ impl AssetManager {
                     .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                         label: Some(&format!("mesh:{}:index(u16)", name)),
                         contents: bytemuck::cast_slice(&inds_u16),
-                        usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+                        usage: wgpu::BufferUsages::INDEX
+                            | wgpu::BufferUsages::COPY_DST
+                            | wgpu::BufferUsages::STORAGE
+                            | wgpu::BufferUsages::COPY_SRC
+                            | extra_usage,
                     });
                 (Some(ib), Some(wgpu::IndexFormat::Uint16))
             } else {
@@ -172,7 +475,11 @@ impl AssetManager {
                     .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                         label: Some(&format!("mesh:{}:index(u32)", name)),
                         contents: bytemuck::cast_slice(&flat_indices_u32),
-                        usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+                        usage: wgpu::BufferUsages::INDEX
+                            | wgpu::BufferUsages::COPY_DST
+                            | wgpu::BufferUsages::STORAGE
+                            | wgpu::BufferUsages::COPY_SRC
+                            | extra_usage,
                     });
                 (Some(ib), Some(wgpu::IndexFormat::Uint32))
             }
@@ -186,59 +493,74 @@ impl AssetManager {
             index_format,
             vertex_count: base_vertex,
             index_count: flat_indices_u32.len() as u32,
+            vertex_format: options.vertex_format,
         };
 
         let id = self.meshes.insert(mesh);
         self.meshes_by_name.insert(name.to_string(), id);
-        id
+        Ok(id)
+    }
+
+    pub fn rewrite_mesh(
+        &mut self,
+        mesh_id: MeshId,
+        primitives: &[Primitive],
+    ) -> Result<(), AssetError> {
+        let queue = self.queue.clone();
+        self.rewrite_mesh_with(mesh_id, primitives, |buf, data| {
+            queue.write_buffer(buf, 0, data)
+        })
     }
 
-    pub fn rewrite_mesh(&mut self, mesh_id: MeshId, primitives: &[Primitive]) {
-        let mesh = self.meshes.get_mut(mesh_id).expect("invalid mesh_id");
+    // Shared by rewrite_mesh and the renderer's streaming path; `write` uploads the flattened
+    // bytes into the given buffer at offset 0. Nothing is written when the vertex or index
+    // count differs from the mesh's.
+    pub fn rewrite_mesh_with<F: FnMut(&wgpu::Buffer, &[u8])>(
+        &mut self,
+        mesh_id: MeshId,
+        primitives: &[Primitive],
+        mut write: F,
+    ) -> Result<(), AssetError> {
+        let mesh = self
+            .meshes
+            .get_mut(mesh_id)
+            .ok_or(AssetError::MeshNotFound(mesh_id))?;
+        let name = mesh.name.clone().unwrap_or_default();
 
         let mut flat_vertices: Vec<Vertex> = Vec::new();
         let mut flat_indices_u32: Vec<u32> = Vec::new();
         let mut base_vertex: u32 = 0;
 
         for prim in primitives {
+            prim.check_topology(&name)?;
             let vcount = prim.vertex.len() as u32;
             flat_vertices.extend_from_slice(&prim.vertex);
 
-            if !prim.index.is_empty() {
-                for index in &prim.index {
-                    let [a, b, c] = index.idx;
-                    flat_indices_u32.push(base_vertex + a);
-                    flat_indices_u32.push(base_vertex + b);
-                    flat_indices_u32.push(base_vertex + c);
-                }
-            }
+            flat_indices_u32.extend(prim.draw_indices().iter().map(|i| base_vertex + i));
 
             base_vertex += vcount;
         }
 
-        assert_eq!(
-            base_vertex, mesh.vertex_count,
-            "vertex count mismatch on rewrite"
-        );
-        assert_eq!(
-            flat_indices_u32.len() as u32,
-            mesh.index_count,
-            "index count mismatch on rewrite"
-        );
+        let actual = (base_vertex, flat_indices_u32.len() as u32);
+        let expected = (mesh.vertex_count, mesh.index_count);
+        if actual != expected {
+            return Err(AssetError::MeshDataMismatch {
+                mesh: name,
+                expected,
+                actual,
+            });
+        }
 
-        self.queue
-            .write_buffer(&mesh.vertex_buf, 0, bytemuck::cast_slice(&flat_vertices));
+        write(&mesh.vertex_buf, &mesh.vertex_format.encode(&flat_vertices));
 
         if let Some(ref ib) = mesh.index_buf {
             match mesh.index_format {
                 Some(wgpu::IndexFormat::Uint16) => {
                     let inds_u16: Vec<u16> = flat_indices_u32.iter().map(|&i| i as u16).collect();
-                    self.queue
-                        .write_buffer(ib, 0, bytemuck::cast_slice(&inds_u16));
+                    write(ib, bytemuck::cast_slice(&inds_u16));
                 }
                 Some(wgpu::IndexFormat::Uint32) => {
-                    self.queue
-                        .write_buffer(ib, 0, bytemuck::cast_slice(&flat_indices_u32));
+                    write(ib, bytemuck::cast_slice(&flat_indices_u32));
                 }
                 None => {}
             }
@@ -247,7 +569,7 @@ impl AssetManager {
         let mut prim_ranges: Vec<PrimitiveRange> = Vec::new();
         let mut cur_first_index = 0u32;
         let mut cur_base = 0i32;
-        for prim in primitives {
+        for (idx, prim) in primitives.iter().enumerate() {
             let mut min = [f32::INFINITY; 3];
             let mut max = [f32::NEG_INFINITY; 3];
             for v in &prim.vertex {
@@ -262,15 +584,18 @@ impl AssetManager {
                 }
             }
 
-            let index_count = (prim.index.len() * 3) as u32;
+            let index_count = prim.draw_indices().len() as u32;
 
             prim_ranges.push(PrimitiveRange {
                 first_index: cur_first_index,
                 index_count,
                 base_vertex: cur_base,
+                topology: prim.topology,
+                vertex_format: mesh.vertex_format,
                 aabb_min: min,
                 aabb_max: max,
-                material: 0.into(),
+                // primitive i keeps whatever set_mat gave it; new ones get the default
+                material: mesh.primitives.get(idx).map_or(0.into(), |p| p.material),
             });
 
             cur_first_index += index_count;
@@ -278,22 +603,328 @@ impl AssetManager {
         }
 
         mesh.primitives = prim_ranges;
+        Ok(())
     }
 
-    pub fn set_mat(&mut self, mesh_id: MeshId, idx: usize, mat_id: MaterialId) {
-        if let Some(mesh) = self.meshes.get_mut(mesh_id) {
-            assert!(
-                idx < mesh.primitives.len(),
-                "set_mat: primitive index {idx} out of range for mesh {:?}",
-                mesh.name
-            );
-            mesh.primitives[idx].material = mat_id;
-        } else {
-            panic!("set_mat: mesh_id {:?} not found", mesh_id);
+    pub fn set_mat(
+        &mut self,
+        mesh_id: MeshId,
+        idx: usize,
+        mat_id: MaterialId,
+    ) -> Result<(), AssetError> {
+        let mesh = self
+            .meshes
+            .get_mut(mesh_id)
+            .ok_or(AssetError::MeshNotFound(mesh_id))?;
+        let count = mesh.primitives.len();
+        let primitive =
+            mesh.primitives
+                .get_mut(idx)
+                .ok_or_else(|| AssetError::PrimitiveOutOfRange {
+                    mesh: mesh.name.clone().unwrap_or_default(),
+                    index: idx,
+                    count,
+                })?;
+        primitive.material = mat_id;
+        Ok(())
+    }
+
+    // Copies a mesh's buffers back from the GPU. Indices are returned as u32 whatever the
+    // buffer format and already include each primitive's base vertex; they are empty for
+    // non-indexed meshes. Strip primitives keep their strip order, and packed vertices are
+    // unpacked. Blocks on the GPU, so returns None where that can't be waited on (WebGPU),
+    // as well as for an unknown mesh.
+    pub fn read_mesh_data(&self, mesh_id: MeshId) -> Option<(Vec<Vertex>, Vec<u32>)> {
+        let mesh = self.meshes.get(mesh_id)?;
+        if mesh.vertex_count == 0 {
+            // a zero-sized buffer can't be mapped
+            return Some((Vec::new(), Vec::new()));
         }
+
+        let vertex_bytes = self.read_buffer(&mesh.vertex_buf)?;
+        let vertex_size = mesh.vertex_count as usize * mesh.vertex_format.stride() as usize;
+        let vertices = mesh.vertex_format.decode(&vertex_bytes[..vertex_size]);
+
+        let indices = match (&mesh.index_buf, mesh.index_format) {
+            (Some(ib), Some(format)) => {
+                let bytes = self.read_buffer(ib)?;
+                let count = mesh.index_count as usize;
+                match format {
+                    wgpu::IndexFormat::Uint16 => bytes[..count * 2]
+                        .chunks_exact(2)
+                        .map(|c| u16::from_le_bytes([c[0], c[1]]) as u32)
+                        .collect(),
+                    wgpu::IndexFormat::Uint32 => bytemuck::pod_collect_to_vec(&bytes[..count * 4]),
+                }
+            }
+            _ => Vec::new(),
+        };
+
+        Some((vertices, indices))
+    }
+
+    // The buffer needs COPY_SRC. Blocks on the GPU like read_mesh_data.
+    pub fn read_buffer(&self, buffer: &wgpu::Buffer) -> Option<Vec<u8>> {
+        if buffer.size() == 0 {
+            return Some(Vec::new());
+        }
+        let staging = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Mesh Readback"),
+            size: buffer.size(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Mesh Readback Encoder"),
+            });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, buffer.size());
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        if let Err(e) = self.device.poll(wgpu::PollType::Wait) {
+            eprintln!("read_mesh_data: device poll failed: {}", e);
+        }
+        let Ok(Ok(())) = rx.try_recv() else {
+            return None;
+        };
+
+        let bytes = slice.get_mapped_range().to_vec();
+        staging.unmap();
+        Some(bytes)
     }
 
     pub fn mesh(&self, key: MeshId) -> Option<&Mesh> {
         self.meshes.get(key)
     }
+
+    // Every loaded or uploaded mesh; `Mesh::name` holds the key it was registered under.
+    pub fn iter_meshes(&self) -> impl Iterator<Item = (MeshId, &Mesh)> {
+        self.meshes.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_manager::importer::GltfImporter;
+    use crate::asset_manager::test_support;
+
+    // `quads` x `quads` cells of size 1 in the xz plane from `origin`, facing up.
+    fn grid(origin: Vec3, quads: u32) -> Primitive {
+        let side = quads + 1;
+        let vertex = (0..side * side)
+            .map(|i| Vertex {
+                position: (origin + Vec3::new((i % side) as f32, 0.0, (i / side) as f32))
+                    .to_array(),
+                uv: [0.0; 2],
+                normal: [0.0, 1.0, 0.0],
+                tangent: [1.0, 0.0, 0.0, 1.0],
+            })
+            .collect();
+        let mut index = Vec::new();
+        for z in 0..quads {
+            for x in 0..quads {
+                let i = z * side + x;
+                index.push(Index {
+                    idx: [i, i + side, i + 1],
+                });
+                index.push(Index {
+                    idx: [i + 1, i + side, i + side + 1],
+                });
+            }
+        }
+        Primitive {
+            vertex,
+            index,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn merge_welds_the_shared_edge_of_two_grids() {
+        let left = grid(Vec3::ZERO, 2);
+        let right = grid(Vec3::new(2.0, 0.0, 0.0), 2);
+        let merged = Primitive::merge(&[left, right]);
+
+        // 9 + 9 vertices, 3 of them on the shared x = 2 edge
+        assert_eq!(merged.vertex.len(), 15);
+        assert_eq!(merged.index.len(), 16);
+        let on_edge: Vec<u32> = (0..merged.vertex.len() as u32)
+            .filter(|&i| merged.vertex[i as usize].position[0] == 2.0)
+            .collect();
+        assert_eq!(on_edge.len(), 3);
+        // each edge vertex is used by triangles on both sides
+        for &v in &on_edge {
+            let sides: Vec<bool> = merged
+                .index
+                .iter()
+                .filter(|t| t.idx.contains(&v))
+                .map(|t| {
+                    t.idx
+                        .iter()
+                        .any(|&i| merged.vertex[i as usize].position[0] > 2.0)
+                })
+                .collect();
+            assert!(sides.contains(&true) && sides.contains(&false));
+        }
+        for v in &merged.vertex {
+            assert_eq!(v.normal, [0.0, 1.0, 0.0]);
+        }
+    }
+
+    // One triangle. "mixed" has a primitive with uvs and one without; "broken" indexes past
+    // its three vertices; "painted" draws it twice with two different materials.
+    fn write_mesh_gltf(name: &str) -> (test_support::TempDir, String) {
+        let dir = test_support::temp_dir(name);
+        let mut bin: Vec<u8> = Vec::new();
+        for v in [[0.0f32, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]] {
+            bin.extend(v.iter().flat_map(|f| f.to_le_bytes()));
+        }
+        for uv in [[0.25f32, 0.5], [0.75, 0.5], [0.25, 1.0]] {
+            bin.extend(uv.iter().flat_map(|f| f.to_le_bytes()));
+        }
+        for i in [0u16, 1, 2, 0, 1, 5] {
+            bin.extend(i.to_le_bytes());
+        }
+        std::fs::write(dir.join("mesh.bin"), &bin).unwrap();
+
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "buffers": [{ "uri": "mesh.bin", "byteLength": 72 }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 36 },
+                { "buffer": 0, "byteOffset": 36, "byteLength": 24 },
+                { "buffer": 0, "byteOffset": 60, "byteLength": 6 },
+                { "buffer": 0, "byteOffset": 66, "byteLength": 6 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                  "min": [0, 0, 0], "max": [1, 1, 0] },
+                { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC2" },
+                { "bufferView": 2, "componentType": 5123, "count": 3, "type": "SCALAR" },
+                { "bufferView": 3, "componentType": 5123, "count": 3, "type": "SCALAR" }
+            ],
+            "materials": [{ "name": "red" }, { "name": "blue" }],
+            "meshes": [
+                { "name": "mixed", "primitives": [
+                    { "attributes": { "POSITION": 0, "TEXCOORD_0": 1 }, "indices": 2 },
+                    { "attributes": { "POSITION": 0 }, "indices": 2 }
+                ] },
+                { "name": "broken", "primitives": [
+                    { "attributes": { "POSITION": 0 }, "indices": 3 }
+                ] },
+                { "name": "painted", "primitives": [
+                    { "attributes": { "POSITION": 0 }, "indices": 2, "material": 1 },
+                    { "attributes": { "POSITION": 0 }, "indices": 2, "material": 0 }
+                ] }
+            ]
+        }"#;
+        let path = dir.join("mesh.gltf");
+        std::fs::write(&path, json).unwrap();
+        let path = path.to_str().unwrap().to_string();
+        (dir, path)
+    }
+
+    #[test]
+    fn primitives_with_different_attributes_share_the_vertex_layout() {
+        let (_dir, path) = write_mesh_gltf("mixed-attributes");
+        let prims = GltfImporter::new().load_mesh(&path, Some("mixed")).unwrap();
+        assert_eq!(prims.len(), 2);
+        for prim in &prims {
+            assert_eq!(prim.vertex.len(), 3);
+            // no NORMAL in either primitive
+            assert!(prim.vertex.iter().all(|v| v.normal == [0.0, 1.0, 0.0]));
+            assert!(prim.vertex.iter().all(|v| v.tangent[3].abs() == 1.0));
+        }
+        assert_eq!(prims[0].vertex[1].uv, [0.75, 0.5]);
+        assert!(prims[1].vertex.iter().all(|v| v.uv == [0.0, 0.0]));
+        assert_eq!(prims[0].index.len(), prims[1].index.len());
+    }
+
+    #[test]
+    fn out_of_range_indices_are_load_errors() {
+        let (_dir, path) = write_mesh_gltf("broken-indices");
+        assert!(matches!(
+            GltfImporter::new().load_mesh(&path, Some("broken")),
+            Err(AssetError::LoadFailed { .. })
+        ));
+
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        let key = format!("{}#broken", path);
+        assert_eq!(assets.get_mesh_or_error(&key), assets.error_mesh);
+
+        let mut prim = Primitive::cube(1.0);
+        prim.index[0].idx[2] = prim.vertex.len() as u32;
+        assert!(assets.set_mesh(&[prim], "bad").is_err());
+        assert!(!assets.contains_mesh("bad"));
+    }
+
+    #[test]
+    fn primitive_materials_survive_a_rewrite() {
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        let (_dir, path) = write_mesh_gltf("painted");
+        let mesh = assets.get_mesh(&format!("{}#painted", path)).unwrap();
+        let red = assets.get_material(&format!("{}#0", path)).unwrap();
+        let blue = assets.get_material(&format!("{}#1", path)).unwrap();
+        assert_ne!(red, blue);
+        let materials = |assets: &AssetManager| -> Vec<MaterialId> {
+            let mesh = assets.mesh(mesh).unwrap();
+            mesh.primitives.iter().map(|p| p.material).collect()
+        };
+        assert_eq!(materials(&assets), [blue, red]);
+
+        let mut prims = GltfImporter::new()
+            .load_mesh(&path, Some("painted"))
+            .unwrap();
+        for v in prims.iter_mut().flat_map(|p| p.vertex.iter_mut()) {
+            v.position[2] = 1.0;
+        }
+        assets.rewrite_mesh(mesh, &prims).unwrap();
+        assert_eq!(materials(&assets), [blue, red]);
+        assert_eq!(assets.mesh(mesh).unwrap().primitives[0].aabb_min[2], 1.0);
+    }
+
+    #[test]
+    fn rewrites_that_change_the_mesh_size_are_errors() {
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        let mesh = assets.set_mesh(&[grid(Vec3::ZERO, 2)], "grid").unwrap();
+
+        let bigger = grid(Vec3::ZERO, 3);
+        assert!(matches!(
+            assets.rewrite_mesh(mesh, &[bigger]),
+            Err(AssetError::MeshDataMismatch {
+                expected: (9, 24),
+                actual: (16, 54),
+                ..
+            })
+        ));
+        let mut lines = grid(Vec3::ZERO, 2);
+        lines.topology = wgpu::PrimitiveTopology::LineList;
+        assert!(matches!(
+            assets.rewrite_mesh(mesh, &[lines.clone()]),
+            Err(AssetError::LoadFailed { .. })
+        ));
+        assert_eq!(assets.mesh(mesh).unwrap().primitives[0].aabb_max[0], 2.0);
+
+        assert!(matches!(
+            assets.set_mesh(&[lines], "lines"),
+            Err(AssetError::LoadFailed { .. })
+        ));
+        assert!(!assets.contains_mesh("lines"));
+        assets.rewrite_mesh(mesh, &[grid(Vec3::ONE, 2)]).unwrap();
+        assert_eq!(assets.mesh(mesh).unwrap().primitives[0].aabb_max[0], 3.0);
+    }
 }
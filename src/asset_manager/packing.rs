@@ -0,0 +1,194 @@
+use bytemuck::{Pod, Zeroable};
+use glam::Vec3;
+
+use super::mesh::Vertex;
+
+// How a mesh's vertices are laid out in its vertex buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum VertexFormat {
+    // Vertex, 48 bytes, full float precision
+    #[default]
+    Full,
+    // PackedVertex, 24 bytes
+    Packed,
+}
+
+impl VertexFormat {
+    pub fn stride(self) -> u64 {
+        match self {
+            VertexFormat::Full => std::mem::size_of::<Vertex>() as u64,
+            VertexFormat::Packed => std::mem::size_of::<PackedVertex>() as u64,
+        }
+    }
+
+    pub fn buffer_layout<'a>(self) -> wgpu::VertexBufferLayout<'a> {
+        match self {
+            VertexFormat::Full => Vertex::buffer_layout(),
+            VertexFormat::Packed => PackedVertex::buffer_layout(),
+        }
+    }
+
+    // The vertex buffer contents for `vertices` in this format.
+    pub fn encode(self, vertices: &[Vertex]) -> Vec<u8> {
+        match self {
+            VertexFormat::Full => bytemuck::cast_slice(vertices).to_vec(),
+            VertexFormat::Packed => {
+                let packed: Vec<PackedVertex> = vertices.iter().map(PackedVertex::pack).collect();
+                bytemuck::cast_slice(&packed).to_vec()
+            }
+        }
+    }
+
+    pub fn decode(self, bytes: &[u8]) -> Vec<Vertex> {
+        match self {
+            VertexFormat::Full => bytemuck::pod_collect_to_vec(bytes),
+            VertexFormat::Packed => bytemuck::pod_collect_to_vec::<u8, PackedVertex>(bytes)
+                .iter()
+                .map(PackedVertex::unpack)
+                .collect(),
+        }
+    }
+}
+
+// Half the size of Vertex for bandwidth-bound targets. Positions stay full floats; uvs are
+// half floats, the normal is octahedral encoded in two snorm16s and the tangent is snorm8
+// with the handedness in w. Unpacked by the vertex fetch, except for the normal, which the
+// shader decodes (PACKED_VERTICES in forward.wgsl).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Zeroable, Pod)]
+pub struct PackedVertex {
+    pub position: [f32; 3],
+    pub uv: [u16; 2],
+    pub normal: [i16; 2],
+    pub tangent: [i8; 4],
+}
+
+impl PackedVertex {
+    pub const ATTRS: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float16x2,
+        2 => Snorm16x2,
+        3 => Snorm8x4,
+    ];
+
+    pub fn buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<PackedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRS,
+        }
+    }
+
+    pub fn pack(v: &Vertex) -> Self {
+        let snorm8 = |x: f32| (x.clamp(-1.0, 1.0) * 127.0).round() as i8;
+        let t = Vec3::from_slice(&v.tangent[..3]).normalize_or_zero();
+        Self {
+            position: v.position,
+            uv: v.uv.map(f32_to_f16),
+            normal: oct_encode(v.normal),
+            tangent: [
+                snorm8(t.x),
+                snorm8(t.y),
+                snorm8(t.z),
+                if v.tangent[3] < 0.0 { -127 } else { 127 },
+            ],
+        }
+    }
+
+    pub fn unpack(&self) -> Vertex {
+        let snorm8 = |x: i8| (x as f32 / 127.0).max(-1.0);
+        Vertex {
+            position: self.position,
+            uv: self.uv.map(f16_to_f32),
+            normal: oct_decode(self.normal),
+            tangent: self.tangent.map(snorm8),
+        }
+    }
+}
+
+fn snorm16(x: f32) -> i16 {
+    (x.clamp(-1.0, 1.0) * 32767.0).round() as i16
+}
+
+fn sign_not_zero(x: f32) -> f32 {
+    if x >= 0.0 { 1.0 } else { -1.0 }
+}
+
+// Maps a unit vector onto the octahedron and unfolds it into the [-1, 1] square. Must match
+// oct_decode in forward.wgsl.
+pub fn oct_encode(n: [f32; 3]) -> [i16; 2] {
+    let n = Vec3::from(n);
+    let n = n / (n.x.abs() + n.y.abs() + n.z.abs()).max(1e-20);
+    let (x, y) = if n.z >= 0.0 {
+        (n.x, n.y)
+    } else {
+        (
+            (1.0 - n.y.abs()) * sign_not_zero(n.x),
+            (1.0 - n.x.abs()) * sign_not_zero(n.y),
+        )
+    };
+    [snorm16(x), snorm16(y)]
+}
+
+pub fn oct_decode(e: [i16; 2]) -> [f32; 3] {
+    let x = (e[0] as f32 / 32767.0).max(-1.0);
+    let y = (e[1] as f32 / 32767.0).max(-1.0);
+    let mut n = Vec3::new(x, y, 1.0 - x.abs() - y.abs());
+    let t = (-n.z).max(0.0);
+    n.x -= t * sign_not_zero(n.x);
+    n.y -= t * sign_not_zero(n.y);
+    n.normalize_or_zero().to_array()
+}
+
+// IEEE 754 binary16 bits of `v`, rounded to nearest even.
+pub fn f32_to_f16(v: f32) -> u16 {
+    let bits = v.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exp = ((bits >> 23) & 0xff) as i32;
+    let mant = bits & 0x7f_ffff;
+
+    if exp == 0xff {
+        // inf stays inf, NaN stays NaN
+        return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 };
+    }
+    let e = exp - 127 + 15;
+    if e >= 0x1f {
+        return sign | 0x7c00;
+    }
+    let round = |value: u32, rem: u32, halfway: u32| {
+        if rem > halfway || (rem == halfway && value & 1 == 1) {
+            value + 1
+        } else {
+            value
+        }
+    };
+    if e <= 0 {
+        // subnormal half, or zero when too small
+        if e < -10 {
+            return sign;
+        }
+        let m = mant | 0x80_0000;
+        let shift = (14 - e) as u32;
+        let value = round(m >> shift, m & ((1 << shift) - 1), 1 << (shift - 1));
+        return sign | value as u16;
+    }
+    // a carry out of the mantissa correctly bumps the exponent, up to inf
+    let value = round(((e as u32) << 10) | (mant >> 13), mant & 0x1fff, 0x1000);
+    sign | value as u16
+}
+
+pub fn f16_to_f32(h: u16) -> f32 {
+    let sign = ((h & 0x8000) as u32) << 16;
+    let exp = ((h >> 10) & 0x1f) as u32;
+    let mant = (h & 0x3ff) as u32;
+    let bits = match (exp, mant) {
+        (0, 0) => sign,
+        (0, m) => {
+            let magnitude = m as f32 * 2f32.powi(-24);
+            return if sign != 0 { -magnitude } else { magnitude };
+        }
+        (0x1f, m) => sign | 0x7f80_0000 | (m << 13),
+        (e, m) => sign | ((e + 112) << 23) | (m << 13),
+    };
+    f32::from_bits(bits)
+}
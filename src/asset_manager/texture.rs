@@ -1,3 +1,4 @@
+use crate::asset_manager::AssetError;
 use crate::asset_manager::AssetManager;
 use crate::asset_manager::SamplerId;
 use crate::asset_manager::TextureId;
@@ -6,6 +7,9 @@ use wgpu::util::TextureDataOrder;
 pub const MAX_COLOR_TEXTURES: u32 = 1024;
 pub const MAX_DATA_TEXTURES: u32 = 1024;
 pub const MAX_DEPTH_TEXTURES: u32 = 1024;
+// sampler_by_name key of the sampler glTF specifies for textures that don't name one; not a
+// valid path#index key, so it can't collide with an imported sampler
+pub const GLTF_DEFAULT_SAMPLER: &str = "gltf:default";
 //#[derive(Debug, Clone, Copy)]
 //pub struct TextureId(pub usize);
 //
@@ -44,6 +48,28 @@ pub struct Sampler {
     pub min_filter: FilterMode,
     pub mipmap_filter: FilterMode,
 }
+
+impl Sampler {
+    // Cube lookups pick the face from the direction, so wrapping only matters at face
+    // edges, where anything but clamping bleeds across the seam.
+    pub fn cubemap() -> Self {
+        Self {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+        }
+    }
+
+    // glTF samplers only describe U and V; 3D textures set the depth axis here.
+    pub fn with_address_mode_w(mut self, mode: AddressMode) -> Self {
+        self.address_mode_w = mode;
+        self
+    }
+}
+
 pub struct Texture {
     pub pixels: Vec<u8>,
     pub width: u32,
@@ -54,12 +80,38 @@ pub struct Texture {
 pub struct TextureKey {
     key: String,
     format: wgpu::TextureFormat,
+    premultiplied: bool,
 }
 
 pub struct GpuTexture {
     pub tex: wgpu::Texture,
     pub tex_view: wgpu::TextureView,
     pub sampler: SamplerId,
+    // what get_texture loaded it from, for reload_texture; None for generated textures
+    pub source: Option<TextureKey>,
+}
+
+// Which material input a texture feeds. The slot decides the color space: color inputs are
+// decoded from sRGB when sampled, data inputs are read as stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TextureSlot {
+    BaseColor,
+    Emissive,
+    MetallicRoughness,
+    Normal,
+    // tiled multiplier over the base color, see set_detail_texture
+    Detail,
+}
+
+impl TextureSlot {
+    pub fn format(self) -> wgpu::TextureFormat {
+        match self {
+            TextureSlot::BaseColor | TextureSlot::Emissive | TextureSlot::Detail => {
+                wgpu::TextureFormat::Rgba8UnormSrgb
+            }
+            TextureSlot::MetallicRoughness | TextureSlot::Normal => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -68,34 +120,90 @@ pub struct TextureGroup {
     pub metallic_roughness: TextureId,
     pub normal: TextureId,
     pub emissive: TextureId,
+    pub detail: TextureId,
 }
 impl AssetManager {
-    pub fn get_texture(&mut self, key: &str, format: wgpu::TextureFormat) -> TextureId {
+    pub fn get_texture(&mut self, key: &str, slot: TextureSlot) -> Result<TextureId, AssetError> {
+        let format = slot.format();
+        let premultiplied = self.premultiplies(slot);
         let tex_key = TextureKey {
             key: key.to_string(),
             format,
+            premultiplied,
         };
 
         if let Some(&id) = self.tex_by_key.get(&tex_key) {
-            return id;
+            return Ok(id);
         }
 
-        let (path, selector) =
-            Self::split_path(key).expect("get_texture: key not valid! expected in form path#0");
-
-        let tex_data = self.importer.load_texture(path, selector);
+        let (texture, sampler) = self.upload_texture(&tex_key)?;
 
-        let sampler_id = if let Some(sampler_index) = tex_data.sampler {
+        let sampler_id = if let Some(sampler_index) = sampler {
+            let (path, _) = key.split_once('#').unwrap_or((key, ""));
             let sampler_key = format!("{}#{}", path, sampler_index);
-            self.get_sampler(&sampler_key)
+            self.get_sampler(&sampler_key)?
         } else {
-            self.sampler_default
+            self.gltf_default_sampler()
         };
 
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let new_id = self.textures.insert(GpuTexture {
+            tex: texture,
+            tex_view: view,
+            sampler: sampler_id,
+            source: Some(tex_key.clone()),
+        });
+
+        self.tex_by_key.insert(tex_key, new_id);
+        self.tex_revision += 1;
+        Ok(new_id)
+    }
+
+    // Reads a texture loaded by get_texture from its file again and swaps in the new
+    // contents. The id stays the same, so materials using it pick the change up (static
+    // bundles only once recorded again); the size may differ from before.
+    pub fn reload_texture(&mut self, id: TextureId) -> Result<(), AssetError> {
+        let source = self
+            .textures
+            .get(id)
+            .ok_or(AssetError::TextureNotFound(id))?
+            .source
+            .clone()
+            .ok_or(AssetError::TextureNotReloadable(id))?;
+
+        let (texture, _) = self.upload_texture(&source)?;
+        let gpu = &mut self.textures[id];
+        gpu.tex_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        gpu.tex = texture;
+        self.tex_revision += 1;
+        Ok(())
+    }
+
+    // Decodes the image `key` selects and uploads it; also returns its glTF sampler index.
+    fn upload_texture(
+        &mut self,
+        key: &TextureKey,
+    ) -> Result<(wgpu::Texture, Option<usize>), AssetError> {
+        let (path, selector) =
+            Self::split_path(&key.key).map_err(|e| AssetError::InvalidPath(key.key.clone(), e))?;
+
+        let mut tex_data = self.importer.load_texture(path, selector)?;
+        if key.premultiplied {
+            premultiply_alpha(&mut tex_data.pixels, key.format.is_srgb());
+        }
+
+        self.validate_texture_data(
+            &tex_data.pixels,
+            tex_data.width,
+            tex_data.height,
+            key.format,
+        )?;
+
         let texture = self.device.create_texture_with_data(
             &self.queue,
             &wgpu::TextureDescriptor {
-                label: Some(key),
+                label: Some(&key.key),
                 size: wgpu::Extent3d {
                     width: tex_data.width,
                     height: tex_data.height,
@@ -104,62 +212,179 @@ impl AssetManager {
                 mip_level_count: 1,
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
-                format,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                format: key.format,
+                // COPY_SRC so loaded textures can be read back, e.g. to check a reload
+                usage: wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::COPY_SRC,
                 view_formats: &[],
             },
             wgpu::util::TextureDataOrder::LayerMajor,
             &tex_data.pixels,
         );
+        Ok((texture, tex_data.sampler))
+    }
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    // The same key loaded for a slot of the other color space counts as a different texture.
+    pub fn contains_texture(&self, key: &str, slot: TextureSlot) -> bool {
+        let format = slot.format();
+        self.tex_by_key.contains_key(&TextureKey {
+            key: key.to_string(),
+            format,
+            premultiplied: self.premultiplies(slot),
+        })
+    }
 
-        let new_id = self.textures.insert(GpuTexture {
-            tex: texture,
-            tex_view: view,
-            sampler: sampler_id,
-        });
+    // Premultiplied base color filters without dark fringes around cutouts and is blended
+    // with PREMULTIPLIED_ALPHA_BLENDING. Applies to materials loaded afterwards; materials
+    // already loaded keep their textures and blending.
+    pub fn set_premultiply_alpha(&mut self, enabled: bool) {
+        self.premultiply_alpha = enabled;
+    }
 
-        self.tex_by_key.insert(tex_key, new_id);
-        new_id
+    fn premultiplies(&self, slot: TextureSlot) -> bool {
+        self.premultiply_alpha && slot == TextureSlot::BaseColor
     }
 
-    pub fn get_sampler(&mut self, key: &str) -> SamplerId {
-        let (path, selector) = Self::split_path(key).expect(&format!(
-            "get_sampler: {} is not a valid key! Expected format is path#0",
-            key
-        ));
+    // Includes the default color/data/depth textures.
+    pub fn iter_textures(&self) -> impl Iterator<Item = (TextureId, &GpuTexture)> {
+        self.textures.iter()
+    }
 
-        *self
-            .sampler_by_name
-            .entry(key.to_string())
-            .or_insert_with(|| {
-                let sampler_info = self.importer.load_sampler(path, selector);
-
-                let wrap = |m: AddressMode| match m {
-                    AddressMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
-                    AddressMode::MirrorRepeat => wgpu::AddressMode::MirrorRepeat,
-                    AddressMode::Repeat => wgpu::AddressMode::Repeat,
-                };
-
-                let filter = |f: FilterMode| match f {
-                    FilterMode::Nearest => wgpu::FilterMode::Nearest,
-                    FilterMode::Linear => wgpu::FilterMode::Linear,
-                };
-
-                let new_sampler = self.device.create_sampler(&wgpu::SamplerDescriptor {
-                    label: Some(key),
-                    address_mode_u: wrap(sampler_info.address_mode_u),
-                    address_mode_v: wrap(sampler_info.address_mode_v),
-                    address_mode_w: wrap(sampler_info.address_mode_w),
-
-                    mag_filter: filter(sampler_info.mag_filter),
-                    min_filter: filter(sampler_info.min_filter),
-                    mipmap_filter: filter(sampler_info.mipmap_filter),
-                    ..Default::default()
-                });
-                self.samplers.insert(new_sampler)
-            })
+    pub fn get_sampler(&mut self, key: &str) -> Result<SamplerId, AssetError> {
+        let (path, selector) =
+            Self::split_path(key).map_err(|e| AssetError::InvalidPath(key.to_string(), e))?;
+
+        if let Some(&id) = self.sampler_by_name.get(key) {
+            return Ok(id);
+        }
+
+        let sampler_info = self.importer.load_sampler(path, selector)?;
+        let id = self.create_sampler(key, &sampler_info);
+        self.sampler_by_name.insert(key.to_string(), id);
+        Ok(id)
+    }
+
+    // glTF textures without a sampler repeat, unlike the engine's clamping default sampler.
+    pub fn gltf_default_sampler(&mut self) -> SamplerId {
+        if let Some(&id) = self.sampler_by_name.get(GLTF_DEFAULT_SAMPLER) {
+            return id;
+        }
+        let id = self.create_sampler(
+            GLTF_DEFAULT_SAMPLER,
+            &Sampler {
+                address_mode_u: AddressMode::Repeat,
+                address_mode_v: AddressMode::Repeat,
+                address_mode_w: AddressMode::ClampToEdge,
+                mag_filter: FilterMode::Linear,
+                min_filter: FilterMode::Linear,
+                mipmap_filter: FilterMode::Nearest,
+            },
+        );
+        self.sampler_by_name
+            .insert(GLTF_DEFAULT_SAMPLER.to_string(), id);
+        id
+    }
+
+    // Registers an unnamed sampler; get_sampler won't find it by key.
+    pub fn create_sampler(&mut self, label: &str, sampler_info: &Sampler) -> SamplerId {
+        let wrap = |m: &AddressMode| match m {
+            AddressMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+            AddressMode::MirrorRepeat => wgpu::AddressMode::MirrorRepeat,
+            AddressMode::Repeat => wgpu::AddressMode::Repeat,
+        };
+
+        let filter = |f: &FilterMode| match f {
+            FilterMode::Nearest => wgpu::FilterMode::Nearest,
+            FilterMode::Linear => wgpu::FilterMode::Linear,
+        };
+
+        let new_sampler = self.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some(label),
+            address_mode_u: wrap(&sampler_info.address_mode_u),
+            address_mode_v: wrap(&sampler_info.address_mode_v),
+            address_mode_w: wrap(&sampler_info.address_mode_w),
+
+            mag_filter: filter(&sampler_info.mag_filter),
+            min_filter: filter(&sampler_info.min_filter),
+            mipmap_filter: filter(&sampler_info.mipmap_filter),
+            ..Default::default()
+        });
+        self.tex_revision += 1;
+        self.samplers.insert(new_sampler)
+    }
+
+    // Uploads a procedurally generated texture together with its own sampler, e.g. a
+    // tiling data texture that needs Repeat wrapping. `data` is tightly packed rows in
+    // `format`.
+    pub fn create_texture_with_sampler(
+        &mut self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        sampler_info: &Sampler,
+    ) -> Result<TextureId, AssetError> {
+        self.validate_texture_data(data, width, height, format)?;
+        let sampler = self.create_sampler("procedural_sampler", sampler_info);
+
+        let tex = self.device.create_texture_with_data(
+            &self.queue,
+            &wgpu::TextureDescriptor {
+                label: Some("procedural_texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            TextureDataOrder::LayerMajor,
+            data,
+        );
+
+        let id = self.textures.insert(GpuTexture {
+            tex_view: tex.create_view(&wgpu::TextureViewDescriptor::default()),
+            tex,
+            sampler,
+            source: None,
+        });
+        self.tex_revision += 1;
+        Ok(id)
+    }
+
+    // Checks that `data` is exactly one mip of `width` x `height` in `format`, which
+    // create_texture_with_data would otherwise panic on.
+    pub fn validate_texture_data(
+        &self,
+        data: &[u8],
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Result<(), AssetError> {
+        let Some(block_size) = format.block_copy_size(None) else {
+            return Err(AssetError::UnsupportedTextureFormat(format));
+        };
+        if !self.device.features().contains(format.required_features()) {
+            return Err(AssetError::UnsupportedTextureFormat(format));
+        }
+        let (block_width, block_height) = format.block_dimensions();
+        let expected = width.div_ceil(block_width) as usize
+            * height.div_ceil(block_height) as usize
+            * block_size as usize;
+        if data.len() != expected {
+            return Err(AssetError::TextureDataMismatch {
+                format,
+                expected,
+                actual: data.len(),
+            });
+        }
+        Ok(())
     }
 
     pub fn create_color_texture(
@@ -234,3 +459,130 @@ impl AssetManager {
         })
     }
 }
+
+// Bytes the texture's full mip chain takes, ignoring any padding the driver adds.
+pub fn texture_bytes(texture: &wgpu::Texture) -> u64 {
+    let format = texture.format();
+    let (block_w, block_h) = format.block_dimensions();
+    let block_size = format.block_copy_size(None).unwrap_or(4) as u64;
+    let layers = texture.depth_or_array_layers() as u64;
+    (0..texture.mip_level_count())
+        .map(|mip| {
+            let w = (texture.width() >> mip).max(1).div_ceil(block_w) as u64;
+            let h = (texture.height() >> mip).max(1).div_ceil(block_h) as u64;
+            w * h * block_size * layers
+        })
+        .sum()
+}
+
+// Scales each RGBA8 texel's color by its alpha. sRGB texels are premultiplied in linear
+// space and encoded again.
+pub fn premultiply_alpha(pixels: &mut [u8], srgb: bool) {
+    let decode: Vec<f32> = (0..256)
+        .map(|v| {
+            let c = v as f32 / 255.0;
+            if !srgb {
+                c
+            } else if c <= 0.04045 {
+                c / 12.92
+            } else {
+                ((c + 0.055) / 1.055).powf(2.4)
+            }
+        })
+        .collect();
+    let encode = |c: f32| {
+        let c = if !srgb {
+            c
+        } else if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        };
+        (c.clamp(0.0, 1.0) * 255.0).round() as u8
+    };
+
+    for texel in pixels.chunks_exact_mut(4) {
+        let alpha = texel[3];
+        if alpha == 255 {
+            continue;
+        }
+        let a = alpha as f32 / 255.0;
+        for c in &mut texel[..3] {
+            *c = encode(decode[*c as usize] * a);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_manager::test_support;
+    use crate::render::offscreen::read_rows;
+
+    // First texel of a 2x2 RGBA8 texture.
+    fn first_texel(assets: &AssetManager, id: TextureId) -> Vec<u8> {
+        let tex = &assets.textures[id].tex;
+        let padded = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = assets.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: padded as u64 * 2,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = assets
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        encoder.copy_texture_to_buffer(
+            tex.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded),
+                    rows_per_image: Some(2),
+                },
+            },
+            tex.size(),
+        );
+        assets.queue.submit(Some(encoder.finish()));
+        read_rows(&assets.device, &buffer, padded, 8).unwrap()[..4].to_vec()
+    }
+
+    #[test]
+    fn reload_texture_keeps_id_and_replaces_contents() {
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        let dir = test_support::temp_dir("reload-texture");
+        let path = test_support::write_texture_gltf(&dir, [255, 0, 0, 255]);
+
+        // a data slot, so the texels read back exactly as written
+        let id = assets
+            .get_texture(&format!("{}#0", path), TextureSlot::Normal)
+            .unwrap();
+        assert_eq!(first_texel(&assets, id), [255, 0, 0, 255]);
+
+        test_support::write_png(&dir, [0, 0, 255, 255]);
+        assets.reload_texture(id).unwrap();
+        assert!(assets.textures.contains_key(id));
+        assert_eq!(first_texel(&assets, id), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn reload_texture_reports_a_missing_file() {
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        let dir = test_support::temp_dir("reload-missing");
+        let path = test_support::write_texture_gltf(&dir, [255, 255, 255, 255]);
+        let id = assets
+            .get_texture(&format!("{}#0", path), TextureSlot::Normal)
+            .unwrap();
+
+        std::fs::remove_file(dir.join("image.png")).unwrap();
+        assert!(matches!(
+            assets.reload_texture(id),
+            Err(AssetError::LoadFailed { .. })
+        ));
+    }
+}
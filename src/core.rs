@@ -1,2 +1,3 @@
 pub mod component;
 pub mod system;
+pub mod time;
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+
+use glam::Mat4;
+use hecs::{Entity, World};
+
+use super::component::{Parent, Transform};
+use crate::{
+    asset_manager::{MeshId, light::Light},
+    render::{Camera, ForwardRenderer, RenderCommand, ScissorRect},
+};
+
+#[derive(Debug)]
+pub enum HierarchyError {
+    // the entity is its own ancestor
+    Cycle(Entity),
+    // the entity's Parent was despawned
+    MissingParent { entity: Entity, parent: Entity },
+}
+
+impl std::fmt::Display for HierarchyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HierarchyError::Cycle(entity) => {
+                write!(f, "entity {:?} is part of a parent cycle", entity)
+            }
+            HierarchyError::MissingParent { entity, parent } => {
+                write!(f, "entity {:?} has a missing parent {:?}", entity, parent)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HierarchyError {}
+
+// The `Transform` component, or a raw `Mat4` component when there is no `Transform`.
+pub fn local_transforms(world: &World) -> HashMap<Entity, Mat4> {
+    world
+        .query::<(Option<&Transform>, Option<&Mat4>)>()
+        .iter()
+        .filter_map(|(entity, (transform, matrix))| {
+            let local = transform.map(Transform::matrix).or(matrix.copied())?;
+            Some((entity, local))
+        })
+        .collect()
+}
+
+// Resolves the world transform of every entity with a local transform or a `Parent`: its
+// parent's world transform times its own local one, over any number of levels. Entities
+// without a local transform count as identity.
+pub fn world_transforms(world: &World) -> Result<HashMap<Entity, Mat4>, HierarchyError> {
+    let locals = local_transforms(world);
+    let parents: HashMap<Entity, Entity> = world
+        .query::<&Parent>()
+        .iter()
+        .map(|(entity, parent)| (entity, parent.0))
+        .collect();
+
+    let mut resolved: HashMap<Entity, Mat4> = HashMap::with_capacity(locals.len());
+    let mut chain = Vec::new();
+    for &entity in locals.keys().chain(parents.keys()) {
+        // walk up to a resolved ancestor or a root, then resolve back down the chain
+        chain.clear();
+        let mut cur = entity;
+        let mut base = Mat4::IDENTITY;
+        loop {
+            if let Some(&world_transform) = resolved.get(&cur) {
+                base = world_transform;
+                break;
+            }
+            if chain.contains(&cur) {
+                return Err(HierarchyError::Cycle(cur));
+            }
+            chain.push(cur);
+            let Some(&parent) = parents.get(&cur) else {
+                break;
+            };
+            if !world.contains(parent) {
+                return Err(HierarchyError::MissingParent {
+                    entity: cur,
+                    parent,
+                });
+            }
+            cur = parent;
+        }
+        for &e in chain.iter().rev() {
+            base *= locals.get(&e).copied().unwrap_or(Mat4::IDENTITY);
+            resolved.insert(e, base);
+        }
+    }
+    Ok(resolved)
+}
+
+// Draws every entity with a `MeshId` at its world transform (see world_transforms), clipped to
+// its `ScissorRect` component if it has one. A broken hierarchy is logged and every entity is
+// drawn at its local transform instead. All transforms are uploaded in one go by `render`.
+pub fn render_world(world: &World, renderer: &mut ForwardRenderer, lights: &[Light], cam: &Camera) {
+    let transforms = world_transforms(world).unwrap_or_else(|e| {
+        eprintln!("render_world: {}; ignoring parents", e);
+        local_transforms(world)
+    });
+
+    let commands: Vec<RenderCommand> = world
+        .query::<(&MeshId, Option<&ScissorRect>)>()
+        .iter()
+        .map(|(entity, (mesh_id, scissor))| RenderCommand {
+            mesh_id: *mesh_id,
+            transform: transforms.get(&entity).copied().unwrap_or(Mat4::IDENTITY),
+            scissor: scissor.copied(),
+            ..Default::default()
+        })
+        .collect();
+
+    renderer.render(lights, cam, &commands);
+}
@@ -0,0 +1,26 @@
+// Frame clock for games, ticked by App before every update.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Time {
+    // seconds since the first tick
+    pub elapsed: f64,
+    // seconds since the previous tick, 0 on the first
+    pub delta: f32,
+    pub frame: u64,
+    // timestamp of the previous tick, see render::stats::now_seconds
+    pub last: Option<f64>,
+}
+
+impl Time {
+    // `now` is in seconds from any fixed point. A clock going backwards counts as no time.
+    pub fn tick(&mut self, now: f64) {
+        let delta = self.last.map_or(0.0, |last| (now - last).max(0.0));
+        self.elapsed += delta;
+        self.delta = delta as f32;
+        self.frame += 1;
+        self.last = Some(now);
+    }
+
+    pub fn elapsed_seconds(&self) -> f32 {
+        self.elapsed as f32
+    }
+}
@@ -1,19 +1,71 @@
 use hecs::World;
+use winit::{
+    dpi::PhysicalSize,
+    error::ExternalError,
+    window::{CursorGrabMode, Window},
+};
 
 use crate::{
     asset_manager::light::{Light, LightKind},
+    core::time::Time,
     render::{Camera, ForwardRenderer, RenderCommand},
 };
 
 pub trait Game {
     fn setup(&mut self, _world: &mut World, _renderer: &mut ForwardRenderer) {}
-    fn update(&mut self, _world: &mut World, _renderer: &mut ForwardRenderer) {}
+    // `window` is the game's window, for size, scale factor and cursor control. `time` has
+    // already been ticked for this frame.
+    fn update(
+        &mut self,
+        _world: &mut World,
+        _renderer: &mut ForwardRenderer,
+        _window: &dyn WindowHandle,
+        _time: &Time,
+    ) {
+    }
+}
+
+// What Game::update can query and control on its window. App passes the winit Window;
+// tests drive App with a stand-in.
+pub trait WindowHandle {
+    fn inner_size(&self) -> PhysicalSize<u32>;
+    fn scale_factor(&self) -> f64;
+    fn set_cursor_visible(&self, visible: bool);
+    fn set_cursor_grab(&self, mode: CursorGrabMode) -> Result<(), ExternalError>;
+    // the winit window itself, for anything not covered above
+    fn window(&self) -> Option<&Window> {
+        None
+    }
+}
+
+impl WindowHandle for Window {
+    fn inner_size(&self) -> PhysicalSize<u32> {
+        Window::inner_size(self)
+    }
+    fn scale_factor(&self) -> f64 {
+        Window::scale_factor(self)
+    }
+    fn set_cursor_visible(&self, visible: bool) {
+        Window::set_cursor_visible(self, visible)
+    }
+    fn set_cursor_grab(&self, mode: CursorGrabMode) -> Result<(), ExternalError> {
+        Window::set_cursor_grab(self, mode)
+    }
+    fn window(&self) -> Option<&Window> {
+        Some(self)
+    }
 }
 impl Game for () {
     fn setup(&mut self, _world: &mut World, _renderer: &mut ForwardRenderer) {}
-    fn update(&mut self, _world: &mut World, renderer: &mut ForwardRenderer) {
+    fn update(
+        &mut self,
+        _world: &mut World,
+        renderer: &mut ForwardRenderer,
+        _window: &dyn WindowHandle,
+        _time: &Time,
+    ) {
         let asset = &mut renderer.asset;
-        let mesh_id = asset.get_mesh("meshes/sphere.glb#0");
+        let mesh_id = asset.get_mesh_or_error("meshes/sphere.glb#0");
 
         let spotlight = Light {
             kind: LightKind::Spot,
@@ -36,6 +88,13 @@ impl Game for () {
             aspect: 16.0 / 9.0,
         };
 
-        renderer.render(&[spotlight], &cam, &[RenderCommand { mesh_id }]);
+        renderer.render(
+            &[spotlight],
+            &cam,
+            &[RenderCommand {
+                mesh_id,
+                ..Default::default()
+            }],
+        );
     }
 }
@@ -2,4 +2,7 @@ pub mod app;
 pub mod asset_manager;
 pub mod core;
 pub mod game;
+pub mod prelude;
 pub mod render;
+
+pub use app::{WindowConfig, run};
@@ -0,0 +1,10 @@
+// Commonly used types, for `use engine::prelude::*`.
+pub use crate::app::{WindowConfig, run};
+pub use crate::asset_manager::light::{Light, LightKind};
+pub use crate::asset_manager::mesh::{Index, Mesh, Primitive, Vertex};
+pub use crate::asset_manager::{AssetManager, MeshId};
+pub use crate::core::component::{Parent, Transform};
+pub use crate::core::time::Time;
+pub use crate::game::{Game, WindowHandle};
+pub use crate::render::{Camera, ForwardRenderer, RenderCommand, ScissorRect};
+pub use winit::window::Window;
@@ -1,32 +1,104 @@
 use anyhow::Result;
-use glam::{Mat4, Vec3};
+use glam::{Mat4, Vec3, Vec4};
+use std::collections::HashMap;
 use std::num::NonZeroU64;
 use std::sync::Arc;
 use wgpu::StoreOp;
 use wgpu::util::DeviceExt;
 use winit::window::Window;
 
-use gpu::GpuContext;
+use bindless::{BindlessTextures, TextureGroupCache};
+use bundles::StaticBundle;
+use capture::FrameCapture;
+use clusters::{ClusterConfig, LightClusters};
+use cookies::CookieAtlas;
+use fog::Fog;
+use frustum::transform_aabb;
+use gpu::{ColorSpace, GpuConfig, GpuContext};
+use gpu_cull::GpuCuller;
+use grid::GroundGrid;
+use lines::LineRenderer;
+use normals::NormalCompute;
+use objects::ObjectBuffer;
+use picking::IdTarget;
+use pipeline_cache::PipelineCacheStore;
+use shadow::{DEFAULT_MAX_SHADOW_CASTERS, PointShadowMaps};
+use stats::{FrameTimer, RenderStats};
 
 use crate::asset_manager::AssetManager;
 use crate::asset_manager::MeshId;
 use crate::asset_manager::light::{Light, LightParams, LightUniform, MAX_LIGHTS};
 use crate::asset_manager::material::MAX_MAT;
-use crate::asset_manager::material::MatId;
-use crate::asset_manager::mesh::{Mesh, Vertex};
+use crate::asset_manager::material::{MatId, MaterialFeatures};
+use crate::asset_manager::mesh::{MAX_OBJECTS, ObjectUniform, Vertex};
+use crate::asset_manager::packing::VertexFormat;
 
 const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+// forward pass depth test; depth is cleared to 1.0, so nearer is smaller
+pub const DEPTH_COMPARE: wgpu::CompareFunction = wgpu::CompareFunction::Less;
 
+// The far plane, which the forward pass clears depth to unless set_depth_clear overrides it.
+pub fn depth_clear_value(reverse_z: bool) -> f32 {
+    if reverse_z { 0.0 } else { 1.0 }
+}
+
+// `compare` as written for standard depth, mirrored for reverse-z where nearer is larger.
+pub fn depth_compare(compare: wgpu::CompareFunction, reverse_z: bool) -> wgpu::CompareFunction {
+    use wgpu::CompareFunction::*;
+    match (compare, reverse_z) {
+        (Less, true) => Greater,
+        (LessEqual, true) => GreaterEqual,
+        (Greater, true) => Less,
+        (GreaterEqual, true) => LessEqual,
+        (compare, _) => compare,
+    }
+}
+
+// Applied after a camera's projection: clip depth z becomes w - z, so the near plane lands on
+// 1.0 and the far plane on 0.0. Identity without reverse-z.
+pub fn reverse_z_clip(reverse_z: bool) -> Mat4 {
+    if !reverse_z {
+        return Mat4::IDENTITY;
+    }
+    Mat4::from_cols(Vec4::X, Vec4::Y, Vec4::NEG_Z, Vec4::new(0.0, 0.0, 1.0, 1.0))
+}
+// stages that can read the lights and light params; add VERTEX for per-vertex lighting
+pub const LIGHT_VISIBILITY: wgpu::ShaderStages = wgpu::ShaderStages::FRAGMENT;
+
+// world_pos_from_depth for effects that read the depth target; append it to their source
+pub const DEPTH_WGSL: &str = include_str!("../shaders/depth.wgsl");
+
+pub mod bindless;
+pub mod bundles;
+pub mod capture;
+pub mod clusters;
+pub mod cookies;
+pub mod fog;
+pub mod frustum;
 pub mod gpu;
+pub mod gpu_cull;
+pub mod grid;
+pub mod lines;
+pub mod normals;
+pub mod objects;
+pub mod offscreen;
+pub mod picking;
+pub mod pipeline_cache;
+pub mod shader_defs;
+pub mod shadow;
+pub mod smooth_camera;
+pub mod stats;
+pub mod streaming;
 
 //pub struct RenderResource(wgpu::Buffer, wgpu::BindGroupLayout, wgpu::BindGroupLayout);
 
 #[repr(C)]
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Debug)]
 pub struct CameraUniform {
-    pub view_proj: [[f32; 4]; 4], // 64 bytes
-    pub camera_pos: [f32; 3],     // 12 bytes
-    pub _pad0: f32,               // 4 bytes padding -> align to 16
+    pub view_proj: [[f32; 4]; 4],     // 64 bytes
+    pub camera_pos: [f32; 3],         // 12 bytes
+    pub _pad0: f32,                   // 4 bytes padding -> align to 16
+    pub inv_view_proj: [[f32; 4]; 4], // 64 bytes, for world_pos_from_depth
 }
 
 impl CameraUniform {
@@ -35,6 +107,7 @@ impl CameraUniform {
             view_proj: glam::Mat4::IDENTITY.to_cols_array_2d(),
             camera_pos: [0.0, 0.0, 0.0],
             _pad0: 0.0,
+            inv_view_proj: glam::Mat4::IDENTITY.to_cols_array_2d(),
         }
     }
 }
@@ -51,12 +124,93 @@ pub struct Camera {
 }
 
 impl Camera {
+    // A camera whose eye sits on its target, or whose up is parallel to the view direction,
+    // has no defined orientation; it looks down -Z (with Y or Z up) instead of producing NaNs.
+    pub fn view(&self) -> Mat4 {
+        let mut dir = self.target - self.eye;
+        let mut up = self.up;
+        if dir.length_squared() < 1e-12 || !dir.is_finite() {
+            warn_degenerate_camera("eye == target");
+            dir = Vec3::NEG_Z;
+        }
+        if up.cross(dir).length_squared() < 1e-12 * dir.length_squared() {
+            warn_degenerate_camera("up is parallel to the view direction");
+            up = if dir.normalize().y.abs() < 0.99 {
+                Vec3::Y
+            } else {
+                Vec3::Z
+            };
+        }
+        // look_to_rh expects a unit direction; a longer one scales view-space depth
+        Mat4::look_to_rh(self.eye, dir.normalize(), up)
+    }
+
     pub fn view_proj(&self) -> Mat4 {
-        let view = Mat4::look_at_rh(self.eye, self.target, self.up);
+        let view = self.view();
         let proj = Mat4::perspective_rh(self.fov_y_radians, self.aspect, self.z_near, self.z_far);
         proj * view
     }
+
+    // World-space corners of the frustum between the view distances `near` and `far`, e.g.
+    // to fit a shadow cascade to a slice. Near plane first, each plane ordered
+    // bottom-left, bottom-right, top-right, top-left.
+    pub fn frustum_corners(&self, near: f32, far: f32) -> [Vec3; 8] {
+        let view = self.view();
+        let proj = Mat4::perspective_rh(self.fov_y_radians, self.aspect, near, far);
+        let inv = (proj * view).inverse();
+
+        let mut corners = [Vec3::ZERO; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let z = if i < 4 { 0.0 } else { 1.0 };
+            let (x, y) = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)][i % 4];
+            *corner = inv.project_point3(Vec3::new(x, y, z));
+        }
+        corners
+    }
+
+    // Swings `eye` around `target` at a fixed distance: `yaw_delta` radians about `up`
+    // (counter-clockwise seen from above), `pitch_delta` radians up or down, keeping the eye
+    // within ORBIT_MAX_PITCH of the horizon so it never crosses over the pole.
+    pub fn orbit(&mut self, yaw_delta: f32, pitch_delta: f32) {
+        let up = self.up.try_normalize().unwrap_or(Vec3::Y);
+        let offset = self.eye - self.target;
+        let distance = offset.length();
+        let Some(dir) = offset.try_normalize() else {
+            return;
+        };
+
+        let pitch = dir.dot(up).clamp(-1.0, 1.0).asin();
+        let new_pitch = (pitch + pitch_delta).clamp(-ORBIT_MAX_PITCH, ORBIT_MAX_PITCH);
+        let flat = (dir - up * dir.dot(up))
+            .try_normalize()
+            .unwrap_or_else(|| up.any_orthonormal_vector());
+        let flat = glam::Quat::from_axis_angle(up, yaw_delta) * flat;
+
+        let dir = flat * new_pitch.cos() + up * new_pitch.sin();
+        self.eye = self.target + dir * distance;
+    }
+}
+
+// Just short of straight up or down, where the view's up vector would degenerate.
+pub const ORBIT_MAX_PITCH: f32 = 89.0 * std::f32::consts::PI / 180.0;
+
+// Reported once per run; a controller bug would otherwise print every frame.
+fn warn_degenerate_camera(reason: &str) {
+    static WARNED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+    if !WARNED.swap(true, std::sync::atomic::Ordering::Relaxed) {
+        eprintln!("camera: {}; using a fallback orientation", reason);
+    }
+}
+
+// A command naming a removed or never registered mesh is skipped; reported once so a stale id
+// doesn't flood the log every frame.
+fn warn_missing_mesh(mesh_id: MeshId) {
+    static WARNED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+    if !WARNED.swap(true, std::sync::atomic::Ordering::Relaxed) {
+        eprintln!("render: skipping draws of unknown mesh {:?}", mesh_id);
+    }
 }
+
 impl Default for Camera {
     fn default() -> Self {
         Self {
@@ -71,8 +225,60 @@ impl Default for Camera {
     }
 }
 
+// Renderer-wide state baked into every forward pipeline variant; changing it recompiles them.
+#[derive(Clone, Copy, Debug)]
+pub struct PipelineConfig {
+    pub format: wgpu::TextureFormat,
+    pub bindless: bool,
+    pub cull_mode: Option<wgpu::Face>,
+    pub front_face: wgpu::FrontFace,
+    pub color_space: ColorSpace,
+    pub depth_bias: wgpu::DepthBiasState,
+    pub reverse_z: bool,
+}
+
+// Identifies one forward pipeline variant.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub features: MaterialFeatures,
+    pub topology: wgpu::PrimitiveTopology,
+    pub vertex_format: VertexFormat,
+}
+
+#[derive(Clone, Copy, Default)]
 pub struct RenderCommand {
     pub mesh_id: MeshId,
+    pub transform: Mat4,
+    // pixels outside the rect are left untouched; None draws to the whole target.
+    // Ignored for static bundles and the shadow maps.
+    pub scissor: Option<ScissorRect>,
+    // draws are stably sorted by this before rendering, lowest first; equal keys keep
+    // their order in the command list. Static bundles are drawn in record order.
+    pub sort_key: u32,
+}
+
+// In pixels, from the top left corner of the render target.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ScissorRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ScissorRect {
+    // Sets `rect` clipped to a `size` target, or the whole target for None.
+    pub fn apply(rect: Option<Self>, rpass: &mut wgpu::RenderPass, size: [u32; 2]) {
+        let [w, h] = size;
+        match rect {
+            Some(r) => {
+                let x = r.x.min(w);
+                let y = r.y.min(h);
+                rpass.set_scissor_rect(x, y, r.width.min(w - x), r.height.min(h - y));
+            }
+            None => rpass.set_scissor_rect(0, 0, w, h),
+        }
+    }
 }
 
 pub struct Command {
@@ -83,7 +289,13 @@ pub struct Command {
 pub struct ForwardRenderer {
     pub context: gpu::GpuContext,
     pub asset: AssetManager,
-    pub pipeline: wgpu::RenderPipeline,
+    pub pipeline_layout: wgpu::PipelineLayout,
+    // forward pipeline variants, compiled on first use
+    pub pipelines: HashMap<PipelineKey, wgpu::RenderPipeline>,
+    pub pipeline_config: PipelineConfig,
+    // replaces forward.wgsl in every variant, see set_shader
+    pub forward_shader: Option<String>,
+    pub pipeline_cache: PipelineCacheStore,
     pub camera_buffer: wgpu::Buffer,
 
     pub camera: Camera,
@@ -96,6 +308,8 @@ pub struct ForwardRenderer {
 
     pub depth_tex: wgpu::Texture,
     pub depth_view: wgpu::TextureView,
+    // what the forward pass clears depth_view to
+    pub depth_clear: f32,
 
     pub mat_bg: wgpu::BindGroup,
     pub mat_bgl: wgpu::BindGroupLayout,
@@ -105,12 +319,60 @@ pub struct ForwardRenderer {
     pub mat_id_bg: wgpu::BindGroup,
 
     pub tex_bgl: wgpu::BindGroupLayout,
+    pub bindless: Option<BindlessTextures>,
+    // group 1 per material when bindless is None
+    pub tex_groups: TextureGroupCache,
+
+    pub shadows: PointShadowMaps,
+    pub objects: ObjectBuffer,
+    pub normals: NormalCompute,
+
+    pub fog: Option<Fog>,
+    pub fog_buffer: wgpu::Buffer,
+    pub cookies: CookieAtlas,
+    pub clusters: LightClusters,
+    // created by the first render_instanced_gpu_culled
+    pub gpu_cull: Option<GpuCuller>,
+
+    pub grid: Option<GroundGrid>,
+    pub lines: LineRenderer,
+
+    // replayed at the start of every forward pass
+    pub static_bundles: Vec<StaticBundle>,
+
+    pub stats: RenderStats,
+    pub frames: FrameTimer,
+
+    // id target for pick(); None while picking is disabled
+    pub picking: Option<IdTarget>,
+
+    // set by capture_frame; the next render copies its frame into `capture`
+    pub capture_requested: bool,
+    pub capture: Option<FrameCapture>,
+
+    pub stream_belt: wgpu::util::StagingBelt,
+    // copies recorded by stream_mesh, submitted ahead of the next frame
+    pub stream_encoder: Option<wgpu::CommandEncoder>,
 }
 
 impl ForwardRenderer {
     pub async fn new(window: &Arc<Window>) -> Result<Self> {
-        let ctx = GpuContext::new(window).await?;
+        Self::with_config(window, GpuConfig::default()).await
+    }
+
+    pub async fn with_config(window: &Arc<Window>, gpu_config: GpuConfig) -> Result<Self> {
+        let ctx = GpuContext::new(window, &gpu_config).await?;
+        Self::from_context(ctx)
+    }
 
+    // A renderer without a window, e.g. for tests and tools: render draws into a scratch
+    // `width` x `height` frame that is never presented, and render_to works as usual.
+    pub async fn headless(width: u32, height: u32, gpu_config: GpuConfig) -> Result<Self> {
+        let ctx = GpuContext::headless(width, height, &gpu_config).await?;
+        Self::from_context(ctx)
+    }
+
+    pub fn from_context(ctx: GpuContext) -> Result<Self> {
         let asset = AssetManager::new(ctx.device.clone(), ctx.queue.clone());
 
         // asset
@@ -142,90 +404,96 @@ impl ForwardRenderer {
 
         //let (camera_buffer, camera_bgl, camera_bg) = Self::create_camera(&ctx.device);
         //let (light_ssbo, light_params, light_bgl, light_bg) =
-        //    Self::create_light(&ctx.device, MAX_LIGHTS);
+        //    Self::create_light(&ctx.device, MAX_LIGHTS, LIGHT_VISIBILITY);
+
+        let objects = ObjectBuffer::new(&ctx.device, MAX_OBJECTS);
+        let normals = NormalCompute::new(&ctx.device);
+
+        let shadows = PointShadowMaps::new(
+            &ctx.device,
+            Vertex::buffer_layout(),
+            &objects.bgl,
+            DEFAULT_MAX_SHADOW_CASTERS,
+        );
+
+        let fog_buffer = fog::create_fog_buffer(&ctx.device);
+        let cookies = CookieAtlas::new(&ctx.device);
+        let clusters = LightClusters::new(&ctx.device, ClusterConfig::default());
 
         let (camera_buffer, light_ssbo, light_params, scene_bgl, scene_bg) =
-            Self::create_scene_bindings(&ctx.device, MAX_LIGHTS);
+            Self::create_scene_bindings(
+                &ctx.device,
+                MAX_LIGHTS,
+                LIGHT_VISIBILITY,
+                &shadows,
+                &objects,
+                &fog_buffer,
+                &cookies,
+                &clusters,
+            );
 
         let (mat_id_buffer, mat_id_bgl, mat_id_bg) =
             Self::create_material_id(&ctx.device, &ctx.queue, MAX_MAT);
 
         let tex_bgl = Self::create_tex(&ctx.device);
+        let bindless = ctx
+            .bindless
+            .then(|| BindlessTextures::new(&ctx.device, &asset));
 
-        let depth_tex = ctx.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Depth"),
-            size: wgpu::Extent3d {
-                width: ctx.config.width,
-                height: ctx.config.height,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            view_formats: &[],
-        });
-        let depth_view = depth_tex.create_view(&wgpu::TextureViewDescriptor::default());
+        let (depth_tex, depth_view) =
+            Self::create_depth_target(&ctx.device, ctx.config.width, ctx.config.height);
 
-        let pipeline = {
-            let shader = ctx
-                .device
-                .create_shader_module(wgpu::ShaderModuleDescriptor {
-                    label: Some("Forward Shader"),
-                    source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
-                        "../shaders/forward.wgsl"
-                    ))),
-                });
-
-            let vertex_layout = Vertex::buffer_layout();
-
-            let pipeline_layout =
-                ctx.device
-                    .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: Some("Forward Pipeline Layout"),
-                        bind_group_layouts: &[&scene_bgl, &tex_bgl, &mat_bgl, &mat_id_bgl],
-                        push_constant_ranges: &[],
-                    });
-
-            ctx.device
-                .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                    label: Some("Forward Pipeline"),
-                    layout: Some(&pipeline_layout),
-                    vertex: wgpu::VertexState {
-                        module: &shader,
-                        entry_point: Some("vs_main"),
-                        buffers: &[vertex_layout],
-                        compilation_options: wgpu::PipelineCompilationOptions::default(),
-                    },
-                    fragment: Some(wgpu::FragmentState {
-                        module: &shader,
-                        entry_point: Some("fs_main"),
-                        targets: &[Some(wgpu::ColorTargetState {
-                            format: ctx.config.format,
-                            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                            write_mask: wgpu::ColorWrites::ALL,
-                        })],
-                        compilation_options: wgpu::PipelineCompilationOptions::default(),
-                    }),
-                    primitive: wgpu::PrimitiveState::default(),
-                    depth_stencil: Some(wgpu::DepthStencilState {
-                        format: DEPTH_FORMAT,
-                        depth_write_enabled: true,
-                        depth_compare: wgpu::CompareFunction::Less,
-                        stencil: wgpu::StencilState::default(),
-                        bias: wgpu::DepthBiasState::default(),
-                    }),
-                    multisample: wgpu::MultisampleState::default(),
-                    multiview: None,
-                    cache: None,
-                })
+        let texture_bgl = bindless.as_ref().map(|b| &b.bgl).unwrap_or(&tex_bgl);
+        let pipeline_layout = ctx
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Forward Pipeline Layout"),
+                bind_group_layouts: &[&scene_bgl, texture_bgl, &mat_bgl, &mat_id_bgl],
+                push_constant_ranges: &[],
+            });
+
+        let pipeline_cache = PipelineCacheStore::load(
+            &ctx.device,
+            pipeline_cache::default_cache_path(&ctx.adapter_info),
+        );
+
+        let pipeline_config = PipelineConfig {
+            format: ctx.view_format,
+            bindless: bindless.is_some(),
+            cull_mode: None,
+            front_face: wgpu::FrontFace::Ccw,
+            color_space: ColorSpace::Srgb,
+            depth_bias: wgpu::DepthBiasState::default(),
+            reverse_z: false,
         };
 
+        let mut lines = LineRenderer::new(&ctx.device, ctx.view_format);
+        lines.scale_factor = ctx.scale_factor as f32;
+
+        let mut pipelines = HashMap::new();
+        pipelines.insert(
+            PipelineKey::default(),
+            Self::create_forward_pipeline(
+                &ctx.device,
+                &pipeline_layout,
+                pipeline_cache.cache.as_ref(),
+                &pipeline_config,
+                None,
+                PipelineKey::default(),
+            )?,
+        );
+        if let Err(e) = pipeline_cache.save() {
+            eprintln!("gpu: {:#}", e);
+        }
+
         Ok(Self {
             context: ctx,
             asset,
-            pipeline,
+            pipeline_layout,
+            pipelines,
+            pipeline_config,
+            forward_shader: None,
+            pipeline_cache,
             camera_buffer,
             camera: Camera {
                 eye: Vec3::new(0.0, 0.0, 5.0),
@@ -242,20 +510,73 @@ impl ForwardRenderer {
             scene_bg,
             depth_tex,
             depth_view,
+            depth_clear: depth_clear_value(pipeline_config.reverse_z),
             mat_bg,
             mat_bgl,
             mat_id_buffer,
             mat_id_bgl,
             mat_id_bg,
             tex_bgl,
+            bindless,
+            tex_groups: TextureGroupCache::default(),
+            shadows,
+            objects,
+            normals,
+            fog: None,
+            fog_buffer,
+            cookies,
+            clusters,
+            gpu_cull: None,
+            grid: None,
+            lines,
+            static_bundles: Vec::new(),
+            stats: RenderStats::default(),
+            frames: FrameTimer::default(),
+            picking: None,
+            capture_requested: false,
+            capture: None,
+            stream_belt: wgpu::util::StagingBelt::new(streaming::STREAM_CHUNK_SIZE),
+            stream_encoder: None,
         })
     }
-    pub fn render(&mut self, lights: &[Light], cam: &Camera, action: &[RenderCommand]) {
-        self.camera = cam.clone();
-        self.update_camera_buffer();
+    // Returns the frame's submission, or None when no surface texture could be acquired.
+    pub fn render(
+        &mut self,
+        lights: &[Light],
+        cam: &Camera,
+        action: &[RenderCommand],
+    ) -> Option<wgpu::SubmissionIndex> {
+        self.render_with(lights, cam, action, |_, _, _| {})
+    }
+
+    // Like render, but `post_render` can record its own passes (e.g. a UI overlay) into the
+    // frame's encoder after the forward pass, drawing into the surface view. It runs before
+    // the frame is submitted and presented, and not at all when no frame was acquired.
+    pub fn render_with(
+        &mut self,
+        lights: &[Light],
+        cam: &Camera,
+        action: &[RenderCommand],
+        post_render: impl FnOnce(&GpuContext, &mut wgpu::CommandEncoder, &wgpu::TextureView),
+    ) -> Option<wgpu::SubmissionIndex> {
+        let commands = action;
+        let order = objects::sort_order(action);
+        let sorted: Vec<RenderCommand>;
+        let action = match &order {
+            Some(order) => {
+                sorted = order.iter().map(|&i| action[i]).collect();
+                &sorted[..]
+            }
+            None => action,
+        };
+
+        let (stream_commands, casters) = self.prepare_frame(lights, cam, action);
+        if let Some(picking) = self.picking.as_mut() {
+            picking.meshes = commands.iter().map(|c| c.mesh_id).collect();
+            picking.order = order.unwrap_or_default();
+        }
 
         let ctx = &self.context;
-        let device = &ctx.device;
         let queue = &ctx.queue;
 
         //let vp = cam.view_proj();
@@ -264,12 +585,13 @@ impl ForwardRenderer {
         //};
         //queue.write_buffer(&self.camera_buffer, 0, bytemuck::bytes_of(&cu));
 
-        let frame = match ctx.surface.get_current_texture() {
-            Ok(f) => f,
-            Err(err) => {
+        let surface_frame = match ctx.surface.as_ref().map(|s| s.get_current_texture()) {
+            None => None,
+            Some(Ok(f)) => Some(f),
+            Some(Err(err)) => {
                 match err {
                     wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => {
-                        ctx.surface.configure(&ctx.device, &ctx.config);
+                        ctx.reconfigure();
                     }
                     wgpu::SurfaceError::OutOfMemory => {
                         eprintln!("wgpu: OutOfMemory on surface get_current_texture");
@@ -282,15 +604,83 @@ impl ForwardRenderer {
                         eprintln!("wgpu: surface acquire error; skipping frame");
                     }
                 }
-                return;
+                // streamed mesh copies still have to land
+                queue.submit(stream_commands);
+                self.stream_belt.recall();
+                return None;
             }
         };
 
-        let color_view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let texture = match &surface_frame {
+            Some(frame) => frame.texture.clone(),
+            None => ctx.create_headless_frame(),
+        };
+        let color_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(ctx.view_format),
+            ..Default::default()
+        });
+
+        let viewport = [ctx.config.width as f32, ctx.config.height as f32];
+        let line_count = self.lines.upload(&ctx.device, queue, cam, viewport);
+
+        let mut encoder = ctx
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Forward Encoder"),
+            });
+
+        self.stats = self.encode_frame(
+            &mut encoder,
+            lights,
+            &casters,
+            action,
+            &color_view,
+            &self.depth_view,
+            [ctx.config.width, ctx.config.height],
+            line_count,
+        );
+        self.render_ids(&mut encoder, action);
+        post_render(&self.context, &mut encoder, &color_view);
+        self.copy_frame(&mut encoder, &texture);
+
+        let queue = &self.context.queue;
+        let index = queue.submit(stream_commands.into_iter().chain(Some(encoder.finish())));
+        if let Some(frame) = surface_frame {
+            frame.present();
+        }
+        self.frames.tick(stats::now_seconds());
+        self.stream_belt.recall();
+        self.lines.pending.clear();
+        Some(index)
+    }
+
+    // Per-frame uploads shared by every render target: camera, materials, objects, lights.
+    // Returns the pending mesh stream commands and the shadow casters.
+    pub fn prepare_frame(
+        &mut self,
+        lights: &[Light],
+        cam: &Camera,
+        action: &[RenderCommand],
+    ) -> (Option<wgpu::CommandBuffer>, Vec<usize>) {
+        self.set_camera(cam);
+        if let Some(bindless) = self.bindless.as_mut() {
+            bindless.refresh(&self.context.device, &self.asset);
+        } else {
+            self.cache_texture_groups(action);
+        }
+        self.asset.flush_materials();
+        self.stats = RenderStats::default();
+        self.upload_objects(&lights[..lights.len().min(MAX_LIGHTS)], action);
+        self.ensure_pipelines_for(action);
+        let stream_commands = self.take_stream_commands();
+        let cookie_indices = self.cookie_indices(&lights[..lights.len().min(MAX_LIGHTS)]);
+
+        let queue = &self.context.queue;
 
-        let depth_view = &self.depth_view;
+        let casters = shadow::shadow_casters(
+            &lights[..lights.len().min(MAX_LIGHTS)],
+            self.shadows.max_casters,
+        );
 
         // upload lights
         {
@@ -302,6 +692,12 @@ impl ForwardRenderer {
             for l in lights.iter().take(count) {
                 tmp.push(l.into());
             }
+            for (slot, &i) in casters.iter().enumerate() {
+                tmp[i].shadow_index = slot as i32;
+            }
+            for (l, &cookie) in tmp.iter_mut().zip(&cookie_indices) {
+                l.cookie_index = cookie;
+            }
 
             if count > 0 {
                 queue.write_buffer(light_buf, 0, bytemuck::cast_slice(&tmp));
@@ -314,15 +710,40 @@ impl ForwardRenderer {
             queue.write_buffer(params_buf, 0, bytemuck::bytes_of(&params));
         }
 
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Forward Encoder"),
-        });
+        self.clusters.update(queue, cam);
+        if let Some(grid) = &self.grid {
+            grid.update(queue, cam);
+        }
+
+        (stream_commands, casters)
+    }
 
+    // Records the shadow and forward passes into `color_view`/`depth_view`. Both must have
+    // the same size; the color view's format must be the pipeline format.
+    #[allow(clippy::too_many_arguments)]
+    pub fn encode_frame(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        lights: &[Light],
+        casters: &[usize],
+        action: &[RenderCommand],
+        color_view: &wgpu::TextureView,
+        depth_view: &wgpu::TextureView,
+        target_size: [u32; 2],
+        line_count: u32,
+    ) -> RenderStats {
+        let device = &self.context.device;
+
+        self.render_point_shadows(encoder, lights, casters, action);
+        self.clusters
+            .dispatch(device, encoder, &self.light_ssbo, &self.light_params);
+
+        let mut stats = RenderStats::default();
         {
             let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Forward Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &color_view,
+                    view: color_view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
@@ -333,7 +754,7 @@ impl ForwardRenderer {
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: depth_view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: wgpu::LoadOp::Clear(self.depth_clear),
                         store: StoreOp::Store,
                     }),
                     stencil_ops: None,
@@ -342,13 +763,37 @@ impl ForwardRenderer {
                 occlusion_query_set: None,
             });
 
-            rpass.set_pipeline(&self.pipeline);
+            // executing bundles resets the pass state, so they go before the direct draws
+            if !self.static_bundles.is_empty() {
+                rpass.execute_bundles(self.static_bundles.iter().map(|b| &b.bundle));
+                for b in &self.static_bundles {
+                    stats.add(&b.stats);
+                }
+            }
+
+            let mut current_key = None;
             rpass.set_bind_group(0, &self.scene_bg, &[]);
-            //rpass.set_bind_group(1, &self.light_bg, &[]);
+            if let Some(bindless) = &self.bindless {
+                rpass.set_bind_group(1, &bindless.bg, &[]);
+            }
             rpass.set_bind_group(2, &self.mat_bg, &[]);
 
-            for cmd in action {
-                let mesh: &Mesh = self.asset.mesh(cmd.mesh_id).expect("mesh not found");
+            let mut current_scissor = None;
+            let mut current_material = None;
+            for (mesh_id, instances) in objects::mesh_runs(action) {
+                let Some(mesh) = self.asset.mesh(mesh_id) else {
+                    warn_missing_mesh(mesh_id);
+                    continue;
+                };
+                if mesh.is_empty() {
+                    continue;
+                }
+
+                let scissor = action[instances.start as usize].scissor;
+                if scissor != current_scissor {
+                    ScissorRect::apply(scissor, &mut rpass, target_size);
+                    current_scissor = scissor;
+                }
 
                 rpass.set_vertex_buffer(0, mesh.vertex_buf.slice(..));
 
@@ -359,36 +804,249 @@ impl ForwardRenderer {
 
                     for p in &mesh.primitives {
                         //let mat_id: u32 = p.material.0 as u32;
+                        let key = self.pipeline_key(p);
+                        if current_key != Some(key) {
+                            rpass.set_pipeline(&self.pipelines[&key]);
+                            current_key = Some(key);
+                        }
+
                         let offset = (p.material.0 * std::mem::size_of::<MatId>()) as u32;
 
                         rpass.set_bind_group(3, &self.mat_id_bg, &[offset]);
 
-                        let tex_bg = self.create_texture_group_bind_group(device, p.material.0);
-                        rpass.set_bind_group(1, &tex_bg, &[]);
+                        if self.bindless.is_none() && current_material != Some(p.material.0) {
+                            self.bind_texture_group(&mut rpass, p.material.0);
+                            current_material = Some(p.material.0);
+                        }
 
                         let first = p.first_index;
                         let count = p.index_count;
-                        rpass.draw_indexed(first..first + count, p.base_vertex, 0..1);
+                        rpass.draw_indexed(first..first + count, p.base_vertex, instances.clone());
+                        stats.record_draw(p, instances.len() as u32);
                     }
                 }
             }
+
+            if current_scissor.is_some() {
+                ScissorRect::apply(None, &mut rpass, target_size);
+            }
+            stats.draw_calls += self.draw_gpu_culled(&mut rpass);
+
+            self.lines.draw(&mut rpass, line_count);
+            if let Some(grid) = &self.grid {
+                grid.draw(&mut rpass);
+            }
+        }
+
+        stats
+    }
+
+    // Blocks until all submitted GPU work, including the last frame, has completed.
+    pub fn wait_idle(&self) {
+        if let Err(e) = self.context.device.poll(wgpu::PollType::Wait) {
+            eprintln!("wgpu: wait_idle failed: {}", e);
+        }
+    }
+
+    // Blocks until the submission returned by `render` has completed.
+    pub fn wait_for(&self, index: wgpu::SubmissionIndex) {
+        if let Err(e) = self
+            .context
+            .device
+            .poll(wgpu::PollType::WaitForSubmissionIndex(index))
+        {
+            eprintln!("wgpu: wait_for failed: {}", e);
+        }
+    }
+
+    pub fn set_max_objects(&mut self, max_objects: usize) {
+        self.objects.resize(&self.context.device, max_objects);
+        self.rebuild_scene_bind_group();
+    }
+
+    pub fn max_objects(&self) -> usize {
+        self.objects.capacity
+    }
+
+    // Writes every command's transform and light mask in one upload; draw i reads object i.
+    pub fn upload_objects(&mut self, lights: &[Light], action: &[RenderCommand]) {
+        if self.objects.reserve(&self.context.device, action.len()) {
+            self.rebuild_scene_bind_group();
+        }
+
+        let objects: Vec<ObjectUniform> = action
+            .iter()
+            .map(|cmd| {
+                let mask = match self.asset.mesh(cmd.mesh_id) {
+                    Some(mesh) => {
+                        let (min, max) = mesh.bounds();
+                        let (min, max) =
+                            transform_aabb(&cmd.transform, Vec3::from(min), Vec3::from(max));
+                        objects::light_mask(lights, min, max)
+                    }
+                    None => objects::ALL_LIGHTS,
+                };
+                ObjectUniform::new(cmd.transform, mask)
+            })
+            .collect();
+        if !objects.is_empty() {
+            self.context.queue.write_buffer(
+                &self.objects.buffer,
+                0,
+                bytemuck::cast_slice(&objects),
+            );
+        }
+    }
+
+    pub fn rebuild_scene_bind_group(&mut self) {
+        self.scene_bg = Self::create_scene_bind_group(
+            &self.context.device,
+            &self.scene_bgl,
+            &self.camera_buffer,
+            &self.light_ssbo,
+            &self.light_params,
+            &self.shadows,
+            &self.objects,
+            &self.fog_buffer,
+            &self.cookies,
+            &self.clusters,
+        );
+    }
+
+    pub fn create_forward_pipeline(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        cache: Option<&wgpu::PipelineCache>,
+        config: &PipelineConfig,
+        forward_shader: Option<&str>,
+        key: PipelineKey,
+    ) -> Result<wgpu::RenderPipeline, shader_defs::PreprocessError> {
+        let mut defs = key.features.shader_defs();
+        defs.extend(config.shader_defs());
+        if key.vertex_format == VertexFormat::Packed {
+            defs.push("PACKED_VERTICES");
         }
+        let source = shader_defs::preprocess(
+            &Self::forward_shader_source(config.bindless, forward_shader),
+            &defs,
+        )?;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Forward Shader"),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
 
-        queue.submit(Some(encoder.finish()));
-        frame.present();
+        Ok(device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Forward Pipeline"),
+            layout: Some(layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[key.vertex_format.buffer_layout()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(if key.features.premultiplied_alpha {
+                        wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING
+                    } else {
+                        wgpu::BlendState::ALPHA_BLENDING
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: key.topology,
+                cull_mode: config.cull_mode,
+                front_face: config.front_face,
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: depth_compare(DEPTH_COMPARE, config.reverse_z),
+                stencil: wgpu::StencilState::default(),
+                bias: config.depth_bias,
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache,
+        }))
+    }
+
+    // `forward_shader` replaces forward.wgsl; the texture bindings are appended either way.
+    pub fn forward_shader_source(
+        bindless: bool,
+        forward_shader: Option<&str>,
+    ) -> std::borrow::Cow<'static, str> {
+        let textures = if bindless {
+            include_str!("../shaders/textures_bindless.wgsl")
+        } else {
+            include_str!("../shaders/textures_bound.wgsl")
+        };
+        std::borrow::Cow::Owned(format!(
+            "{}\n{}",
+            forward_shader.unwrap_or(include_str!("../shaders/forward.wgsl")),
+            textures
+        ))
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
         if width == 0 || height == 0 {
             return;
         }
+        // keeps the surface and depth target when nothing changed
+        if width == self.context.config.width && height == self.context.config.height {
+            return;
+        }
 
         let ctx = &mut self.context;
         ctx.config.width = width;
         ctx.config.height = height;
-        ctx.surface.configure(&ctx.device, &ctx.config);
+        ctx.reconfigure();
+
+        (self.depth_tex, self.depth_view) = Self::create_depth_target(&ctx.device, width, height);
+        if let Some(picking) = self.picking.as_mut() {
+            picking.resize(&ctx.device, width, height);
+        }
+
+        self.camera.aspect = width as f32 / height as f32;
+        self.update_camera_buffer();
+    }
+
+    // Takes effect with the next frame; kept across resizes.
+    pub fn set_max_frame_latency(&mut self, frames: u32) {
+        let ctx = &mut self.context;
+        ctx.config.desired_maximum_frame_latency = frames.max(1);
+        ctx.reconfigure();
+    }
+
+    pub fn max_frame_latency(&self) -> u32 {
+        self.context.config.desired_maximum_frame_latency
+    }
+
+    // Called by App on ScaleFactorChanged, e.g. when the window moves to a HiDPI monitor.
+    // Only updates DPI-dependent state; the surface is reconfigured by resize.
+    pub fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.context.scale_factor = scale_factor;
+        self.lines.scale_factor = scale_factor as f32;
+    }
 
-        self.depth_tex = ctx.device.create_texture(&wgpu::TextureDescriptor {
+    pub fn scale_factor(&self) -> f64 {
+        self.context.scale_factor
+    }
+
+    // The depth target is also sampleable so post-processing can read scene depth. Bind it
+    // as texture_depth_2d (or an unfilterable float texture); it is written by the forward pass.
+    pub fn create_depth_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let depth_tex = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("Depth"),
             size: wgpu::Extent3d {
                 width,
@@ -399,32 +1057,51 @@ impl ForwardRenderer {
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             view_formats: &[],
         });
-        self.depth_view = self
-            .depth_tex
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        let depth_view = depth_tex.create_view(&wgpu::TextureViewDescriptor::default());
+        (depth_tex, depth_view)
+    }
 
-        self.camera.aspect = width as f32 / height as f32;
+    pub fn depth_texture(&self) -> &wgpu::Texture {
+        &self.depth_tex
+    }
+
+    // Recreated on resize, so don't hold bind groups built from it across a resize.
+    pub fn depth_texture_view(&self) -> &wgpu::TextureView {
+        &self.depth_view
+    }
+
+    // Uploads `cam` without drawing, e.g. ahead of a custom pass; render does this itself.
+    pub fn set_camera(&mut self, cam: &Camera) {
+        self.camera = *cam;
         self.update_camera_buffer();
     }
 
     pub fn update_camera_buffer(&mut self) {
-        let vp = self.camera.view_proj();
+        let vp = reverse_z_clip(self.pipeline_config.reverse_z) * self.camera.view_proj();
         let cu = CameraUniform {
             view_proj: vp.to_cols_array_2d(),
             camera_pos: self.camera.eye.to_array(), // assuming glam::Vec3
             _pad0: 0.0,
+            inv_view_proj: vp.inverse().to_cols_array_2d(),
         };
         self.context
             .queue
             .write_buffer(&self.camera_buffer, 0, bytemuck::bytes_of(&cu));
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn create_scene_bindings(
         device: &wgpu::Device,
         max_lights: usize,
+        light_visibility: wgpu::ShaderStages,
+        shadows: &PointShadowMaps,
+        objects: &ObjectBuffer,
+        fog_buffer: &wgpu::Buffer,
+        cookies: &CookieAtlas,
+        clusters: &LightClusters,
     ) -> (
         wgpu::Buffer, // Camera UBO
         wgpu::Buffer, // Lights SSBO
@@ -480,7 +1157,7 @@ impl ForwardRenderer {
                     // binding 1: Lights Storage Buffer
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT, // Only needed in fragment shader
+                        visibility: light_visibility,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Storage { read_only: true },
                             has_dynamic_offset: false,
@@ -491,7 +1168,7 @@ impl ForwardRenderer {
                     // binding 2: Light Count Uniform
                     wgpu::BindGroupLayoutEntry {
                         binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT, // Only needed in fragment shader
+                        visibility: light_visibility,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -501,12 +1178,117 @@ impl ForwardRenderer {
                         },
                         count: None,
                     },
+                    // binding 3: Point Shadow Cube Array
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Depth,
+                            view_dimension: wgpu::TextureViewDimension::CubeArray,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // binding 4: Shadow Comparison Sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                        count: None,
+                    },
+                    // binding 5: Object Transforms
+                    ObjectBuffer::layout_entry(5),
+                    // binding 6: Fog Uniform
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 6,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: NonZeroU64::new(
+                                std::mem::size_of::<fog::FogUniform>() as u64,
+                            ),
+                        },
+                        count: None,
+                    },
+                    // binding 7: Spot Cookie Array
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            view_dimension: wgpu::TextureViewDimension::D2Array,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                    // binding 8: Cookie Sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                    // binding 9: Light Cluster Params
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: light_visibility,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: NonZeroU64::new(std::mem::size_of::<
+                                clusters::ClusterParams,
+                            >()
+                                as u64),
+                        },
+                        count: None,
+                    },
+                    // binding 10: Light Cluster Grid
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: light_visibility,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
                 ],
             });
 
-        let scene_bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        let scene_bg = Self::create_scene_bind_group(
+            device,
+            &scene_bgl,
+            &camera_buffer,
+            &lights_ssbo,
+            &params_ubo,
+            shadows,
+            objects,
+            fog_buffer,
+            cookies,
+            clusters,
+        );
+
+        (camera_buffer, lights_ssbo, params_ubo, scene_bgl, scene_bg)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_scene_bind_group(
+        device: &wgpu::Device,
+        scene_bgl: &wgpu::BindGroupLayout,
+        camera_buffer: &wgpu::Buffer,
+        lights_ssbo: &wgpu::Buffer,
+        params_ubo: &wgpu::Buffer,
+        shadows: &PointShadowMaps,
+        objects: &ObjectBuffer,
+        fog_buffer: &wgpu::Buffer,
+        cookies: &CookieAtlas,
+        clusters: &LightClusters,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Scene BG"),
-            layout: &scene_bgl,
+            layout: scene_bgl,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -520,15 +1302,46 @@ impl ForwardRenderer {
                     binding: 2,
                     resource: params_ubo.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&shadows.cube_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(&shadows.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: objects.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: fog_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::TextureView(&cookies.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::Sampler(&cookies.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: clusters.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: clusters.grid.as_entire_binding(),
+                },
             ],
-        });
-
-        (camera_buffer, lights_ssbo, params_ubo, scene_bgl, scene_bg)
+        })
     }
 
     pub fn create_light(
         device: &wgpu::Device,
         max_lights: usize,
+        visibility: wgpu::ShaderStages,
     ) -> (
         wgpu::Buffer,
         wgpu::Buffer,
@@ -541,7 +1354,7 @@ impl ForwardRenderer {
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        visibility,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Storage { read_only: true },
                             has_dynamic_offset: false,
@@ -551,7 +1364,7 @@ impl ForwardRenderer {
                     },
                     wgpu::BindGroupLayoutEntry {
                         binding: 1,
-                        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                        visibility,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -764,6 +1577,23 @@ impl ForwardRenderer {
                     ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
                     count: None,
                 },
+                // detail
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
             ],
         });
         texture_group_bgl
@@ -779,11 +1609,13 @@ impl ForwardRenderer {
         let metallic_roughness = &self.asset.textures[texture_group.metallic_roughness];
         let normal = &self.asset.textures[texture_group.normal];
         let emissive = &self.asset.textures[texture_group.emissive];
+        let detail = &self.asset.textures[texture_group.detail];
 
         let base_color_sampler = &self.asset.samplers[base_color.sampler];
         let metallic_roughness_sampler = &self.asset.samplers[metallic_roughness.sampler];
         let normal_sampler = &self.asset.samplers[normal.sampler];
         let emissive_sampler = &self.asset.samplers[emissive.sampler];
+        let detail_sampler = &self.asset.samplers[detail.sampler];
 
         device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("TextureGroup BindGroup"),
@@ -821,7 +1653,112 @@ impl ForwardRenderer {
                     binding: 7,
                     resource: wgpu::BindingResource::Sampler(emissive_sampler),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(&detail.tex_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::Sampler(detail_sampler),
+                },
             ],
         })
     }
 }
+
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+    use crate::asset_manager::mesh::Primitive;
+
+    // A headless renderer on whatever adapter is available; None skips the test on machines
+    // without one.
+    pub fn renderer(width: u32, height: u32) -> Option<ForwardRenderer> {
+        pollster::block_on(ForwardRenderer::headless(
+            width,
+            height,
+            GpuConfig::default(),
+        ))
+        .ok()
+    }
+
+    // Looks down -Z at the origin from 4 units away.
+    pub fn camera(aspect: f32) -> Camera {
+        Camera {
+            eye: Vec3::new(0.0, 0.0, 4.0),
+            aspect,
+            ..Default::default()
+        }
+    }
+
+    // A white point light next to the camera.
+    pub fn light() -> Light {
+        Light {
+            position: [0.0, 0.0, 3.0],
+            range: 20.0,
+            ..Default::default()
+        }
+    }
+
+    pub fn cube(renderer: &mut ForwardRenderer, name: &str) -> MeshId {
+        renderer
+            .asset
+            .set_mesh(&[Primitive::cube(0.5)], name)
+            .unwrap()
+    }
+
+    pub fn draw(mesh_id: MeshId, translation: Vec3) -> RenderCommand {
+        RenderCommand {
+            mesh_id,
+            transform: Mat4::from_translation(translation),
+            ..Default::default()
+        }
+    }
+
+    // RGBA of the pixel at (x, y) in a tightly packed 4 bytes per pixel frame.
+    pub fn pixel(frame: &[u8], width: u32, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * width + x) * 4) as usize;
+        frame[i..i + 4].try_into().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn view_keeps_distances() {
+        let cam = Camera::default();
+        let dir = (cam.target - cam.eye).normalize();
+        let p = cam.view().transform_point3(cam.eye + dir * 2.0);
+        assert!((p - Vec3::new(0.0, 0.0, -2.0)).length() < 1e-5);
+    }
+
+    #[test]
+    fn reverse_z_clears_to_zero_and_flips_the_depth_test() {
+        assert_eq!(depth_clear_value(false), 1.0);
+        assert_eq!(depth_clear_value(true), 0.0);
+
+        use wgpu::CompareFunction::*;
+        assert_eq!(depth_compare(DEPTH_COMPARE, false), Less);
+        assert_eq!(depth_compare(DEPTH_COMPARE, true), Greater);
+        assert_eq!(depth_compare(LessEqual, true), GreaterEqual);
+        assert_eq!(depth_compare(Always, true), Always);
+    }
+
+    #[test]
+    fn reverse_z_clip_swaps_the_near_and_far_planes() {
+        let cam = Camera::default();
+        let dir = (cam.target - cam.eye).normalize();
+        let depth = |reverse_z, distance: f32| {
+            let vp = reverse_z_clip(reverse_z) * cam.view_proj();
+            vp.project_point3(cam.eye + dir * distance).z
+        };
+        assert!((depth(false, cam.z_near) - 0.0).abs() < 1e-4);
+        assert!((depth(false, cam.z_far) - 1.0).abs() < 1e-4);
+        assert!((depth(true, cam.z_near) - 1.0).abs() < 1e-4);
+        assert!((depth(true, cam.z_far) - 0.0).abs() < 1e-4);
+        // nearer is larger with reverse-z, matching the Greater test
+        assert!(depth(true, 1.0) > depth(true, 10.0));
+    }
+}
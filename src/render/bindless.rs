@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+
+use wgpu::util::DeviceExt;
+
+use super::{ForwardRenderer, RenderCommand};
+use crate::asset_manager::material::MAX_MAT;
+use crate::asset_manager::{AssetManager, SamplerId, TextureId};
+
+pub const MAX_BINDLESS_TEXTURES: u32 = 1024;
+pub const MAX_BINDLESS_SAMPLERS: u32 = 64;
+
+// Per-material indices into the texture and sampler binding arrays.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialTextures {
+    pub base_color: u32,
+    pub base_color_sampler: u32,
+    pub metallic_roughness: u32,
+    pub metallic_roughness_sampler: u32,
+    pub normal: u32,
+    pub normal_sampler: u32,
+    pub emissive: u32,
+    pub emissive_sampler: u32,
+    pub detail: u32,
+    pub detail_sampler: u32,
+}
+
+pub struct BindlessTextures {
+    pub texture_capacity: u32,
+    pub sampler_capacity: u32,
+    pub texture_count: u32,
+    pub sampler_count: u32,
+
+    pub bgl: wgpu::BindGroupLayout,
+    pub bg: wgpu::BindGroup,
+    pub mat_tex_buffer: wgpu::Buffer,
+
+    // asset revision the bind group was built from
+    pub revision: u64,
+}
+
+impl BindlessTextures {
+    pub fn new(device: &wgpu::Device, asset: &AssetManager) -> Self {
+        let (texture_capacity, sampler_capacity) = Self::capacities(&device.limits());
+        let bgl = Self::create_layout(device, texture_capacity, sampler_capacity);
+
+        let mat_tex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Material Textures Buffer"),
+            contents: bytemuck::cast_slice(&[MaterialTextures::default(); MAX_MAT]),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let (bg, texture_count, sampler_count) = Self::create_bind_group(
+            device,
+            &bgl,
+            &mat_tex_buffer,
+            asset,
+            texture_capacity,
+            sampler_capacity,
+        );
+
+        Self {
+            texture_capacity,
+            sampler_capacity,
+            texture_count,
+            sampler_count,
+            bgl,
+            bg,
+            mat_tex_buffer,
+            revision: asset.tex_revision,
+        }
+    }
+
+    // Binding array sizes for the texture and sampler arrays: the engine maximums, clamped
+    // to what the device allows per shader stage.
+    pub fn capacities(limits: &wgpu::Limits) -> (u32, u32) {
+        (
+            MAX_BINDLESS_TEXTURES.min(limits.max_binding_array_elements_per_shader_stage),
+            MAX_BINDLESS_SAMPLERS.min(limits.max_binding_array_sampler_elements_per_shader_stage),
+        )
+    }
+
+    pub fn create_layout(
+        device: &wgpu::Device,
+        texture_capacity: u32,
+        sampler_capacity: u32,
+    ) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Bindless Texture BGL"),
+            entries: &[
+                // binding 0: all texture views
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: NonZeroU32::new(texture_capacity),
+                },
+                // binding 1: all samplers
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: NonZeroU32::new(sampler_capacity),
+                },
+                // binding 2: per-material texture/sampler indices
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    // Builds the arrays from every loaded texture/sampler, padding the unused tail with the
+    // defaults so the arrays always match the layout capacity. Returns the real counts.
+    pub fn create_bind_group(
+        device: &wgpu::Device,
+        bgl: &wgpu::BindGroupLayout,
+        mat_tex_buffer: &wgpu::Buffer,
+        asset: &AssetManager,
+        texture_capacity: u32,
+        sampler_capacity: u32,
+    ) -> (wgpu::BindGroup, u32, u32) {
+        let mut tex_index: HashMap<TextureId, u32> = HashMap::new();
+        let mut views: Vec<&wgpu::TextureView> = Vec::new();
+
+        let default_color = &asset.textures[asset.color_tex_default];
+        views.push(&default_color.tex_view);
+        tex_index.insert(asset.color_tex_default, 0);
+
+        for (id, tex) in asset.textures.iter() {
+            // depth textures can't live in a filterable float array
+            if id == asset.color_tex_default || tex.tex.format().is_depth_stencil_format() {
+                continue;
+            }
+            if views.len() as u32 >= texture_capacity {
+                eprintln!(
+                    "bindless: more than {} textures loaded; extra textures fall back to the default",
+                    texture_capacity
+                );
+                break;
+            }
+            tex_index.insert(id, views.len() as u32);
+            views.push(&tex.tex_view);
+        }
+
+        let mut sampler_index: HashMap<SamplerId, u32> = HashMap::new();
+        let mut samplers: Vec<&wgpu::Sampler> = Vec::new();
+
+        samplers.push(&asset.samplers[asset.sampler_default]);
+        sampler_index.insert(asset.sampler_default, 0);
+
+        for (id, sampler) in asset.samplers.iter() {
+            if id == asset.sampler_default {
+                continue;
+            }
+            if samplers.len() as u32 >= sampler_capacity {
+                eprintln!(
+                    "bindless: more than {} samplers loaded; extra samplers fall back to the default",
+                    sampler_capacity
+                );
+                break;
+            }
+            sampler_index.insert(id, samplers.len() as u32);
+            samplers.push(sampler);
+        }
+
+        let texture_count = views.len() as u32;
+        let sampler_count = samplers.len() as u32;
+        views.resize(texture_capacity as usize, &default_color.tex_view);
+        samplers.resize(
+            sampler_capacity as usize,
+            &asset.samplers[asset.sampler_default],
+        );
+
+        let slot = |id: TextureId| -> (u32, u32) {
+            let tex = tex_index.get(&id).copied().unwrap_or(0);
+            let sampler = asset
+                .textures
+                .get(id)
+                .and_then(|t| sampler_index.get(&t.sampler).copied())
+                .unwrap_or(0);
+            (tex, sampler)
+        };
+
+        let mat_textures: Vec<MaterialTextures> = asset
+            .tex_by_mat
+            .iter()
+            .map(|group| {
+                let (base_color, base_color_sampler) = slot(group.base_color);
+                let (metallic_roughness, metallic_roughness_sampler) =
+                    slot(group.metallic_roughness);
+                let (normal, normal_sampler) = slot(group.normal);
+                let (emissive, emissive_sampler) = slot(group.emissive);
+                let (detail, detail_sampler) = slot(group.detail);
+                MaterialTextures {
+                    base_color,
+                    base_color_sampler,
+                    metallic_roughness,
+                    metallic_roughness_sampler,
+                    normal,
+                    normal_sampler,
+                    emissive,
+                    emissive_sampler,
+                    detail,
+                    detail_sampler,
+                }
+            })
+            .collect();
+        asset
+            .queue
+            .write_buffer(mat_tex_buffer, 0, bytemuck::cast_slice(&mat_textures));
+
+        let bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Bindless Texture BG"),
+            layout: bgl,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureViewArray(&views),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::SamplerArray(&samplers),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: mat_tex_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        (bg, texture_count, sampler_count)
+    }
+
+    // Rebuilds the arrays if textures or materials changed since the last build.
+    pub fn refresh(&mut self, device: &wgpu::Device, asset: &AssetManager) {
+        if self.revision == asset.tex_revision {
+            return;
+        }
+        let (bg, texture_count, sampler_count) = Self::create_bind_group(
+            device,
+            &self.bgl,
+            &self.mat_tex_buffer,
+            asset,
+            self.texture_capacity,
+            self.sampler_capacity,
+        );
+        self.bg = bg;
+        self.texture_count = texture_count;
+        self.sampler_count = sampler_count;
+        self.revision = asset.tex_revision;
+    }
+}
+
+// Per-material texture group bind groups for the non-bindless path. Built on first use and
+// dropped together whenever textures or materials change, like BindlessTextures::refresh.
+pub struct TextureGroupCache {
+    pub revision: u64,
+    pub groups: Vec<Option<wgpu::BindGroup>>,
+}
+
+impl Default for TextureGroupCache {
+    fn default() -> Self {
+        Self {
+            revision: 0,
+            groups: (0..MAX_MAT).map(|_| None).collect(),
+        }
+    }
+}
+
+impl TextureGroupCache {
+    pub fn refresh(&mut self, asset: &AssetManager) {
+        if self.revision == asset.tex_revision {
+            return;
+        }
+        self.groups.iter_mut().for_each(|g| *g = None);
+        self.revision = asset.tex_revision;
+    }
+}
+
+impl ForwardRenderer {
+    // Builds the missing texture groups of every material `action` and the pending GPU-culled
+    // mesh draw with, so the passes only bind cached ones.
+    pub fn cache_texture_groups(&mut self, action: &[RenderCommand]) {
+        self.tex_groups.refresh(&self.asset);
+
+        let pending = self.gpu_cull.as_ref().and_then(|c| c.pending);
+        let mut missing = Vec::new();
+        let mut last = None;
+        for mesh_id in action.iter().map(|c| c.mesh_id).chain(pending) {
+            if last.replace(mesh_id) == Some(mesh_id) {
+                continue;
+            }
+            let Some(mesh) = self.asset.mesh(mesh_id) else {
+                continue;
+            };
+            for p in &mesh.primitives {
+                let idx = p.material.0;
+                if self.tex_groups.groups[idx].is_none() && !missing.contains(&idx) {
+                    missing.push(idx);
+                }
+            }
+        }
+
+        for idx in missing {
+            let bg = self.create_texture_group_bind_group(&self.context.device, idx);
+            self.tex_groups.groups[idx] = Some(bg);
+        }
+    }
+
+    // Binds group 1 for `material_index` on the non-bindless path, from the cache when
+    // cache_texture_groups has built it.
+    pub fn bind_texture_group(&self, rpass: &mut wgpu::RenderPass, material_index: usize) {
+        match &self.tex_groups.groups[material_index] {
+            Some(bg) => rpass.set_bind_group(1, bg, &[]),
+            None => {
+                let bg = self.create_texture_group_bind_group(&self.context.device, material_index);
+                rpass.set_bind_group(1, &bg, &[]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_manager::test_support;
+    use crate::asset_manager::texture::TextureSlot;
+
+    #[test]
+    fn binding_array_capacity_is_clamped_to_the_device_limits() {
+        let small = wgpu::Limits {
+            max_binding_array_elements_per_shader_stage: 16,
+            max_binding_array_sampler_elements_per_shader_stage: 4,
+            ..Default::default()
+        };
+        assert_eq!(BindlessTextures::capacities(&small), (16, 4));
+
+        let large = wgpu::Limits {
+            max_binding_array_elements_per_shader_stage: 500_000,
+            max_binding_array_sampler_elements_per_shader_stage: 1000,
+            ..Default::default()
+        };
+        assert_eq!(
+            BindlessTextures::capacities(&large),
+            (MAX_BINDLESS_TEXTURES, MAX_BINDLESS_SAMPLERS)
+        );
+    }
+
+    // Needs an adapter with TEXTURE_BINDING_ARRAY; skipped elsewhere.
+    #[test]
+    fn binding_arrays_count_the_loaded_textures() {
+        let instance = wgpu::Instance::default();
+        let Ok(adapter) =
+            pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))
+        else {
+            return;
+        };
+        let Some(features) = crate::render::gpu::bindless_features(adapter.features()) else {
+            return;
+        };
+        let limits = adapter.limits();
+        let Ok((device, queue)) =
+            pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor {
+                required_features: features,
+                required_limits: limits.clone(),
+                ..Default::default()
+            }))
+        else {
+            return;
+        };
+        let mut assets = AssetManager::new(std::sync::Arc::new(device), std::sync::Arc::new(queue));
+        let device = assets.device.clone();
+
+        let mut bindless = BindlessTextures::new(&device, &assets);
+        let (texture_capacity, sampler_capacity) = BindlessTextures::capacities(&limits);
+        assert_eq!(bindless.texture_capacity, texture_capacity);
+        assert_eq!(bindless.sampler_capacity, sampler_capacity);
+        let color_textures = |assets: &AssetManager| {
+            assets
+                .textures
+                .values()
+                .filter(|t| !t.tex.format().is_depth_stencil_format())
+                .count() as u32
+        };
+        assert_eq!(bindless.texture_count, color_textures(&assets));
+        assert_eq!(bindless.sampler_count, assets.samplers.len() as u32);
+
+        let dir = test_support::temp_dir("bindless-count");
+        let path = test_support::write_texture_gltf(&dir, [255, 0, 0, 255]);
+        assets
+            .get_texture(&format!("{}#albedo", path), TextureSlot::BaseColor)
+            .unwrap();
+        bindless.refresh(&device, &assets);
+        assert_eq!(bindless.texture_count, color_textures(&assets));
+    }
+
+    #[test]
+    fn texture_group_cache_drops_groups_when_textures_change() {
+        let Some(mut assets) = test_support::assets() else {
+            return;
+        };
+        let bgl = assets
+            .device
+            .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[],
+            });
+        let bg = assets.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &bgl,
+            entries: &[],
+        });
+
+        let mut cache = TextureGroupCache::default();
+        cache.refresh(&assets);
+        cache.groups[3] = Some(bg);
+        cache.refresh(&assets);
+        assert!(cache.groups[3].is_some());
+
+        assets.tex_revision += 1;
+        cache.refresh(&assets);
+        assert!(cache.groups.iter().all(Option::is_none));
+        assert_eq!(cache.revision, assets.tex_revision);
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use super::objects::{ALL_LIGHTS, ObjectBuffer, mesh_runs};
+use super::stats::RenderStats;
+use super::{DEPTH_FORMAT, ForwardRenderer, RenderCommand};
+use crate::asset_manager::material::MatId;
+use crate::asset_manager::mesh::ObjectUniform;
+
+// Pre-recorded forward draws for geometry that doesn't move, replayed every frame with
+// execute_bundles instead of being re-encoded. The bundle owns its object transforms, so
+// its draws are independent of the per-frame command list.
+//
+// A bundle captures the pipelines, textures, shadow maps and light cluster grid current when
+// it was recorded; record it again after changing any of those. Bundled objects are not
+// drawn into the shadow maps or the picking target.
+pub struct StaticBundle {
+    pub bundle: wgpu::RenderBundle,
+    pub objects: ObjectBuffer,
+    pub scene_bg: wgpu::BindGroup,
+    pub commands: Vec<RenderCommand>,
+    // what one execution of the bundle draws
+    pub stats: RenderStats,
+}
+
+impl ForwardRenderer {
+    pub fn create_static_bundle(&mut self, commands: &[RenderCommand]) -> StaticBundle {
+        // bundles can't change the scissor rect
+        if commands.iter().any(|c| c.scissor.is_some()) {
+            eprintln!("create_static_bundle: scissor rects are ignored in bundles");
+        }
+        self.ensure_pipelines_for(commands);
+
+        let device = &self.context.device;
+
+        let objects = ObjectBuffer::new(device, commands.len());
+        let transforms: Vec<ObjectUniform> = commands
+            .iter()
+            .map(|cmd| ObjectUniform::new(cmd.transform, ALL_LIGHTS))
+            .collect();
+        if !transforms.is_empty() {
+            self.context
+                .queue
+                .write_buffer(&objects.buffer, 0, bytemuck::cast_slice(&transforms));
+        }
+
+        let scene_bg = Self::create_scene_bind_group(
+            device,
+            &self.scene_bgl,
+            &self.camera_buffer,
+            &self.light_ssbo,
+            &self.light_params,
+            &self.shadows,
+            &objects,
+            &self.fog_buffer,
+            &self.cookies,
+            &self.clusters,
+        );
+
+        let runs = mesh_runs(commands);
+
+        // bundles borrow their bind groups, so per-material texture groups are made up front
+        let mut tex_bgs: HashMap<usize, wgpu::BindGroup> = HashMap::new();
+        if self.bindless.is_none() {
+            for (mesh_id, _) in &runs {
+                let Some(mesh) = self.asset.mesh(*mesh_id) else {
+                    continue;
+                };
+                for p in &mesh.primitives {
+                    tex_bgs.entry(p.material.0).or_insert_with(|| {
+                        self.create_texture_group_bind_group(device, p.material.0)
+                    });
+                }
+            }
+        }
+
+        let mut encoder =
+            device.create_render_bundle_encoder(&wgpu::RenderBundleEncoderDescriptor {
+                label: Some("Static Bundle Encoder"),
+                color_formats: &[Some(self.pipeline_config.format)],
+                depth_stencil: Some(wgpu::RenderBundleDepthStencil {
+                    format: DEPTH_FORMAT,
+                    depth_read_only: false,
+                    stencil_read_only: true,
+                }),
+                sample_count: 1,
+                multiview: None,
+            });
+
+        encoder.set_bind_group(0, &scene_bg, &[]);
+        if let Some(bindless) = &self.bindless {
+            encoder.set_bind_group(1, &bindless.bg, &[]);
+        }
+        encoder.set_bind_group(2, &self.mat_bg, &[]);
+
+        let mut current_key = None;
+        let mut stats = RenderStats::default();
+        for (mesh_id, instances) in runs {
+            let Some(mesh) = self.asset.mesh(mesh_id) else {
+                eprintln!("create_static_bundle: mesh_id {:?} not found", mesh_id);
+                continue;
+            };
+            let (Some(index_buf), Some(index_fmt)) = (mesh.index_buf.as_ref(), mesh.index_format)
+            else {
+                continue;
+            };
+
+            encoder.set_vertex_buffer(0, mesh.vertex_buf.slice(..));
+            encoder.set_index_buffer(index_buf.slice(..), index_fmt);
+
+            for p in &mesh.primitives {
+                let key = self.pipeline_key(p);
+                if current_key != Some(key) {
+                    encoder.set_pipeline(&self.pipelines[&key]);
+                    current_key = Some(key);
+                }
+
+                let offset = (p.material.0 * std::mem::size_of::<MatId>()) as u32;
+                encoder.set_bind_group(3, &self.mat_id_bg, &[offset]);
+
+                if let Some(tex_bg) = tex_bgs.get(&p.material.0) {
+                    encoder.set_bind_group(1, tex_bg, &[]);
+                }
+
+                encoder.draw_indexed(
+                    p.first_index..p.first_index + p.index_count,
+                    p.base_vertex,
+                    instances.clone(),
+                );
+                stats.record_draw(p, instances.len() as u32);
+            }
+        }
+
+        let bundle = encoder.finish(&wgpu::RenderBundleDescriptor {
+            label: Some("Static Bundle"),
+        });
+
+        StaticBundle {
+            bundle,
+            objects,
+            scene_bg,
+            commands: commands.to_vec(),
+            stats,
+        }
+    }
+
+    // Records `commands` once and draws them every frame until cleared. Returns the
+    // bundle's index in `static_bundles`.
+    pub fn add_static_bundle(&mut self, commands: &[RenderCommand]) -> usize {
+        let bundle = self.create_static_bundle(commands);
+        self.static_bundles.push(bundle);
+        self.static_bundles.len() - 1
+    }
+
+    // Re-records every bundle from its commands, e.g. after pipelines or textures changed.
+    pub fn rerecord_static_bundles(&mut self) {
+        let commands: Vec<Vec<RenderCommand>> = self
+            .static_bundles
+            .iter()
+            .map(|b| b.commands.clone())
+            .collect();
+        self.static_bundles = commands
+            .iter()
+            .map(|c| self.create_static_bundle(c))
+            .collect();
+    }
+
+    pub fn clear_static_bundles(&mut self) {
+        self.static_bundles.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::test_support::*;
+    use glam::Vec3;
+
+    #[test]
+    fn bundle_draws_the_same_image_as_the_direct_path() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let cube = cube(&mut renderer, "cube");
+        let target = renderer.create_offscreen_target(32, 32);
+        let cam = camera(1.0);
+        let commands = [
+            draw(cube, Vec3::new(-0.75, 0.0, 0.0)),
+            draw(cube, Vec3::new(0.75, 0.0, -1.0)),
+        ];
+
+        renderer.render_to(&target, &[light()], &cam, &commands);
+        let direct = renderer.read_frame(&target).unwrap();
+        let direct_stats = renderer.stats;
+
+        renderer.add_static_bundle(&commands);
+        renderer.render_to(&target, &[light()], &cam, &[]);
+        let bundled = renderer.read_frame(&target).unwrap();
+
+        assert_eq!(renderer.stats, direct_stats);
+        assert!(
+            direct == bundled,
+            "bundled frame differs from the direct one"
+        );
+    }
+}
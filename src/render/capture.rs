@@ -0,0 +1,135 @@
+use std::path::Path;
+
+use super::ForwardRenderer;
+use super::offscreen::read_rows;
+
+// Readback of one presented frame, copied out of the surface texture before present.
+pub struct FrameCapture {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub readback: wgpu::Buffer,
+    pub padded_bytes_per_row: u32,
+}
+
+// A captured frame as tightly packed RGBA8 rows, top row first.
+pub struct Screenshot {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl Screenshot {
+    pub fn save_png(&self, path: impl AsRef<Path>) -> image::ImageResult<()> {
+        image::save_buffer_with_format(
+            path,
+            &self.pixels,
+            self.width,
+            self.height,
+            image::ColorType::Rgba8,
+            image::ImageFormat::Png,
+        )
+    }
+}
+
+// Surface formats a capture can be turned into RGBA8 from; the bool is a red/blue swap.
+pub fn capture_swizzle(format: wgpu::TextureFormat) -> Option<bool> {
+    match format {
+        wgpu::TextureFormat::Rgba8Unorm | wgpu::TextureFormat::Rgba8UnormSrgb => Some(false),
+        wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb => Some(true),
+        _ => None,
+    }
+}
+
+impl ForwardRenderer {
+    // Has the next render copy the frame it presents, for take_capture to read back.
+    // Returns false when the surface can't be copied from or its format isn't 8-bit RGBA.
+    pub fn capture_frame(&mut self) -> bool {
+        let config = &self.context.config;
+        if !config.usage.contains(wgpu::TextureUsages::COPY_SRC) {
+            eprintln!("capture_frame: the surface doesn't support copies");
+            return false;
+        }
+        if capture_swizzle(config.format).is_none() {
+            eprintln!(
+                "capture_frame: can't capture surface format {:?}",
+                config.format
+            );
+            return false;
+        }
+        self.capture_requested = true;
+        true
+    }
+
+    // Records the copy armed by capture_frame; render calls this before presenting.
+    pub fn copy_frame(&mut self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        if !std::mem::take(&mut self.capture_requested) {
+            return;
+        }
+        let width = texture.width();
+        let height = texture.height();
+        let format = texture.format();
+        let padded_bytes_per_row = (width * format.block_copy_size(None).unwrap_or(4))
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback = self.context.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Capture Readback"),
+            size: padded_bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &readback,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        self.capture = Some(FrameCapture {
+            width,
+            height,
+            format,
+            readback,
+            padded_bytes_per_row,
+        });
+    }
+
+    // The frame copied since the last capture_frame, if one has been rendered. Blocks on the
+    // GPU like read_frame.
+    pub fn take_capture(&mut self) -> Option<Screenshot> {
+        let capture = self.capture.take()?;
+        let swap = capture_swizzle(capture.format)?;
+        let mut pixels = read_rows(
+            &self.context.device,
+            &capture.readback,
+            capture.padded_bytes_per_row,
+            capture.width * 4,
+        )?;
+        if swap {
+            for px in pixels.chunks_exact_mut(4) {
+                px.swap(0, 2);
+            }
+        }
+        Some(Screenshot {
+            width: capture.width,
+            height: capture.height,
+            pixels,
+        })
+    }
+}
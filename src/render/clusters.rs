@@ -0,0 +1,275 @@
+use super::{Camera, ForwardRenderer};
+use crate::asset_manager::light::MAX_LIGHTS;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// The view frustum is split into dims[0] x dims[1] screen tiles and dims[2] depth slices,
+// spaced exponentially between the camera's near and far planes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClusterConfig {
+    pub dims: [u32; 3],
+    // lights past this many in one cluster are left out of it
+    pub max_lights_per_cluster: u32,
+}
+
+impl Default for ClusterConfig {
+    fn default() -> Self {
+        Self {
+            dims: [16, 9, 24],
+            max_lights_per_cluster: MAX_LIGHTS as u32,
+        }
+    }
+}
+
+impl ClusterConfig {
+    pub fn cluster_count(&self) -> u32 {
+        self.dims.iter().map(|&d| d.max(1)).product()
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ClusterParams {
+    pub view: [[f32; 4]; 4],
+    pub dims: [u32; 3],
+    pub max_lights: u32,
+    pub near: f32,
+    pub far: f32,
+    pub tan_half_fov: [f32; 2],
+}
+
+impl ClusterParams {
+    pub fn new(config: &ClusterConfig, cam: &Camera) -> Self {
+        let near = cam.z_near.max(1e-4);
+        let tan = (cam.fov_y_radians * 0.5).tan();
+        Self {
+            view: cam.view().to_cols_array_2d(),
+            dims: config.dims.map(|d| d.max(1)),
+            max_lights: config.max_lights_per_cluster,
+            near,
+            far: cam.z_far.max(near * 2.0),
+            tan_half_fov: [tan * cam.aspect, tan],
+        }
+    }
+}
+
+// Clustered light lists for the forward pass. Each frame a compute pass tests every light
+// against every cluster, and the forward shader only loops over the lights of the cluster
+// its fragment falls into (light_clusters.wgsl).
+pub struct LightClusters {
+    pub config: ClusterConfig,
+    pub params: wgpu::Buffer,
+    // per cluster: the light count, then max_lights_per_cluster light indices
+    pub grid: wgpu::Buffer,
+    pub bgl: wgpu::BindGroupLayout,
+    pub pipeline: wgpu::ComputePipeline,
+}
+
+impl LightClusters {
+    pub fn new(device: &wgpu::Device, config: ClusterConfig) -> Self {
+        let buffer = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Light Cluster BGL"),
+            entries: &[
+                buffer(0, wgpu::BufferBindingType::Storage { read_only: true }),
+                buffer(1, wgpu::BufferBindingType::Uniform),
+                buffer(2, wgpu::BufferBindingType::Uniform),
+                buffer(3, wgpu::BufferBindingType::Storage { read_only: false }),
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Light Cluster Shader"),
+            source: wgpu::ShaderSource::Wgsl(
+                include_str!("../../shaders/light_clusters.wgsl").into(),
+            ),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Light Cluster Pipeline Layout"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Light Cluster Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Cluster Params"),
+            size: std::mem::size_of::<ClusterParams>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            config,
+            params,
+            grid: Self::create_grid(device, &config),
+            bgl,
+            pipeline,
+        }
+    }
+
+    fn create_grid(device: &wgpu::Device, config: &ClusterConfig) -> wgpu::Buffer {
+        let stride = config.max_lights_per_cluster as u64 + 1;
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Light Cluster Grid"),
+            size: config.cluster_count() as u64 * stride * 4,
+            // COPY_SRC to read the light lists back when debugging
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, cam: &Camera) {
+        let params = ClusterParams::new(&self.config, cam);
+        queue.write_buffer(&self.params, 0, bytemuck::bytes_of(&params));
+    }
+
+    pub fn dispatch(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        lights: &wgpu::Buffer,
+        light_params: &wgpu::Buffer,
+    ) {
+        let bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Light Cluster BG"),
+            layout: &self.bgl,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: lights.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: light_params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.grid.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Light Cluster Pass"),
+            timestamp_writes: None,
+        });
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &bg, &[]);
+        cpass.dispatch_workgroups(self.config.cluster_count().div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
+
+impl ForwardRenderer {
+    // Static bundles keep the previous grid; record them again afterwards.
+    pub fn set_cluster_config(&mut self, config: ClusterConfig) {
+        let config = ClusterConfig {
+            dims: config.dims.map(|d| d.max(1)),
+            max_lights_per_cluster: config.max_lights_per_cluster.clamp(1, MAX_LIGHTS as u32),
+        };
+        if config == self.clusters.config {
+            return;
+        }
+        self.clusters.config = config;
+        self.clusters.grid = LightClusters::create_grid(&self.context.device, &config);
+        self.rebuild_scene_bind_group();
+    }
+
+    pub fn cluster_config(&self) -> ClusterConfig {
+        self.clusters.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Mat4, Vec2, Vec3};
+
+    use super::*;
+    use crate::asset_manager::light::Light;
+    use crate::render::test_support::*;
+
+    // Whether a point light reaches cluster (x, y, z), with the box light_clusters.wgsl
+    // builds for it.
+    fn overlaps(params: &ClusterParams, [x, y, z]: [u32; 3], light: &Light) -> bool {
+        let dims = params.dims;
+        let slice =
+            |k: u32| params.near * (params.far / params.near).powf(k as f32 / dims[2] as f32);
+        let tiles = Vec2::new(dims[0] as f32, dims[1] as f32);
+        let tan = Vec2::from(params.tan_half_fov);
+        let a = Vec2::new(x as f32, y as f32) / tiles * 2.0 - 1.0;
+        let b = Vec2::new((x + 1) as f32, (y + 1) as f32) / tiles * 2.0 - 1.0;
+        let (a, b) = (a * tan, b * tan);
+        let (d0, d1) = (slice(z), slice(z + 1));
+        let box_min = (a * d0).min(a * d1).min(b * d0).min(b * d1).extend(-d1);
+        let box_max = (a * d0).max(a * d1).max(b * d0).max(b * d1).extend(-d0);
+
+        let view = Mat4::from_cols_array_2d(&params.view);
+        let p = view.transform_point3(Vec3::from(light.position));
+        let d = p.clamp(box_min, box_max) - p;
+        d.length_squared() <= light.range * light.range
+    }
+
+    #[test]
+    fn point_light_lands_in_the_clusters_it_overlaps() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let config = ClusterConfig {
+            dims: [4, 4, 8],
+            max_lights_per_cluster: 4,
+        };
+        renderer.set_cluster_config(config);
+        let cam = camera(1.0);
+        let light = Light {
+            position: [0.6, -0.4, 0.5],
+            range: 0.8,
+            ..Default::default()
+        };
+        // the far light covers no cluster of interest, it only checks the index stored
+        let far = Light {
+            position: [0.0, 0.0, 500.0],
+            range: 1.0,
+            ..Default::default()
+        };
+
+        let target = renderer.create_offscreen_target(64, 64);
+        renderer.render_to(&target, &[far, light], &cam, &[]);
+        let bytes = renderer.asset.read_buffer(&renderer.clusters.grid).unwrap();
+        let grid: Vec<u32> = bytemuck::pod_collect_to_vec(&bytes);
+
+        let params = ClusterParams::new(&config, &cam);
+        let stride = config.max_lights_per_cluster as usize + 1;
+        let mut hit = 0;
+        for index in 0..config.cluster_count() {
+            let [dx, dy, _] = config.dims;
+            let cluster = [index % dx, (index / dx) % dy, index / (dx * dy)];
+            let lights = &grid[index as usize * stride..][..stride];
+            if overlaps(&params, cluster, &light) {
+                assert_eq!(&lights[..2], &[1, 1], "cluster {:?}", cluster);
+                hit += 1;
+            } else {
+                assert_eq!(lights[0], 0, "cluster {:?}", cluster);
+            }
+        }
+        assert!(hit > 0 && hit < config.cluster_count() / 4);
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+
+use super::ForwardRenderer;
+use crate::asset_manager::TextureId;
+use crate::asset_manager::light::{Light, LightKind};
+
+pub const MAX_COOKIES: u32 = 8;
+pub const COOKIE_SIZE: u32 = 256;
+pub const COOKIE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+// Spot light cookies are copied into the layers of one array texture so the forward shader
+// can sample them in both the bindless and the bound texture paths. Each texture is copied
+// (and rescaled) once, the first time a light uses it.
+pub struct CookieAtlas {
+    pub tex: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+    pub layers: HashMap<TextureId, u32>,
+
+    pub blit_pipeline: wgpu::RenderPipeline,
+    pub blit_bgl: wgpu::BindGroupLayout,
+}
+
+impl CookieAtlas {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let tex = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Cookie Atlas"),
+            size: wgpu::Extent3d {
+                width: COOKIE_SIZE,
+                height: COOKIE_SIZE,
+                depth_or_array_layers: MAX_COOKIES,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: COOKIE_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = tex.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Cookie Atlas View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Cookie Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let blit_bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Cookie Blit BGL"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Cookie Blit Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/cookie_blit.wgsl").into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cookie Blit Pipeline Layout"),
+            bind_group_layouts: &[&blit_bgl],
+            push_constant_ranges: &[],
+        });
+        let blit_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Cookie Blit Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: COOKIE_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            tex,
+            view,
+            sampler,
+            layers: HashMap::new(),
+            blit_pipeline,
+            blit_bgl,
+        }
+    }
+
+    // Returns the layer holding `id`, copying `source` into a free layer on first use.
+    // None once every layer is taken.
+    pub fn layer_for(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        id: TextureId,
+        source: &wgpu::TextureView,
+    ) -> Option<u32> {
+        if let Some(&layer) = self.layers.get(&id) {
+            return Some(layer);
+        }
+        let layer = self.layers.len() as u32;
+        if layer >= MAX_COOKIES {
+            return None;
+        }
+
+        let target = self.tex.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Cookie Layer View"),
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        });
+        let bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Cookie Blit BG"),
+            layout: &self.blit_bgl,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Cookie Blit Encoder"),
+        });
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Cookie Blit Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            rpass.set_pipeline(&self.blit_pipeline);
+            rpass.set_bind_group(0, &bg, &[]);
+            rpass.draw(0..3, 0..1);
+        }
+        queue.submit(Some(encoder.finish()));
+
+        self.layers.insert(id, layer);
+        Some(layer)
+    }
+}
+
+impl ForwardRenderer {
+    // Atlas layer of each light's cookie, -1 for lights without one.
+    pub fn cookie_indices(&mut self, lights: &[Light]) -> Vec<i32> {
+        lights
+            .iter()
+            .map(|l| {
+                let (LightKind::Spot, Some(id)) = (l.kind, l.cookie) else {
+                    return -1;
+                };
+                let Some(tex) = self.asset.textures.get(id) else {
+                    eprintln!("cookie: texture {:?} not found", id);
+                    return -1;
+                };
+                match self.cookies.layer_for(
+                    &self.context.device,
+                    &self.context.queue,
+                    id,
+                    &tex.tex_view,
+                ) {
+                    Some(layer) => layer as i32,
+                    None => {
+                        eprintln!("cookie: all {} cookie layers are in use", MAX_COOKIES);
+                        -1
+                    }
+                }
+            })
+            .collect()
+    }
+
+    // Frees every cookie layer; they are filled again from the next frame's lights.
+    pub fn clear_cookies(&mut self) {
+        self.cookies.layers.clear();
+    }
+}
@@ -0,0 +1,75 @@
+use wgpu::util::DeviceExt;
+
+use super::ForwardRenderer;
+
+// Exponential distance fog, measured from the camera position.
+#[derive(Clone, Copy, Debug)]
+pub struct Fog {
+    pub color: [f32; 3],
+    pub density: f32,
+    // distance before which there is no fog
+    pub start: f32,
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            color: [0.5, 0.5, 0.55],
+            density: 0.02,
+            start: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FogUniform {
+    pub color: [f32; 3],
+    pub density: f32,
+    pub start: f32,
+    pub enabled: u32,
+    pub _pad: [u32; 2],
+}
+
+impl From<Option<Fog>> for FogUniform {
+    fn from(fog: Option<Fog>) -> Self {
+        let f = fog.unwrap_or_default();
+        Self {
+            color: f.color,
+            density: f.density,
+            start: f.start,
+            enabled: fog.is_some() as u32,
+            _pad: [0; 2],
+        }
+    }
+}
+
+// How much of the fog color replaces the surface color at `distance`; must match
+// fog_factor in forward.wgsl.
+pub fn fog_factor(fog: &Fog, distance: f32) -> f32 {
+    let d = (distance - fog.start).max(0.0);
+    1.0 - (-fog.density * d).exp()
+}
+
+pub fn create_fog_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("Fog UBO"),
+        contents: bytemuck::bytes_of(&FogUniform::from(None)),
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+impl ForwardRenderer {
+    pub fn set_fog(&mut self, fog: Option<Fog>) {
+        self.fog = fog;
+        self.context.queue.write_buffer(
+            &self.fog_buffer,
+            0,
+            bytemuck::bytes_of(&FogUniform::from(fog)),
+        );
+    }
+
+    pub fn fog(&self) -> Option<Fog> {
+        self.fog
+    }
+}
@@ -0,0 +1,105 @@
+use glam::{Mat4, Vec3, Vec4};
+
+use super::{Camera, ForwardRenderer, RenderCommand};
+use crate::asset_manager::MeshId;
+use crate::asset_manager::light::Light;
+
+// Planes as (normal, distance) with normals pointing inside; a point p is inside a plane
+// when dot(normal, p) + distance >= 0.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    // Extracts the planes from a view-projection with wgpu's 0..1 depth range.
+    pub fn from_view_proj(m: Mat4) -> Self {
+        let r0 = m.row(0);
+        let r1 = m.row(1);
+        let r2 = m.row(2);
+        let r3 = m.row(3);
+
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2].map(|p| {
+            let len = p.truncate().length();
+            if len > 0.0 { p / len } else { p }
+        });
+        Self { planes }
+    }
+
+    pub fn from_camera(cam: &Camera) -> Self {
+        Self::from_view_proj(cam.view_proj())
+    }
+
+    // Conservative: boxes straddling a plane count as visible.
+    pub fn intersects_aabb(&self, min: Vec3, max: Vec3) -> bool {
+        self.planes.iter().all(|p| {
+            let n = p.truncate();
+            // the box corner furthest along the plane normal
+            let corner = Vec3::select(n.cmpge(Vec3::ZERO), max, min);
+            n.dot(corner) + p.w >= 0.0
+        })
+    }
+}
+
+// World-space AABB of a local box transformed by an affine matrix.
+pub fn transform_aabb(m: &Mat4, min: Vec3, max: Vec3) -> (Vec3, Vec3) {
+    let center = (min + max) * 0.5;
+    let extent = (max - min) * 0.5;
+
+    let world_center = m.transform_point3(center);
+    let world_extent = Vec3::new(
+        m.row(0).truncate().abs().dot(extent),
+        m.row(1).truncate().abs().dot(extent),
+        m.row(2).truncate().abs().dot(extent),
+    );
+    (world_center - world_extent, world_center + world_extent)
+}
+
+// Returns the transforms whose transformed mesh bounds touch the frustum, in input order.
+pub fn cull_instances(frustum: &Frustum, min: Vec3, max: Vec3, transforms: &[Mat4]) -> Vec<Mat4> {
+    transforms
+        .iter()
+        .filter(|m| {
+            let (wmin, wmax) = transform_aabb(m, min, max);
+            frustum.intersects_aabb(wmin, wmax)
+        })
+        .copied()
+        .collect()
+}
+
+impl ForwardRenderer {
+    // Draws `mesh_id` once per visible transform as a single instanced run. Returns the
+    // number of instances that survived culling.
+    pub fn render_instanced_culled(
+        &mut self,
+        lights: &[Light],
+        cam: &Camera,
+        mesh_id: MeshId,
+        transforms: &[Mat4],
+    ) -> usize {
+        let Some(mesh) = self.asset.mesh(mesh_id) else {
+            eprintln!("render_instanced_culled: mesh_id {:?} not found", mesh_id);
+            return 0;
+        };
+        let (min, max) = mesh.bounds();
+
+        let visible = cull_instances(
+            &Frustum::from_camera(cam),
+            Vec3::from(min),
+            Vec3::from(max),
+            transforms,
+        );
+        let action: Vec<RenderCommand> = visible
+            .iter()
+            .map(|&transform| RenderCommand {
+                mesh_id,
+                transform,
+                ..Default::default()
+            })
+            .collect();
+
+        self.render(lights, cam, &action);
+        self.stats.culled = transforms.len() - action.len();
+        action.len()
+    }
+}
@@ -3,15 +3,108 @@ use winit::window::Window;
 pub struct GpuContext {
     pub device: Arc<wgpu::Device>,
     pub queue: Arc<wgpu::Queue>,
-    pub surface: wgpu::Surface<'static>,
+    // None for a headless context, see GpuContext::headless
+    pub surface: Option<wgpu::Surface<'static>>,
     pub config: wgpu::SurfaceConfiguration,
+    // true when textures are bound through binding arrays instead of per draw
+    pub bindless: bool,
+    pub adapter_info: wgpu::AdapterInfo,
+    // every format the surface can be configured with
+    pub surface_formats: Vec<wgpu::TextureFormat>,
+    // format of the views rendered into, may differ from config.format in its sRGB suffix
+    pub view_format: wgpu::TextureFormat,
+    // the surface can be viewed in a format other than its own (not on WebGL2)
+    pub surface_view_formats: bool,
+    // physical pixels per logical pixel of the window, see ForwardRenderer::set_scale_factor
+    pub scale_factor: f64,
+}
+
+// Settings the renderer is created with, see ForwardRenderer::with_config.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GpuConfig {
+    // frames the CPU may queue ahead of the display: 1 for the lowest input latency, 3 for
+    // throughput
+    pub max_frame_latency: u32,
+}
+
+impl Default for GpuConfig {
+    fn default() -> Self {
+        Self {
+            max_frame_latency: 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ColorSpace {
+    // tonemapped and gamma encoded, for display
+    #[default]
+    Srgb,
+    // scene-linear HDR values, for compositing in an external pipeline
+    Linear,
+}
+
+// Linear output prefers a float format so HDR values above 1.0 survive.
+pub fn select_surface_format(
+    formats: &[wgpu::TextureFormat],
+    color_space: ColorSpace,
+) -> Option<wgpu::TextureFormat> {
+    match color_space {
+        ColorSpace::Srgb => formats.iter().copied().find(|f| f.is_srgb()),
+        ColorSpace::Linear => formats
+            .iter()
+            .copied()
+            .find(|&f| f == wgpu::TextureFormat::Rgba16Float)
+            .or_else(|| formats.iter().copied().find(|f| !f.is_srgb())),
+    }
+}
+
+// Blending in an sRGB view happens on linear values, with the encode applied on store, so
+// display output renders through an sRGB view whenever the surface allows one. Linear output
+// wants the values stored as written.
+pub fn select_view_format(
+    surface_format: wgpu::TextureFormat,
+    color_space: ColorSpace,
+    surface_view_formats: bool,
+) -> wgpu::TextureFormat {
+    let view_format = match color_space {
+        ColorSpace::Srgb => surface_format.add_srgb_suffix(),
+        ColorSpace::Linear => surface_format.remove_srgb_suffix(),
+    };
+    if surface_view_formats {
+        view_format
+    } else {
+        surface_format
+    }
+}
+
+// Set when display output has to be gamma encoded by the forward shader (ENCODE_SRGB)
+// because neither the surface nor a view of it is sRGB. Colors stay correct, but blending
+// and the line and grid overlays then work on encoded values.
+pub fn srgb_encode_warning(
+    view_format: wgpu::TextureFormat,
+    color_space: ColorSpace,
+) -> Option<String> {
+    (color_space == ColorSpace::Srgb && !view_format.is_srgb()).then(|| {
+        format!(
+            "no sRGB view of {:?} available; encoding sRGB in the shader, so blending is not gamma correct",
+            view_format
+        )
+    })
 }
 
 use anyhow::{Context, Result};
 use winit::dpi::PhysicalSize;
 
+// Material ids come from a uniform, so binding arrays are only ever indexed uniformly and
+// TEXTURE_BINDING_ARRAY is the one feature the bindless path needs.
+pub fn bindless_features(adapter_features: wgpu::Features) -> Option<wgpu::Features> {
+    let required = wgpu::Features::TEXTURE_BINDING_ARRAY;
+    adapter_features.contains(required).then_some(required)
+}
+
 impl GpuContext {
-    pub async fn new(window: &Arc<Window>) -> Result<Self> {
+    pub async fn new(window: &Arc<Window>, gpu_config: &GpuConfig) -> Result<Self> {
         let instance = wgpu::Instance::default();
 
         let surface = instance.create_surface(window.clone()).unwrap();
@@ -24,38 +117,19 @@ impl GpuContext {
             })
             .await
             .context("No suitable GPU adapters found on the system")?;
-
-        //let required_limits = wgpu::Limits::default().using_resolution(adapter.limits());
-        let adapter_limits = adapter.limits();
-
-        let limits = wgpu::Limits {
-            max_binding_array_elements_per_shader_stage: adapter_limits
-                .max_binding_array_elements_per_shader_stage
-                .min(8192),
-            ..wgpu::Limits::downlevel_defaults().using_resolution(adapter_limits)
-        };
-        let features = wgpu::Features::TEXTURE_BINDING_ARRAY
-            | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING
-            | wgpu::Features::TEXTURE_BINDING_ARRAY;
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: Some("Device"),
-                required_features: features,
-                required_limits: limits,
-                ..Default::default()
-            })
-            .await
-            .context("Failed to request device")?;
+        let (device, queue, bindless) = Self::request_device(&adapter).await?;
 
         let size: PhysicalSize<u32> = window.inner_size();
         let surface_caps = surface.get_capabilities(&adapter);
 
-        let surface_format = surface_caps
-            .formats
-            .iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
+        let surface_format = select_surface_format(&surface_caps.formats, ColorSpace::Srgb)
+            .unwrap_or_else(|| {
+                eprintln!(
+                    "gpu: surface offers no sRGB format, using {:?}",
+                    surface_caps.formats[0]
+                );
+                surface_caps.formats[0]
+            });
 
         let present_mode = if surface_caps
             .present_modes
@@ -69,23 +143,156 @@ impl GpuContext {
         let alpha_mode = surface_caps.alpha_modes[0];
 
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            // COPY_SRC where available, for capture_frame
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC),
             format: surface_format,
             width: size.width.max(1),
             height: size.height.max(1),
             present_mode,
-            desired_maximum_frame_latency: 2,
+            desired_maximum_frame_latency: gpu_config.max_frame_latency.max(1),
             alpha_mode,
             view_formats: vec![],
         };
 
-        surface.configure(&device, &config);
+        let surface_view_formats = adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::SURFACE_VIEW_FORMATS);
+
+        let mut ctx = Self {
+            device: Arc::new(device),
+            queue: Arc::new(queue),
+            surface: Some(surface),
+            config,
+            bindless,
+            adapter_info: adapter.get_info(),
+            surface_formats: surface_caps.formats,
+            view_format: surface_format,
+            surface_view_formats,
+            scale_factor: window.scale_factor(),
+        };
+        ctx.configure_surface(surface_format, ColorSpace::Srgb);
+        Ok(ctx)
+    }
+
+    // A context without a window, e.g. for tests: frames are drawn into a scratch texture of
+    // `width` x `height` (see create_headless_frame) and never presented.
+    pub async fn headless(width: u32, height: u32, gpu_config: &GpuConfig) -> Result<Self> {
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions::default())
+            .await
+            .context("No suitable GPU adapters found on the system")?;
+        let (device, queue, bindless) = Self::request_device(&adapter).await?;
 
-        Ok(Self {
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+            desired_maximum_frame_latency: gpu_config.max_frame_latency.max(1),
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+        };
+
+        let mut ctx = Self {
             device: Arc::new(device),
             queue: Arc::new(queue),
-            surface,
+            surface: None,
             config,
+            bindless,
+            adapter_info: adapter.get_info(),
+            surface_formats: vec![
+                format,
+                wgpu::TextureFormat::Rgba8Unorm,
+                wgpu::TextureFormat::Rgba16Float,
+            ],
+            view_format: format,
+            surface_view_formats: true,
+            scale_factor: 1.0,
+        };
+        ctx.configure_surface(format, ColorSpace::Srgb);
+        Ok(ctx)
+    }
+
+    // Requests the device with the features the renderer can use on `adapter`; the bool is
+    // whether bindless texture arrays are among them.
+    async fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue, bool)> {
+        //let required_limits = wgpu::Limits::default().using_resolution(adapter.limits());
+        let adapter_limits = adapter.limits();
+
+        let limits = wgpu::Limits {
+            max_binding_array_elements_per_shader_stage: adapter_limits
+                .max_binding_array_elements_per_shader_stage
+                .min(8192),
+            max_binding_array_sampler_elements_per_shader_stage: adapter_limits
+                .max_binding_array_sampler_elements_per_shader_stage
+                .min(1024),
+            ..wgpu::Limits::downlevel_defaults().using_resolution(adapter_limits)
+        };
+        let bindless = bindless_features(adapter.features());
+        match bindless {
+            Some(_) => println!("gpu: using bindless texture arrays"),
+            None => eprintln!(
+                "gpu: adapter lacks TEXTURE_BINDING_ARRAY; falling back to per-draw texture binding"
+            ),
+        }
+        let mut features = bindless.unwrap_or(wgpu::Features::empty());
+        // lets compiled pipelines be persisted between runs, see render/pipeline_cache.rs
+        if adapter.features().contains(wgpu::Features::PIPELINE_CACHE) {
+            features |= wgpu::Features::PIPELINE_CACHE;
+        }
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("Device"),
+                required_features: features,
+                required_limits: limits,
+                ..Default::default()
+            })
+            .await
+            .context("Failed to request device")?;
+        Ok((device, queue, bindless.is_some()))
+    }
+
+    pub fn configure_surface(&mut self, format: wgpu::TextureFormat, color_space: ColorSpace) {
+        self.view_format = select_view_format(format, color_space, self.surface_view_formats);
+        if let Some(warning) = srgb_encode_warning(self.view_format, color_space) {
+            eprintln!("gpu: {}", warning);
+        }
+        self.config.format = format;
+        self.config.view_formats = if self.view_format != format {
+            vec![self.view_format]
+        } else {
+            vec![]
+        };
+        self.reconfigure();
+    }
+
+    // Applies `config` to the surface, if there is one.
+    pub fn reconfigure(&self) {
+        if let Some(surface) = &self.surface {
+            surface.configure(&self.device, &self.config);
+        }
+    }
+
+    // Stands in for the surface texture of a headless context; render draws into it.
+    pub fn create_headless_frame(&self) -> wgpu::Texture {
+        self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Headless Frame"),
+            size: wgpu::Extent3d {
+                width: self.config.width,
+                height: self.config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.config.format,
+            usage: self.config.usage,
+            view_formats: &self.config.view_formats,
         })
     }
 }
@@ -0,0 +1,393 @@
+use std::sync::mpsc;
+
+use glam::{Mat4, Vec3};
+use wgpu::util::{DeviceExt, DrawIndexedIndirectArgs};
+
+use super::frustum::{Frustum, transform_aabb};
+use super::objects;
+use super::{Camera, ForwardRenderer, RenderCommand};
+use crate::asset_manager::MeshId;
+use crate::asset_manager::light::{Light, MAX_LIGHTS};
+use crate::asset_manager::material::MatId;
+use crate::asset_manager::mesh::ObjectUniform;
+
+const WORKGROUP_SIZE: u32 = 64;
+const ARGS_SIZE: u64 = std::mem::size_of::<DrawIndexedIndirectArgs>() as u64;
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CullParams {
+    pub planes: [[f32; 4]; 6],
+    pub aabb_min: [f32; 4],
+    pub aabb_max: [f32; 4],
+    pub count: u32,
+    pub _pad: [u32; 3],
+}
+
+// Frustum culling on the GPU for large instance counts. A compute pass writes the visible
+// transforms to the front of the object buffer and their count into one indirect draw per
+// primitive, so the CPU never learns which instances survived.
+pub struct GpuCuller {
+    pub bgl: wgpu::BindGroupLayout,
+    pub pipeline: wgpu::ComputePipeline,
+
+    // all instance transforms, grown to the largest set seen
+    pub instances: wgpu::Buffer,
+    pub instance_capacity: usize,
+    pub counter: wgpu::Buffer,
+    // one DrawIndexedIndirectArgs per primitive
+    pub args: wgpu::Buffer,
+    pub args_capacity: usize,
+    pub readback: wgpu::Buffer,
+
+    // mesh drawn from `args` by the next forward pass
+    pub pending: Option<MeshId>,
+}
+
+impl GpuCuller {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("GPU Cull BGL"),
+            entries: &[
+                storage(0, true),
+                storage(1, false),
+                storage(2, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("GPU Cull Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
+                "../../shaders/gpu_cull.wgsl"
+            ))),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("GPU Cull Pipeline Layout"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("GPU Cull Pipeline"),
+            layout: Some(&layout),
+            module: &shader,
+            entry_point: Some("cs_main"),
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let counter = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Cull Counter"),
+            size: 4,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Cull Readback"),
+            size: 4,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            bgl,
+            pipeline,
+            instances: Self::create_instances(device, 1),
+            instance_capacity: 1,
+            counter,
+            args: Self::create_args(device, 1),
+            args_capacity: 1,
+            readback,
+            pending: None,
+        }
+    }
+
+    fn create_instances(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Cull Instances"),
+            size: (capacity * std::mem::size_of::<ObjectUniform>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_args(device: &wgpu::Device, primitives: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Cull Indirect Args"),
+            size: primitives as u64 * ARGS_SIZE,
+            usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn reserve(&mut self, device: &wgpu::Device, instances: usize, primitives: usize) {
+        if instances > self.instance_capacity {
+            self.instance_capacity = instances.next_power_of_two();
+            self.instances = Self::create_instances(device, self.instance_capacity);
+        }
+        if primitives > self.args_capacity {
+            self.args_capacity = primitives.next_power_of_two();
+            self.args = Self::create_args(device, self.args_capacity);
+        }
+    }
+}
+
+impl ForwardRenderer {
+    // Like render_instanced_culled, but the culling runs in a compute pass and the mesh is
+    // drawn with draw_indexed_indirect. The instances don't cast shadows and can't be
+    // picked; read_gpu_visible_count reports how many were drawn.
+    pub fn render_instanced_gpu_culled(
+        &mut self,
+        lights: &[Light],
+        cam: &Camera,
+        mesh_id: MeshId,
+        transforms: &[Mat4],
+    ) -> Option<wgpu::SubmissionIndex> {
+        let Some(mesh) = self.asset.mesh(mesh_id) else {
+            eprintln!(
+                "render_instanced_gpu_culled: mesh_id {:?} not found",
+                mesh_id
+            );
+            return None;
+        };
+        if mesh.index_buf.is_none() {
+            eprintln!(
+                "render_instanced_gpu_culled: mesh {:?} has no index buffer",
+                mesh.name
+            );
+            return None;
+        }
+        let (min, max) = mesh.bounds();
+        let primitive_count = mesh.primitives.len();
+
+        let device = &self.context.device;
+        self.gpu_cull
+            .get_or_insert_with(|| GpuCuller::new(device))
+            .reserve(device, transforms.len(), primitive_count);
+        if self.objects.reserve(device, transforms.len()) {
+            self.rebuild_scene_bind_group();
+        }
+
+        let device = &self.context.device;
+        let queue = &self.context.queue;
+        let cull = self.gpu_cull.as_ref()?;
+        let mesh = self.asset.mesh(mesh_id)?;
+
+        let lights = &lights[..lights.len().min(MAX_LIGHTS)];
+        let instances: Vec<ObjectUniform> = transforms
+            .iter()
+            .map(|m| {
+                let (wmin, wmax) = transform_aabb(m, Vec3::from(min), Vec3::from(max));
+                ObjectUniform::new(*m, objects::light_mask(lights, wmin, wmax))
+            })
+            .collect();
+        if !instances.is_empty() {
+            queue.write_buffer(&cull.instances, 0, bytemuck::cast_slice(&instances));
+        }
+        queue.write_buffer(&cull.counter, 0, bytemuck::bytes_of(&0u32));
+
+        let args: Vec<u8> = mesh
+            .primitives
+            .iter()
+            .flat_map(|p| {
+                DrawIndexedIndirectArgs {
+                    index_count: p.index_count,
+                    instance_count: 0,
+                    first_index: p.first_index,
+                    base_vertex: p.base_vertex,
+                    first_instance: 0,
+                }
+                .as_bytes()
+                .to_vec()
+            })
+            .collect();
+        queue.write_buffer(&cull.args, 0, &args);
+
+        let frustum = Frustum::from_camera(cam);
+        let params = CullParams {
+            planes: frustum.planes.map(|p| p.to_array()),
+            aabb_min: Vec3::from(min).extend(0.0).to_array(),
+            aabb_max: Vec3::from(max).extend(0.0).to_array(),
+            count: transforms.len() as u32,
+            _pad: [0; 3],
+        };
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("GPU Cull Params"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("GPU Cull BG"),
+            layout: &cull.bgl,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: cull.instances.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.objects.buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: cull.counter.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("GPU Cull Encoder"),
+        });
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("GPU Cull Pass"),
+                timestamp_writes: None,
+            });
+            cpass.set_pipeline(&cull.pipeline);
+            cpass.set_bind_group(0, &bg, &[]);
+            cpass.dispatch_workgroups((transforms.len() as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+        }
+        // every primitive draws the same visible instances
+        for i in 0..primitive_count as u64 {
+            encoder.copy_buffer_to_buffer(&cull.counter, 0, &cull.args, i * ARGS_SIZE + 4, 4);
+        }
+        encoder.copy_buffer_to_buffer(&cull.counter, 0, &cull.readback, 0, 4);
+        queue.submit(Some(encoder.finish()));
+
+        if let Some(cull) = self.gpu_cull.as_mut() {
+            cull.pending = Some(mesh_id);
+        }
+        self.ensure_pipelines_for(&[RenderCommand {
+            mesh_id,
+            ..Default::default()
+        }]);
+        let index = self.render(lights, cam, &[]);
+        if let Some(cull) = self.gpu_cull.as_mut() {
+            cull.pending = None;
+        }
+        index
+    }
+
+    // Draws the pending GPU-culled mesh inside the forward pass. Returns the draw count.
+    pub fn draw_gpu_culled(&self, rpass: &mut wgpu::RenderPass) -> usize {
+        let Some(cull) = &self.gpu_cull else {
+            return 0;
+        };
+        let Some(mesh) = cull.pending.and_then(|id| self.asset.mesh(id)) else {
+            return 0;
+        };
+        let (Some(index_buf), Some(index_fmt)) = (mesh.index_buf.as_ref(), mesh.index_format)
+        else {
+            return 0;
+        };
+
+        rpass.set_vertex_buffer(0, mesh.vertex_buf.slice(..));
+        rpass.set_index_buffer(index_buf.slice(..), index_fmt);
+        let mut current_material = None;
+        for (i, p) in mesh.primitives.iter().enumerate() {
+            rpass.set_pipeline(&self.pipelines[&self.pipeline_key(p)]);
+
+            let offset = (p.material.0 * std::mem::size_of::<MatId>()) as u32;
+            rpass.set_bind_group(3, &self.mat_id_bg, &[offset]);
+            if self.bindless.is_none() && current_material != Some(p.material.0) {
+                self.bind_texture_group(rpass, p.material.0);
+                current_material = Some(p.material.0);
+            }
+
+            rpass.draw_indexed_indirect(&cull.args, i as u64 * ARGS_SIZE);
+        }
+        mesh.primitives.len()
+    }
+
+    // Instances drawn by the last render_instanced_gpu_culled. Blocks on the GPU, so
+    // returns None where that can't be waited on (WebGPU).
+    pub fn read_gpu_visible_count(&self) -> Option<u32> {
+        let cull = self.gpu_cull.as_ref()?;
+        let slice = cull.readback.slice(..);
+        let (tx, rx) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        if self.context.device.poll(wgpu::PollType::Wait).is_err() {
+            eprintln!("read_gpu_visible_count: device poll failed");
+        }
+        let Ok(Ok(())) = rx.try_recv() else {
+            return None;
+        };
+
+        let count = {
+            let data = slice.get_mapped_range();
+            u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+        };
+        cull.readback.unmap();
+        Some(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::frustum::cull_instances;
+    use crate::render::test_support::*;
+
+    #[test]
+    fn gpu_visible_count_matches_cpu_culling() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let cube = cube(&mut renderer, "cube");
+        let (min, max) = renderer.asset.mesh(cube).unwrap().bounds();
+        // a 16x16 field of cubes around the origin, partly behind and beside the camera
+        let transforms: Vec<Mat4> = (0..256)
+            .map(|i| {
+                let (x, z) = ((i % 16) as f32, (i / 16) as f32);
+                Mat4::from_translation(Vec3::new(x * 1.7 - 13.0, 0.3, z * 1.7 - 13.0))
+            })
+            .collect();
+
+        for cam in [camera(1.0), Camera::default()] {
+            let expected = cull_instances(
+                &Frustum::from_camera(&cam),
+                Vec3::from(min),
+                Vec3::from(max),
+                &transforms,
+            )
+            .len();
+            assert!(expected > 0 && expected < transforms.len());
+
+            renderer
+                .render_instanced_gpu_culled(&[light()], &cam, cube, &transforms)
+                .unwrap();
+            assert_eq!(renderer.read_gpu_visible_count(), Some(expected as u32));
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use glam::Mat4;
+use wgpu::util::DeviceExt;
+
+use super::{Camera, DEPTH_FORMAT, ForwardRenderer, depth_compare, reverse_z_clip};
+
+#[derive(Clone, Copy, Debug)]
+pub struct GridConfig {
+    // world units between grid lines
+    pub cell_size: f32,
+    // line color, alpha scales the line opacity
+    pub color: [f32; 4],
+    // distance from the camera at which the grid has faded out completely
+    pub fade_distance: f32,
+    // world y of the ground plane
+    pub height: f32,
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        Self {
+            cell_size: 1.0,
+            color: [0.5, 0.5, 0.5, 0.6],
+            fade_distance: 50.0,
+            height: 0.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GridUniform {
+    pub view_proj: [[f32; 4]; 4],
+    pub inv_view_proj: [[f32; 4]; 4],
+    pub camera_pos: [f32; 3],
+    pub cell_size: f32,
+    pub color: [f32; 4],
+    pub fade_distance: f32,
+    pub height: f32,
+    pub _pad: [f32; 2],
+}
+
+impl GridUniform {
+    // The near/far rays unproject through the camera's own projection; only the depth the
+    // grid writes goes through reverse_z_clip.
+    pub fn new(config: &GridConfig, cam: &Camera, reverse_z: bool) -> Self {
+        let view_proj: Mat4 = cam.view_proj();
+        Self {
+            view_proj: (reverse_z_clip(reverse_z) * view_proj).to_cols_array_2d(),
+            inv_view_proj: view_proj.inverse().to_cols_array_2d(),
+            camera_pos: cam.eye.to_array(),
+            cell_size: config.cell_size,
+            color: config.color,
+            fade_distance: config.fade_distance,
+            height: config.height,
+            _pad: [0.0; 2],
+        }
+    }
+}
+
+pub struct GroundGrid {
+    pub config: GridConfig,
+    pub reverse_z: bool,
+    pub pipeline: wgpu::RenderPipeline,
+    pub buffer: wgpu::Buffer,
+    pub bg: wgpu::BindGroup,
+}
+
+impl GroundGrid {
+    pub fn new(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        config: GridConfig,
+        reverse_z: bool,
+    ) -> Self {
+        let bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Grid BGL"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Grid UBO"),
+            contents: bytemuck::bytes_of(&GridUniform::new(&config, &Camera::default(), reverse_z)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Grid BG"),
+            layout: &bgl,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Grid Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
+                "../../shaders/grid.wgsl"
+            ))),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Grid Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            // hidden behind meshes, but transparent so it doesn't occlude anything drawn later
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: depth_compare(wgpu::CompareFunction::Less, reverse_z),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            config,
+            reverse_z,
+            pipeline,
+            buffer,
+            bg,
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, cam: &Camera) {
+        queue.write_buffer(
+            &self.buffer,
+            0,
+            bytemuck::bytes_of(&GridUniform::new(&self.config, cam, self.reverse_z)),
+        );
+    }
+
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass) {
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bg, &[]);
+        rpass.draw(0..3, 0..1);
+    }
+}
+
+impl ForwardRenderer {
+    // Shows an infinite reference grid on the ground plane, or hides it with None. The
+    // pipeline is only created the first time a grid is configured.
+    pub fn set_ground_grid(&mut self, config: Option<GridConfig>) {
+        match (config, self.grid.as_mut()) {
+            (None, _) => self.grid = None,
+            (Some(config), Some(grid)) => grid.config = config,
+            (Some(config), None) => {
+                self.grid = Some(GroundGrid::new(
+                    &self.context.device,
+                    self.context.view_format,
+                    config,
+                    self.pipeline_config.reverse_z,
+                ))
+            }
+        }
+    }
+
+    pub fn ground_grid(&self) -> Option<GridConfig> {
+        self.grid.as_ref().map(|g| g.config)
+    }
+}
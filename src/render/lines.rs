@@ -0,0 +1,253 @@
+use glam::Vec3;
+
+use super::{Camera, DEPTH_FORMAT, ForwardRenderer, depth_compare, reverse_z_clip};
+
+// Every segment is drawn as one instance of a two-triangle quad.
+pub const LINE_QUAD_VERTICES: u32 = 6;
+
+pub fn line_vertex_count(segments: usize) -> u32 {
+    segments as u32 * LINE_QUAD_VERTICES
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineWidth {
+    // constant on screen regardless of distance, in logical pixels
+    Pixels(f32),
+    // thickness in world units, shrinking with distance like regular geometry
+    World(f32),
+}
+
+impl Default for LineWidth {
+    fn default() -> Self {
+        LineWidth::Pixels(2.0)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineSegment {
+    pub a: [f32; 3],
+    pub b: [f32; 3],
+    pub color: [f32; 4],
+}
+
+impl LineSegment {
+    pub const ATTRS: [wgpu::VertexAttribute; 3] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x3,
+        2 => Float32x4,
+    ];
+
+    pub fn buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineSegment>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRS,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineUniform {
+    pub view_proj: [[f32; 4]; 4],
+    pub camera_pos: [f32; 3],
+    pub width: f32,
+    pub viewport: [f32; 2],
+    pub mode: u32,
+    pub _pad: u32,
+}
+
+// Immediate-mode debug lines: segments queued with draw_line are drawn by the next render
+// and then discarded.
+pub struct LineRenderer {
+    pub width: LineWidth,
+    // physical pixels per logical pixel, applied to LineWidth::Pixels
+    pub scale_factor: f32,
+    pub pending: Vec<LineSegment>,
+    // matches the renderer's PipelineConfig::reverse_z
+    pub reverse_z: bool,
+
+    pub pipeline: wgpu::RenderPipeline,
+    pub bgl: wgpu::BindGroupLayout,
+    pub uniform: wgpu::Buffer,
+    pub bg: wgpu::BindGroup,
+    pub instances: wgpu::Buffer,
+    pub capacity: usize,
+}
+
+impl LineRenderer {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+        let bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Line BGL"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Line UBO"),
+            size: std::mem::size_of::<LineUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Line BG"),
+            layout: &bgl,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform.as_entire_binding(),
+            }],
+        });
+
+        let capacity = 256;
+        let instances = Self::create_instances(device, capacity);
+        let pipeline = Self::create_pipeline(device, &bgl, format, false);
+
+        Self {
+            width: LineWidth::default(),
+            scale_factor: 1.0,
+            pending: Vec::new(),
+            reverse_z: false,
+            pipeline,
+            bgl,
+            uniform,
+            bg,
+            instances,
+            capacity,
+        }
+    }
+
+    fn create_instances(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Line Instances"),
+            size: (capacity * std::mem::size_of::<LineSegment>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn create_pipeline(
+        device: &wgpu::Device,
+        bgl: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        reverse_z: bool,
+    ) -> wgpu::RenderPipeline {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Line Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
+                "../../shaders/line.wgsl"
+            ))),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Line Pipeline Layout"),
+            bind_group_layouts: &[bgl],
+            push_constant_ranges: &[],
+        });
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Line Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                buffers: &[LineSegment::buffer_layout()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            // quads may face either way depending on the segment direction
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: depth_compare(wgpu::CompareFunction::LessEqual, reverse_z),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // Uploads the queued segments and this frame's camera; returns how many to draw.
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cam: &Camera,
+        viewport: [f32; 2],
+    ) -> u32 {
+        if self.pending.is_empty() {
+            return 0;
+        }
+        if self.pending.len() > self.capacity {
+            self.capacity = self.pending.len().next_power_of_two();
+            self.instances = Self::create_instances(device, self.capacity);
+        }
+        queue.write_buffer(&self.instances, 0, bytemuck::cast_slice(&self.pending));
+
+        let (mode, width) = match self.width {
+            LineWidth::Pixels(w) => (0, w * self.scale_factor),
+            LineWidth::World(w) => (1, w),
+        };
+        let uniform = LineUniform {
+            view_proj: (reverse_z_clip(self.reverse_z) * cam.view_proj()).to_cols_array_2d(),
+            camera_pos: cam.eye.to_array(),
+            width,
+            viewport,
+            mode,
+            _pad: 0,
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::bytes_of(&uniform));
+
+        self.pending.len() as u32
+    }
+
+    pub fn draw(&self, rpass: &mut wgpu::RenderPass, count: u32) {
+        if count == 0 {
+            return;
+        }
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bg, &[]);
+        rpass.set_vertex_buffer(0, self.instances.slice(..));
+        rpass.draw(0..LINE_QUAD_VERTICES, 0..count);
+    }
+}
+
+impl ForwardRenderer {
+    // Queues a line for the next frame only.
+    pub fn draw_line(&mut self, a: Vec3, b: Vec3, color: [f32; 4]) {
+        self.lines.pending.push(LineSegment {
+            a: a.to_array(),
+            b: b.to_array(),
+            color,
+        });
+    }
+
+    pub fn set_line_width(&mut self, width: LineWidth) {
+        self.lines.width = width;
+    }
+
+    pub fn line_width(&self) -> LineWidth {
+        self.lines.width
+    }
+}
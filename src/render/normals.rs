@@ -0,0 +1,307 @@
+use wgpu::util::DeviceExt;
+
+use super::ForwardRenderer;
+use crate::asset_manager::MeshId;
+use crate::asset_manager::packing::VertexFormat;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct NormalParams {
+    pub vertex_count: u32,
+    pub index_count: u32,
+    pub index_mode: u32,
+    pub _pad: u32,
+}
+
+// Compute pipelines that rebuild a mesh's vertex normals from its positions and indices.
+pub struct NormalCompute {
+    pub bgl: wgpu::BindGroupLayout,
+    pub clear: wgpu::ComputePipeline,
+    pub accumulate: wgpu::ComputePipeline,
+    pub resolve: wgpu::ComputePipeline,
+
+    // fixed point accumulators, grown to the largest mesh seen
+    pub accum: wgpu::Buffer,
+    pub accum_vertices: u32,
+    // bound in place of the index buffer for non-indexed meshes
+    pub empty_indices: wgpu::Buffer,
+}
+
+impl NormalCompute {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Normal Compute BGL"),
+            entries: &[
+                storage(0, false),
+                storage(1, true),
+                storage(2, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Normal Compute Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
+                "../../shaders/recompute_normals.wgsl"
+            ))),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Normal Compute Pipeline Layout"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+                cache: None,
+            })
+        };
+
+        let clear = pipeline("Normal Clear Pipeline", "cs_clear");
+        let accumulate = pipeline("Normal Accumulate Pipeline", "cs_accumulate");
+        let resolve = pipeline("Normal Resolve Pipeline", "cs_resolve");
+
+        let accum = Self::create_accum(device, 1);
+        let empty_indices = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Normal Compute Empty Indices"),
+            size: 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            bgl,
+            clear,
+            accumulate,
+            resolve,
+            accum,
+            accum_vertices: 1,
+            empty_indices,
+        }
+    }
+
+    fn create_accum(device: &wgpu::Device, vertices: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Normal Accumulators"),
+            size: (vertices as u64) * 3 * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn reserve(&mut self, device: &wgpu::Device, vertices: u32) {
+        if vertices > self.accum_vertices {
+            self.accum_vertices = vertices.next_power_of_two();
+            self.accum = Self::create_accum(device, self.accum_vertices);
+        }
+    }
+}
+
+impl ForwardRenderer {
+    // Rebuilds the mesh's normals on the GPU by averaging the unit normals of the adjacent
+    // triangles. Faces are weighted equally, not by area or angle.
+    pub fn recompute_normals_gpu(&mut self, mesh_id: MeshId) {
+        let device = &self.context.device;
+        let Some(mesh) = self.asset.meshes.get(mesh_id) else {
+            eprintln!("recompute_normals_gpu: mesh_id {:?} not found", mesh_id);
+            return;
+        };
+        if mesh.vertex_count == 0 {
+            return;
+        }
+        // the compute passes read and write the full Vertex layout
+        if mesh.vertex_format != VertexFormat::Full {
+            eprintln!(
+                "recompute_normals_gpu: mesh {:?} uses packed vertices; skipping",
+                mesh.name
+            );
+            return;
+        }
+        // the accumulate pass reads indices as a triangle list
+        if mesh
+            .primitives
+            .iter()
+            .any(|p| p.topology != wgpu::PrimitiveTopology::TriangleList)
+        {
+            eprintln!(
+                "recompute_normals_gpu: mesh {:?} has strip primitives; skipping",
+                mesh.name
+            );
+            return;
+        }
+
+        self.normals.reserve(device, mesh.vertex_count);
+
+        let (indices, index_mode, index_count) = match (&mesh.index_buf, mesh.index_format) {
+            (Some(ib), Some(wgpu::IndexFormat::Uint16)) => (ib, 1, mesh.index_count),
+            (Some(ib), Some(wgpu::IndexFormat::Uint32)) => (ib, 2, mesh.index_count),
+            _ => (&self.normals.empty_indices, 0, mesh.vertex_count),
+        };
+
+        let params = NormalParams {
+            vertex_count: mesh.vertex_count,
+            index_count,
+            index_mode,
+            _pad: 0,
+        };
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Normal Compute Params"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let bg = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Normal Compute BG"),
+            layout: &self.normals.bgl,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: mesh.vertex_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: indices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.normals.accum.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let vertex_groups = mesh.vertex_count.div_ceil(WORKGROUP_SIZE);
+        let triangle_groups = (index_count / 3).div_ceil(WORKGROUP_SIZE);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Normal Compute Encoder"),
+        });
+        {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Normal Compute Pass"),
+                timestamp_writes: None,
+            });
+            cpass.set_bind_group(0, &bg, &[]);
+
+            cpass.set_pipeline(&self.normals.clear);
+            cpass.dispatch_workgroups(vertex_groups, 1, 1);
+
+            if triangle_groups > 0 {
+                cpass.set_pipeline(&self.normals.accumulate);
+                cpass.dispatch_workgroups(triangle_groups, 1, 1);
+            }
+
+            cpass.set_pipeline(&self.normals.resolve);
+            cpass.dispatch_workgroups(vertex_groups, 1, 1);
+        }
+        self.context.queue.submit(Some(encoder.finish()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use crate::asset_manager::mesh::{Index, Primitive, Vertex};
+    use crate::render::test_support::*;
+
+    // The same average the shader computes: each adjacent face's unit normal, equally weighted.
+    fn cpu_normals(vertices: &[Vertex], triangles: &[Index]) -> Vec<Vec3> {
+        let mut sums = vec![Vec3::ZERO; vertices.len()];
+        for t in triangles {
+            let [a, b, c] = t.idx.map(|i| Vec3::from(vertices[i as usize].position));
+            let n = (b - a).cross(c - a).normalize();
+            for i in t.idx {
+                sums[i as usize] += n;
+            }
+        }
+        sums.iter().map(|s| s.normalize_or(Vec3::Y)).collect()
+    }
+
+    #[test]
+    fn gpu_normals_match_a_cpu_reference() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        // a bumpy 5x5 height field with zeroed normals, plus one unreferenced vertex
+        let mut vertex: Vec<Vertex> = (0..25)
+            .map(|i| {
+                let (x, z) = ((i % 5) as f32, (i / 5) as f32);
+                Vertex {
+                    position: [x, (x * 1.3).sin() * (z * 0.7).cos(), z],
+                    uv: [0.0; 2],
+                    normal: [0.0; 3],
+                    tangent: [1.0, 0.0, 0.0, 1.0],
+                }
+            })
+            .collect();
+        vertex.push(Vertex {
+            position: [9.0, 9.0, 9.0],
+            ..vertex[0]
+        });
+        let mut index = Vec::new();
+        for z in 0..4 {
+            for x in 0..4 {
+                let i = z * 5 + x;
+                index.push(Index {
+                    idx: [i, i + 5, i + 1],
+                });
+                index.push(Index {
+                    idx: [i + 1, i + 5, i + 6],
+                });
+            }
+        }
+        let expected = cpu_normals(&vertex, &index);
+        let mesh = renderer
+            .asset
+            .set_mesh(
+                &[Primitive {
+                    vertex,
+                    index,
+                    ..Default::default()
+                }],
+                "height field",
+            )
+            .unwrap();
+
+        renderer.recompute_normals_gpu(mesh);
+        let (vertices, _) = renderer.asset.read_mesh_data(mesh).unwrap();
+        assert_eq!(vertices.len(), expected.len());
+        for (v, expected) in vertices.iter().zip(&expected) {
+            let n = Vec3::from(v.normal);
+            assert!((n - *expected).length() < 1e-3, "{:?} != {:?}", n, expected);
+        }
+        assert_eq!(vertices.last().unwrap().normal, [0.0, 1.0, 0.0]);
+    }
+}
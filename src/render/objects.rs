@@ -0,0 +1,181 @@
+use std::ops::Range;
+
+use glam::Vec3;
+
+use super::RenderCommand;
+use crate::asset_manager::MeshId;
+use crate::asset_manager::light::{Light, LightKind};
+use crate::asset_manager::mesh::ObjectUniform;
+
+// Stable order of `action` by sort_key, or None when it's already in order.
+pub fn sort_order(action: &[RenderCommand]) -> Option<Vec<usize>> {
+    if action.is_sorted_by_key(|c| c.sort_key) {
+        return None;
+    }
+    let mut order: Vec<usize> = (0..action.len()).collect();
+    order.sort_by_key(|&i| action[i].sort_key);
+    Some(order)
+}
+
+// Light mask that lets every light through, for objects whose bounds aren't known.
+pub const ALL_LIGHTS: u32 = u32::MAX;
+
+// Groups consecutive commands that share a mesh and scissor rect so each group is one
+// instanced draw over its object indices.
+pub fn mesh_runs(action: &[RenderCommand]) -> Vec<(MeshId, Range<u32>)> {
+    let mut runs: Vec<(MeshId, Range<u32>)> = Vec::new();
+    for (i, cmd) in action.iter().enumerate() {
+        let i = i as u32;
+        match runs.last_mut() {
+            Some((mesh_id, range))
+                if *mesh_id == cmd.mesh_id
+                    && action[range.start as usize].scissor == cmd.scissor =>
+            {
+                range.end = i + 1
+            }
+            _ => runs.push((cmd.mesh_id, i..i + 1)),
+        }
+    }
+    runs
+}
+
+// Bit i is set when lights[i] can reach the world-space box `min`..`max`. Directional
+// lights always can; point and spot lights when their range sphere touches the box (the
+// spot cone itself isn't tested). Lights past the 32nd are never masked out.
+pub fn light_mask(lights: &[Light], min: Vec3, max: Vec3) -> u32 {
+    let mut mask = ALL_LIGHTS;
+    for (i, l) in lights.iter().enumerate().take(32) {
+        let reaches = match l.kind {
+            LightKind::Directional => true,
+            LightKind::Point | LightKind::Spot => {
+                let pos = Vec3::from(l.position);
+                !l.range.is_finite()
+                    || pos.clamp(min, max).distance_squared(pos) <= l.range * l.range
+            }
+        };
+        if !reaches {
+            mask &= !(1 << i);
+        }
+    }
+    mask
+}
+
+// Per-object model matrices and light masks, uploaded once per frame and indexed in the
+// shaders through the draw's instance index.
+pub struct ObjectBuffer {
+    pub buffer: wgpu::Buffer,
+    pub capacity: usize,
+    pub bgl: wgpu::BindGroupLayout,
+    pub bg: wgpu::BindGroup,
+}
+
+impl ObjectBuffer {
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let buffer = Self::create_buffer(device, capacity);
+
+        let bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Object BGL"),
+            entries: &[Self::layout_entry(0)],
+        });
+        let bg = Self::create_bind_group(device, &bgl, &buffer);
+
+        Self {
+            buffer,
+            capacity,
+            bgl,
+            bg,
+        }
+    }
+
+    pub fn layout_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Object Buffer"),
+            size: (capacity * std::mem::size_of::<ObjectUniform>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        bgl: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Object BG"),
+            layout: bgl,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, capacity: usize) {
+        self.capacity = capacity.max(1);
+        self.buffer = Self::create_buffer(device, self.capacity);
+        self.bg = Self::create_bind_group(device, &self.bgl, &self.buffer);
+    }
+
+    // Grows to the next power of two when `count` objects don't fit. Returns true if the
+    // buffer was reallocated, in which case bind groups referencing it must be rebuilt.
+    pub fn reserve(&mut self, device: &wgpu::Device, count: usize) -> bool {
+        if count <= self.capacity {
+            return false;
+        }
+        let capacity = count.next_power_of_two();
+        eprintln!(
+            "render: {} objects exceed max_objects {}; growing object buffer to {}",
+            count, self.capacity, capacity
+        );
+        self.resize(device, capacity);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::test_support::*;
+
+    #[test]
+    fn object_buffer_holds_max_objects_and_grows_on_overflow() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let object_size = std::mem::size_of::<ObjectUniform>() as u64;
+        renderer.set_max_objects(4);
+        assert_eq!(renderer.max_objects(), 4);
+        assert_eq!(renderer.objects.buffer.size(), 4 * object_size);
+
+        let cube = cube(&mut renderer, "cube");
+        let target = renderer.create_offscreen_target(32, 32);
+        let cam = camera(1.0);
+        renderer.render_to(&target, &[light()], &cam, &[draw(cube, Vec3::ZERO)]);
+        let single = renderer.read_frame(&target).unwrap();
+
+        // nine objects behind the camera, then the visible one past the configured count
+        let mut commands: Vec<RenderCommand> = (0..9)
+            .map(|i| draw(cube, Vec3::new(i as f32, 0.0, 10.0)))
+            .collect();
+        commands.push(draw(cube, Vec3::ZERO));
+        renderer.render_to(&target, &[light()], &cam, &commands);
+        assert_eq!(renderer.max_objects(), 16);
+        assert_eq!(renderer.objects.buffer.size(), 16 * object_size);
+        assert!(renderer.read_frame(&target).unwrap() == single);
+    }
+}
@@ -0,0 +1,251 @@
+use std::sync::mpsc;
+
+use super::{Camera, ForwardRenderer, RenderCommand, objects};
+use crate::asset_manager::light::Light;
+
+// A fixed-size color + depth target the renderer can draw into without a surface, e.g. for
+// recording frames to a video file. Each render_to copies the frame into `readback`, which
+// read_frame maps.
+pub struct OffscreenTarget {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat,
+    pub color: wgpu::Texture,
+    pub color_view: wgpu::TextureView,
+    pub depth: wgpu::Texture,
+    pub depth_view: wgpu::TextureView,
+    pub readback: wgpu::Buffer,
+    // rows in `readback` are padded to COPY_BYTES_PER_ROW_ALIGNMENT
+    pub padded_bytes_per_row: u32,
+}
+
+impl OffscreenTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+    ) -> Self {
+        let width = width.max(1);
+        let height = height.max(1);
+
+        let color = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen Color"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let color_view = color.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let (depth, depth_view) = ForwardRenderer::create_depth_target(device, width, height);
+
+        let bytes_per_pixel = format.block_copy_size(None).unwrap_or(4);
+        let padded_bytes_per_row = (width * bytes_per_pixel)
+            .div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen Readback"),
+            size: padded_bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            width,
+            height,
+            format,
+            color,
+            color_view,
+            depth,
+            depth_view,
+            readback,
+            padded_bytes_per_row,
+        }
+    }
+
+    pub fn bytes_per_pixel(&self) -> u32 {
+        self.format.block_copy_size(None).unwrap_or(4)
+    }
+}
+
+impl ForwardRenderer {
+    // The target uses the pipeline format, so the forward pipelines can draw into it.
+    pub fn create_offscreen_target(&self, width: u32, height: u32) -> OffscreenTarget {
+        OffscreenTarget::new(
+            &self.context.device,
+            width,
+            height,
+            self.pipeline_config.format,
+        )
+    }
+
+    // Draws a frame into `target` instead of the surface; nothing is presented and the
+    // picking target is left untouched. `cam.aspect` should match the target's size.
+    pub fn render_to(
+        &mut self,
+        target: &OffscreenTarget,
+        lights: &[Light],
+        cam: &Camera,
+        action: &[RenderCommand],
+    ) -> wgpu::SubmissionIndex {
+        if target.format != self.pipeline_config.format {
+            eprintln!(
+                "render_to: target format {:?} doesn't match the pipeline format {:?}",
+                target.format, self.pipeline_config.format
+            );
+        }
+
+        let sorted: Vec<RenderCommand>;
+        let action = match objects::sort_order(action) {
+            Some(order) => {
+                sorted = order.iter().map(|&i| action[i]).collect();
+                &sorted[..]
+            }
+            None => action,
+        };
+
+        let (stream_commands, casters) = self.prepare_frame(lights, cam, action);
+
+        let device = &self.context.device;
+        let queue = &self.context.queue;
+        let viewport = [target.width as f32, target.height as f32];
+        let line_count = self.lines.upload(device, queue, cam, viewport);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Offscreen Encoder"),
+        });
+        self.stats = self.encode_frame(
+            &mut encoder,
+            lights,
+            &casters,
+            action,
+            &target.color_view,
+            &target.depth_view,
+            [target.width, target.height],
+            line_count,
+        );
+
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &target.color,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &target.readback,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(target.padded_bytes_per_row),
+                    rows_per_image: Some(target.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: target.width,
+                height: target.height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let queue = &self.context.queue;
+        let index = queue.submit(stream_commands.into_iter().chain(Some(encoder.finish())));
+        self.stream_belt.recall();
+        self.lines.pending.clear();
+        index
+    }
+
+    // Returns the last frame rendered into `target` as tightly packed rows in the target's
+    // format. Blocks on the GPU, so returns None where that can't be waited on (WebGPU).
+    pub fn read_frame(&self, target: &OffscreenTarget) -> Option<Vec<u8>> {
+        read_rows(
+            &self.context.device,
+            &target.readback,
+            target.padded_bytes_per_row,
+            target.width * target.bytes_per_pixel(),
+        )
+    }
+}
+
+// Maps `buffer` and strips the padding off each of its rows. Blocks on the GPU, so returns
+// None where that can't be waited on (WebGPU).
+pub fn read_rows(
+    device: &wgpu::Device,
+    buffer: &wgpu::Buffer,
+    padded_bytes_per_row: u32,
+    row_bytes: u32,
+) -> Option<Vec<u8>> {
+    let slice = buffer.slice(..);
+    let (tx, rx) = mpsc::channel();
+    slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = tx.send(result);
+    });
+    if device.poll(wgpu::PollType::Wait).is_err() {
+        eprintln!("read_frame: device poll failed");
+    }
+    let Ok(Ok(())) = rx.try_recv() else {
+        return None;
+    };
+
+    let row_bytes = row_bytes as usize;
+    let mut pixels =
+        Vec::with_capacity(row_bytes * (buffer.size() / padded_bytes_per_row as u64) as usize);
+    {
+        let data = slice.get_mapped_range();
+        for row in data.chunks(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..row_bytes]);
+        }
+    }
+    buffer.unmap();
+    Some(pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::test_support::*;
+    use glam::Vec3;
+
+    #[test]
+    fn renders_and_reads_back_consecutive_frames() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let cube = cube(&mut renderer, "cube");
+        let target = renderer.create_offscreen_target(32, 32);
+        assert_eq!(target.bytes_per_pixel(), 4);
+        let cam = camera(1.0);
+
+        let mut frames = Vec::new();
+        for x in [-1.0, 0.0, 1.0] {
+            renderer.render_to(
+                &target,
+                &[light()],
+                &cam,
+                &[draw(cube, Vec3::new(x, 0.0, 0.0))],
+            );
+            let frame = renderer.read_frame(&target).unwrap();
+            assert_eq!(frame.len(), 32 * 32 * 4);
+            frames.push(frame);
+        }
+
+        // the cube moves left to right, so each frame covers a different column
+        let center = |frame: &[u8]| pixel(frame, 32, 16, 16);
+        assert_eq!(center(&frames[0]), center(&frames[2]));
+        assert_ne!(center(&frames[0]), center(&frames[1]));
+        assert_ne!(frames[0], frames[2]);
+
+        // the headless frame renders through the regular path as well
+        assert!(
+            renderer
+                .render(&[light()], &cam, &[draw(cube, Vec3::ZERO)])
+                .is_some()
+        );
+    }
+}
@@ -0,0 +1,362 @@
+use std::sync::mpsc;
+
+use super::{DEPTH_FORMAT, ForwardRenderer, RenderCommand, ScissorRect, depth_compare};
+use crate::asset_manager::MeshId;
+use crate::asset_manager::mesh::{PrimitiveRange, Vertex};
+use crate::asset_manager::packing::{PackedVertex, VertexFormat};
+
+pub const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+// Object id target drawn after the forward pass, for pixel-exact picking.
+pub struct IdTarget {
+    pub tex: wgpu::Texture,
+    pub view: wgpu::TextureView,
+    pub pipeline: wgpu::RenderPipeline,
+    pub pipeline_strip: wgpu::RenderPipeline,
+    // both topologies again for meshes in the packed vertex format
+    pub pipeline_packed: wgpu::RenderPipeline,
+    pub pipeline_packed_strip: wgpu::RenderPipeline,
+    // one row of a 1x1 copy, padded to COPY_BYTES_PER_ROW_ALIGNMENT
+    pub readback: wgpu::Buffer,
+    // mesh of each command in the last rendered frame, in command order
+    pub meshes: Vec<MeshId>,
+    // command index of each object index when sorting reordered the frame, else empty
+    pub order: Vec<usize>,
+}
+
+impl IdTarget {
+    pub fn new(
+        device: &wgpu::Device,
+        scene_bgl: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+        reverse_z: bool,
+    ) -> Self {
+        let (tex, view) = Self::create_target(device, width, height);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Picking Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
+                "../../shaders/picking.wgsl"
+            ))),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Picking Pipeline Layout"),
+            bind_group_layouts: &[scene_bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline_for = |topology, vertex_layout: wgpu::VertexBufferLayout| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Picking Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[vertex_layout],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: ID_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology,
+                    ..Default::default()
+                },
+                // tests against the forward pass depth so only the visible surface writes its id
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: depth_compare(wgpu::CompareFunction::LessEqual, reverse_z),
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let list = wgpu::PrimitiveTopology::TriangleList;
+        let strip = wgpu::PrimitiveTopology::TriangleStrip;
+        let pipeline = pipeline_for(list, Vertex::buffer_layout());
+        let pipeline_strip = pipeline_for(strip, Vertex::buffer_layout());
+        let pipeline_packed = pipeline_for(list, PackedVertex::buffer_layout());
+        let pipeline_packed_strip = pipeline_for(strip, PackedVertex::buffer_layout());
+
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Picking Readback"),
+            size: wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            tex,
+            view,
+            pipeline,
+            pipeline_strip,
+            pipeline_packed,
+            pipeline_packed_strip,
+            readback,
+            meshes: Vec::new(),
+            order: Vec::new(),
+        }
+    }
+
+    pub fn pipeline_for(&self, p: &PrimitiveRange) -> &wgpu::RenderPipeline {
+        let strip = p.topology == wgpu::PrimitiveTopology::TriangleStrip;
+        match (p.vertex_format, strip) {
+            (VertexFormat::Full, false) => &self.pipeline,
+            (VertexFormat::Full, true) => &self.pipeline_strip,
+            (VertexFormat::Packed, false) => &self.pipeline_packed,
+            (VertexFormat::Packed, true) => &self.pipeline_packed_strip,
+        }
+    }
+
+    pub fn create_target(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let tex = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Picking Ids"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ID_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = tex.create_view(&wgpu::TextureViewDescriptor::default());
+        (tex, view)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.tex, self.view) = Self::create_target(device, width, height);
+        self.meshes.clear();
+        self.order.clear();
+    }
+}
+
+impl ForwardRenderer {
+    // Enables the id pass; it costs one extra draw per object each frame while on.
+    pub fn set_picking(&mut self, enabled: bool) {
+        if !enabled {
+            self.picking = None;
+        } else if self.picking.is_none() {
+            let config = &self.context.config;
+            self.picking = Some(IdTarget::new(
+                &self.context.device,
+                &self.scene_bgl,
+                config.width,
+                config.height,
+                self.pipeline_config.reverse_z,
+            ));
+        }
+    }
+
+    pub fn render_ids(&self, encoder: &mut wgpu::CommandEncoder, action: &[RenderCommand]) {
+        let Some(picking) = &self.picking else {
+            return;
+        };
+
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Picking Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &picking.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        rpass.set_bind_group(0, &self.scene_bg, &[]);
+
+        // same clipping as the forward pass, so no id lands where its object wasn't drawn
+        let size = [picking.tex.width(), picking.tex.height()];
+        let mut current_scissor = None;
+        for (mesh_id, instances) in super::objects::mesh_runs(action) {
+            let Some(mesh) = self.asset.mesh(mesh_id) else {
+                continue;
+            };
+            let scissor = action[instances.start as usize].scissor;
+            if scissor != current_scissor {
+                ScissorRect::apply(scissor, &mut rpass, size);
+                current_scissor = scissor;
+            }
+            let (Some(index_buf), Some(index_fmt)) = (mesh.index_buf.as_ref(), mesh.index_format)
+            else {
+                continue;
+            };
+
+            rpass.set_vertex_buffer(0, mesh.vertex_buf.slice(..));
+            rpass.set_index_buffer(index_buf.slice(..), index_fmt);
+            for p in &mesh.primitives {
+                rpass.set_pipeline(picking.pipeline_for(p));
+                rpass.draw_indexed(
+                    p.first_index..p.first_index + p.index_count,
+                    p.base_vertex,
+                    instances.clone(),
+                );
+            }
+        }
+    }
+
+    // Index into the last frame's render commands of the object under pixel (x, y), or
+    // None for background. Blocks until the GPU has finished that frame. Returns None
+    // where mapping can't be waited on (WebGPU) or while picking is disabled.
+    pub fn pick_object(&self, x: u32, y: u32) -> Option<usize> {
+        let picking = self.picking.as_ref()?;
+        let config = &self.context.config;
+        if x >= config.width || y >= config.height {
+            return None;
+        }
+
+        let device = &self.context.device;
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Picking Readback Encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &picking.tex,
+                mip_level: 0,
+                origin: wgpu::Origin3d { x, y, z: 0 },
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &picking.readback,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+                    rows_per_image: Some(1),
+                },
+            },
+            wgpu::Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.context.queue.submit(Some(encoder.finish()));
+
+        let slice = picking.readback.slice(..4);
+        let (tx, rx) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        if device.poll(wgpu::PollType::Wait).is_err() {
+            eprintln!("pick: device poll failed");
+        }
+        let Ok(Ok(())) = rx.try_recv() else {
+            return None;
+        };
+
+        let id = {
+            let data = slice.get_mapped_range();
+            u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+        };
+        picking.readback.unmap();
+
+        let index = id.checked_sub(1)? as usize;
+        if index >= picking.meshes.len() {
+            return None;
+        }
+        Some(picking.order.get(index).copied().unwrap_or(index))
+    }
+
+    pub fn pick(&self, x: u32, y: u32) -> Option<MeshId> {
+        let index = self.pick_object(x, y)?;
+        self.picking.as_ref().map(|p| p.meshes[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::render::RenderCommand;
+    use crate::render::test_support::*;
+    use glam::Vec3;
+
+    #[test]
+    fn picks_the_visible_object_under_the_cursor() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let front = cube(&mut renderer, "front");
+        let back = cube(&mut renderer, "back");
+        let commands = [
+            draw(back, Vec3::new(0.0, 0.0, -2.0)),
+            draw(front, Vec3::ZERO),
+        ];
+
+        renderer
+            .render(&[light()], &camera(1.0), &commands)
+            .unwrap();
+        assert_eq!(renderer.pick(32, 32), None, "picking is off by default");
+
+        renderer.set_picking(true);
+        renderer
+            .render(&[light()], &camera(1.0), &commands)
+            .unwrap();
+        assert_eq!(renderer.pick(32, 32), Some(front));
+        assert_eq!(renderer.pick_object(32, 32), Some(1));
+        assert_eq!(renderer.pick(0, 0), None);
+        assert_eq!(renderer.pick(64, 32), None);
+    }
+
+    #[test]
+    fn picks_by_command_index_after_sorting() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let left = cube(&mut renderer, "left");
+        let right = cube(&mut renderer, "right");
+        renderer.set_picking(true);
+
+        // the sort draws these in reverse
+        let commands = [
+            RenderCommand {
+                sort_key: 1,
+                ..draw(left, Vec3::new(-1.0, 0.0, 0.0))
+            },
+            RenderCommand {
+                sort_key: 0,
+                ..draw(right, Vec3::new(1.0, 0.0, 0.0))
+            },
+        ];
+        renderer
+            .render(&[light()], &camera(1.0), &commands)
+            .unwrap();
+
+        assert_eq!(renderer.pick_object(13, 32), Some(0));
+        assert_eq!(renderer.pick(13, 32), Some(left));
+        assert_eq!(renderer.pick_object(51, 32), Some(1));
+        assert_eq!(renderer.pick(51, 32), Some(right));
+        assert_eq!(renderer.pick(32, 2), None);
+    }
+}
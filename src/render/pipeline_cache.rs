@@ -0,0 +1,113 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use super::ForwardRenderer;
+
+// Driver pipeline cache persisted between runs, so compiled forward variants don't have to
+// be rebuilt from scratch on every start. Only available with Features::PIPELINE_CACHE.
+pub struct PipelineCacheStore {
+    pub cache: Option<wgpu::PipelineCache>,
+    pub path: Option<PathBuf>,
+    // set when a pipeline was compiled since the last save
+    pub dirty: bool,
+}
+
+// One file per adapter/driver under the system temp dir; None where there is no filesystem
+// or wgpu can't key the adapter.
+pub fn default_cache_path(adapter_info: &wgpu::AdapterInfo) -> Option<PathBuf> {
+    if cfg!(target_arch = "wasm32") {
+        return None;
+    }
+    let key = wgpu::util::pipeline_cache_key(adapter_info)?;
+    Some(std::env::temp_dir().join("delta").join(key))
+}
+
+impl PipelineCacheStore {
+    pub fn load(device: &wgpu::Device, path: Option<PathBuf>) -> Self {
+        if !device.features().contains(wgpu::Features::PIPELINE_CACHE) {
+            return Self {
+                cache: None,
+                path,
+                dirty: false,
+            };
+        }
+
+        let data = path.as_deref().and_then(|p| std::fs::read(p).ok());
+        // SAFETY: the blob was written by `save` from PipelineCache::get_data, and its file
+        // name is keyed on the adapter. wgpu also validates the header and, with `fallback`,
+        // starts an empty cache if the data doesn't match.
+        let cache = unsafe {
+            device.create_pipeline_cache(&wgpu::PipelineCacheDescriptor {
+                label: Some("Pipeline Cache"),
+                data: data.as_deref(),
+                fallback: true,
+            })
+        };
+        if data.is_some() {
+            println!("gpu: loaded pipeline cache");
+        }
+
+        Self {
+            cache: Some(cache),
+            path,
+            dirty: false,
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let (Some(cache), Some(path)) = (&self.cache, &self.path) else {
+            return Ok(());
+        };
+        let Some(data) = cache.get_data() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("creating pipeline cache dir {}", dir.display()))?;
+        }
+        // write then rename so a crash mid-write never leaves a truncated blob behind
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, &data)
+            .with_context(|| format!("writing pipeline cache {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("writing pipeline cache {}", path.display()))?;
+        Ok(())
+    }
+
+    // Saves once for a batch of compiles; errors are only logged.
+    pub fn flush(&mut self) {
+        if !std::mem::take(&mut self.dirty) {
+            return;
+        }
+        if let Err(e) = self.save() {
+            eprintln!("gpu: {:#}", e);
+        }
+    }
+}
+
+// Catches pipelines compiled outside a batch, e.g. by a lone ensure_pipeline.
+impl Drop for PipelineCacheStore {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl ForwardRenderer {
+    pub fn pipeline_cache(&self) -> Option<&wgpu::PipelineCache> {
+        self.pipeline_cache.cache.as_ref()
+    }
+
+    pub fn pipeline_cache_path(&self) -> Option<&Path> {
+        self.pipeline_cache.path.as_deref()
+    }
+
+    // Changes where the cache blob is saved; takes effect on the next save.
+    pub fn set_pipeline_cache_path(&mut self, path: Option<PathBuf>) {
+        self.pipeline_cache.path = path;
+    }
+
+    pub fn save_pipeline_cache(&self) -> Result<()> {
+        self.pipeline_cache.save()
+    }
+}
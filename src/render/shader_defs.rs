@@ -0,0 +1,478 @@
+use std::collections::HashMap;
+
+use super::gpu::{self, ColorSpace};
+use super::grid::GroundGrid;
+use super::lines::LineRenderer;
+use super::shadow::SHADOW_FRONT_FACE;
+use super::{
+    DEPTH_COMPARE, DEPTH_FORMAT, ForwardRenderer, PipelineConfig, PipelineKey, RenderCommand,
+    depth_clear_value, depth_compare,
+};
+use crate::asset_manager::mesh::PrimitiveRange;
+
+// Snapshot of every setting that decides how the forward and shadow passes rasterize, so
+// tests can check the renderer's state after a series of setters without rendering.
+// Sample count is fixed for now: no MSAA.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DebugConfig {
+    pub format: wgpu::TextureFormat,
+    pub color_space: ColorSpace,
+    pub bindless: bool,
+    pub cull_mode: Option<wgpu::Face>,
+    pub front_face: wgpu::FrontFace,
+    pub depth_bias: wgpu::DepthBiasState,
+    pub depth_compare: wgpu::CompareFunction,
+    pub depth_format: wgpu::TextureFormat,
+    pub sample_count: u32,
+    pub reverse_z: bool,
+    pub depth_clear: f32,
+    pub shadow_cull_mode: Option<wgpu::Face>,
+    pub shadow_front_face: wgpu::FrontFace,
+}
+
+// Line numbers are 1-based.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PreprocessError {
+    ElseWithoutIfdef { line: usize },
+    EndifWithoutIfdef { line: usize },
+    // the line of the innermost #ifdef or #ifndef left open
+    UnterminatedIfdef { line: usize },
+}
+
+impl std::fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreprocessError::ElseWithoutIfdef { line } => {
+                write!(f, "line {}: #else without #ifdef", line)
+            }
+            PreprocessError::EndifWithoutIfdef { line } => {
+                write!(f, "line {}: #endif without #ifdef", line)
+            }
+            PreprocessError::UnterminatedIfdef { line } => {
+                write!(f, "line {}: #ifdef without #endif", line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PreprocessError {}
+
+// Minimal WGSL preprocessor: lines between `#ifdef NAME` / `#ifndef NAME` and `#else` /
+// `#endif` are kept or dropped depending on whether NAME is in `defs`. Blocks may nest.
+// Dropped lines are replaced by blank ones so naga errors keep their line numbers.
+// Whether the blocks are balanced doesn't depend on `defs`.
+pub fn preprocess(source: &str, defs: &[&str]) -> Result<String, PreprocessError> {
+    // one entry per open block: (this branch active, parent active, line it opened on)
+    let mut stack: Vec<(bool, bool, usize)> = Vec::new();
+    let mut out = String::with_capacity(source.len());
+
+    for (line_no, line) in source.lines().enumerate() {
+        let line_no = line_no + 1;
+        let trimmed = line.trim();
+        let active = stack.last().is_none_or(|&(on, _, _)| on);
+
+        if let Some(name) = trimmed.strip_prefix("#ifdef ") {
+            stack.push((active && defs.contains(&name.trim()), active, line_no));
+        } else if let Some(name) = trimmed.strip_prefix("#ifndef ") {
+            stack.push((active && !defs.contains(&name.trim()), active, line_no));
+        } else if trimmed == "#else" {
+            let (on, parent, opened) = stack
+                .pop()
+                .ok_or(PreprocessError::ElseWithoutIfdef { line: line_no })?;
+            stack.push((parent && !on, parent, opened));
+        } else if trimmed == "#endif" {
+            stack
+                .pop()
+                .ok_or(PreprocessError::EndifWithoutIfdef { line: line_no })?;
+        } else if active {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+
+    match stack.last() {
+        Some(&(_, _, line)) => Err(PreprocessError::UnterminatedIfdef { line }),
+        None => Ok(out),
+    }
+}
+
+impl PipelineConfig {
+    pub fn shader_defs(&self) -> Vec<&'static str> {
+        let mut defs = Vec::new();
+        if self.color_space == ColorSpace::Linear {
+            defs.push("LINEAR_OUTPUT");
+        } else if !self.format.is_srgb() {
+            // no sRGB view to encode on store; blending then happens on encoded values
+            defs.push("ENCODE_SRGB");
+        }
+        defs
+    }
+}
+
+impl ForwardRenderer {
+    pub fn pipeline_key(&self, primitive: &PrimitiveRange) -> PipelineKey {
+        PipelineKey {
+            features: self.asset.features_by_mat[primitive.material.0],
+            topology: primitive.topology,
+            vertex_format: primitive.vertex_format,
+        }
+    }
+
+    // Compiles the forward pipeline variant for `key` the first time it is needed. The cache
+    // is only marked dirty; callers compiling a batch save it once at the end.
+    pub fn ensure_pipeline(&mut self, key: PipelineKey) {
+        if self.pipelines.contains_key(&key) {
+            return;
+        }
+        // set_shader only accepts sources that preprocess, whatever the defs
+        let pipeline = Self::create_forward_pipeline(
+            &self.context.device,
+            &self.pipeline_layout,
+            self.pipeline_cache.cache.as_ref(),
+            &self.pipeline_config,
+            self.forward_shader.as_deref(),
+            key,
+        )
+        .expect("forward shader #ifdef blocks are balanced");
+        self.pipelines.insert(key, pipeline);
+        self.pipeline_cache.dirty = true;
+    }
+
+    // Every variant needed to draw `commands`.
+    pub fn ensure_pipelines_for(&mut self, commands: &[RenderCommand]) {
+        let mut keys: Vec<PipelineKey> = Vec::new();
+        for cmd in commands {
+            if let Some(mesh) = self.asset.mesh(cmd.mesh_id) {
+                keys.extend(mesh.primitives.iter().map(|p| self.pipeline_key(p)));
+            }
+        }
+        for key in keys {
+            self.ensure_pipeline(key);
+        }
+        self.pipeline_cache.flush();
+    }
+
+    // Recompiles every variant compiled so far, e.g. after pipeline_config changed.
+    pub fn rebuild_pipelines(&mut self) {
+        let variants: Vec<PipelineKey> = self.pipelines.keys().copied().collect();
+        self.pipelines.clear();
+        for key in variants {
+            self.ensure_pipeline(key);
+        }
+        self.pipeline_cache.flush();
+        // bundles hold on to the pipelines they were recorded with
+        self.rerecord_static_bundles();
+    }
+
+    // `None` disables culling. Defaults to no culling with counter-clockwise front faces.
+    pub fn set_cull_mode(&mut self, cull_mode: Option<wgpu::Face>, front_face: wgpu::FrontFace) {
+        self.pipeline_config.cull_mode = cull_mode;
+        self.pipeline_config.front_face = front_face;
+        self.rebuild_pipelines();
+    }
+
+    // Reconfigures the surface for `color_space`. Linear output skips the tonemap and gamma
+    // steps. Returns false, leaving the output unchanged, if the surface has no fitting format.
+    pub fn set_output_color_space(&mut self, color_space: ColorSpace) -> bool {
+        let ctx = &mut self.context;
+        // display output can still be encoded without an sRGB format, see configure_surface
+        let fallback = ctx
+            .surface_formats
+            .first()
+            .copied()
+            .filter(|_| color_space == ColorSpace::Srgb);
+        let Some(format) =
+            gpu::select_surface_format(&ctx.surface_formats, color_space).or(fallback)
+        else {
+            eprintln!("gpu: surface has no format for {:?} output", color_space);
+            return false;
+        };
+
+        ctx.configure_surface(format, color_space);
+        let format = ctx.view_format;
+
+        self.pipeline_config.format = format;
+        self.pipeline_config.color_space = color_space;
+        self.rebuild_pipelines();
+
+        self.rebuild_overlay_pipelines();
+        true
+    }
+
+    // The grid and line pipelines draw into the forward pass with its format and depth test.
+    pub fn rebuild_overlay_pipelines(&mut self) {
+        let device = &self.context.device;
+        let config = &self.pipeline_config;
+        if let Some(grid) = self.grid.as_mut() {
+            *grid = GroundGrid::new(device, config.format, grid.config, config.reverse_z);
+        }
+        self.lines.reverse_z = config.reverse_z;
+        self.lines.pipeline =
+            LineRenderer::create_pipeline(device, &self.lines.bgl, config.format, config.reverse_z);
+    }
+
+    pub fn output_color_space(&self) -> ColorSpace {
+        self.pipeline_config.color_space
+    }
+
+    // Offsets forward pass depth by `constant` units plus `slope_scale` times the depth
+    // slope, limited to `clamp` when it is non-zero. Useful against z-fighting of decals and
+    // coplanar geometry; all zero (the default) disables it.
+    pub fn set_depth_bias(&mut self, constant: i32, slope_scale: f32, clamp: f32) {
+        self.pipeline_config.depth_bias = wgpu::DepthBiasState {
+            constant,
+            slope_scale,
+            clamp,
+        };
+        self.rebuild_pipelines();
+    }
+
+    // Depth the forward pass clears to before drawing; depth_clear_value by default. A value
+    // nearer than the far plane keeps everything behind it from being drawn, and the near
+    // plane itself (0.0, or 1.0 with reverse-z) draws nothing at all.
+    pub fn set_depth_clear(&mut self, depth: f32) {
+        self.depth_clear = depth.clamp(0.0, 1.0);
+    }
+
+    pub fn depth_clear(&self) -> f32 {
+        self.depth_clear
+    }
+
+    // Maps the near plane to depth 1.0 and the far plane to 0.0, which spreads float depth
+    // precision more evenly over distance. Flips every depth test drawing into the forward
+    // pass and resets the depth clear to the new far plane. Shadow maps are unaffected.
+    pub fn set_reverse_z(&mut self, enabled: bool) {
+        if self.pipeline_config.reverse_z == enabled {
+            return;
+        }
+        self.pipeline_config.reverse_z = enabled;
+        self.depth_clear = depth_clear_value(enabled);
+        self.rebuild_pipelines();
+        self.rebuild_overlay_pipelines();
+        if self.picking.is_some() {
+            self.set_picking(false);
+            self.set_picking(true);
+        }
+        self.update_camera_buffer();
+    }
+
+    pub fn reverse_z(&self) -> bool {
+        self.pipeline_config.reverse_z
+    }
+
+    pub fn debug_config(&self) -> DebugConfig {
+        let config = &self.pipeline_config;
+        DebugConfig {
+            format: config.format,
+            color_space: config.color_space,
+            bindless: config.bindless,
+            cull_mode: config.cull_mode,
+            front_face: config.front_face,
+            depth_bias: config.depth_bias,
+            depth_compare: depth_compare(DEPTH_COMPARE, config.reverse_z),
+            depth_format: DEPTH_FORMAT,
+            sample_count: 1,
+            reverse_z: config.reverse_z,
+            depth_clear: self.depth_clear,
+            shadow_cull_mode: self.shadows.cull_mode,
+            shadow_front_face: SHADOW_FRONT_FACE,
+        }
+    }
+
+    // Draws every mesh with `source` instead of forward.wgsl. It has to declare the same
+    // bind groups 0, 2 and 3 and the vertex inputs, and gets the group 1 texture bindings and
+    // sample_* helpers appended; #ifdef variants work as in forward.wgsl. The default variant
+    // and every variant compiled so far are built before anything is swapped in, and an
+    // error in any of them leaves the current shader in place. On the web, validation errors
+    // are only reported asynchronously, so the shader is taken as is.
+    pub fn set_shader(&mut self, source: &str) -> anyhow::Result<()> {
+        let mut keys = vec![PipelineKey::default()];
+        keys.extend(
+            self.pipelines
+                .keys()
+                .filter(|&&k| k != PipelineKey::default()),
+        );
+
+        let device = &self.context.device;
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let compiled: Result<Vec<_>, _> = keys
+            .iter()
+            .map(|&key| {
+                Self::create_forward_pipeline(
+                    device,
+                    &self.pipeline_layout,
+                    self.pipeline_cache.cache.as_ref(),
+                    &self.pipeline_config,
+                    Some(source),
+                    key,
+                )
+                .map(|pipeline| (key, pipeline))
+            })
+            .collect();
+        let mut scope = std::pin::pin!(device.pop_error_scope());
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        if let std::task::Poll::Ready(Some(e)) = scope.as_mut().poll(&mut cx) {
+            anyhow::bail!("forward shader: {}", e);
+        }
+        let compiled = compiled.map_err(|e| anyhow::anyhow!("forward shader: {}", e))?;
+
+        self.forward_shader = Some(source.to_string());
+        self.pipelines = compiled.into_iter().collect();
+        self.pipeline_cache.dirty = true;
+        self.pipeline_cache.flush();
+        // bundles hold on to the pipelines they were recorded with
+        self.rerecord_static_bundles();
+        Ok(())
+    }
+
+    // Goes back to the built-in forward.wgsl.
+    pub fn reset_shader(&mut self) {
+        if self.forward_shader.take().is_some() {
+            self.rebuild_pipelines();
+        }
+    }
+
+    pub fn pipeline_variants(&self) -> &HashMap<PipelineKey, wgpu::RenderPipeline> {
+        &self.pipelines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PreprocessError, preprocess};
+    use crate::asset_manager::MeshId;
+    use crate::asset_manager::mesh::Primitive;
+    use crate::asset_manager::test_support as assets;
+    use crate::render::ForwardRenderer;
+    use crate::render::test_support::*;
+    use glam::Vec3;
+
+    #[test]
+    fn preprocess_keeps_the_active_branches() {
+        let source = "a\n#ifdef X\nb\n#ifndef Y\nc\n#else\nd\n#endif\n#else\ne\n#endif\nf";
+        assert_eq!(
+            preprocess(source, &["X"]).unwrap(),
+            "a\n\nb\n\nc\n\n\n\n\n\n\nf\n"
+        );
+        assert_eq!(
+            preprocess(source, &["X", "Y"]).unwrap(),
+            "a\n\nb\n\n\n\nd\n\n\n\n\nf\n"
+        );
+        assert_eq!(
+            preprocess(source, &[]).unwrap(),
+            "a\n\n\n\n\n\n\n\n\ne\n\nf\n"
+        );
+    }
+
+    #[test]
+    fn preprocess_reports_unbalanced_blocks() {
+        assert_eq!(
+            preprocess("a\n#else\n", &[]),
+            Err(PreprocessError::ElseWithoutIfdef { line: 2 })
+        );
+        assert_eq!(
+            preprocess("#ifdef X\n#endif\n#endif\n", &["X"]),
+            Err(PreprocessError::EndifWithoutIfdef { line: 3 })
+        );
+        assert_eq!(
+            preprocess("#ifdef X\n#ifdef Y\n#endif\n", &[]),
+            Err(PreprocessError::UnterminatedIfdef { line: 1 })
+        );
+    }
+
+    // A cube mesh with a lit and an unlit primitive.
+    fn lit_and_unlit(renderer: &mut ForwardRenderer, dir: &std::path::Path) -> MeshId {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "extensionsUsed": ["KHR_materials_unlit"],
+            "materials": [
+                { "name": "lit" },
+                { "name": "flat", "extensions": { "KHR_materials_unlit": {} } }
+            ]
+        }"#;
+        let path = dir.join("materials.gltf");
+        std::fs::write(&path, json).unwrap();
+        let path = path.to_str().unwrap();
+
+        let asset = &mut renderer.asset;
+        let lit = asset.get_material(&format!("{}#lit", path)).unwrap();
+        let flat = asset.get_material(&format!("{}#flat", path)).unwrap();
+        let mesh = asset
+            .set_mesh(
+                &[Primitive::cube(0.5), Primitive::cube(0.25)],
+                "two materials",
+            )
+            .unwrap();
+        asset.set_mat(mesh, 0, lit).unwrap();
+        asset.set_mat(mesh, 1, flat).unwrap();
+        mesh
+    }
+
+    #[test]
+    fn each_feature_set_gets_its_own_cached_pipeline() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let dir = assets::temp_dir("pipeline-variants");
+        let mesh = lit_and_unlit(&mut renderer, &dir);
+
+        // the lit material uses the default variant, compiled up front
+        assert_eq!(renderer.pipeline_variants().len(), 1);
+        let commands = [draw(mesh, Vec3::ZERO)];
+        renderer.ensure_pipelines_for(&commands);
+        let variants = renderer.pipeline_variants();
+        assert_eq!(variants.len(), 2);
+        assert!(variants.keys().any(|k| !k.features.unlit));
+        assert!(variants.keys().any(|k| k.features.unlit));
+
+        // drawing again reuses them
+        renderer
+            .render(&[light()], &camera(1.0), &commands)
+            .unwrap();
+        assert_eq!(renderer.pipeline_variants().len(), 2);
+    }
+
+    #[test]
+    fn the_pipeline_cache_is_saved_once_per_batch() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let dir = assets::temp_dir("pipeline-cache-batch");
+        let mesh = lit_and_unlit(&mut renderer, &dir);
+        let commands = [draw(mesh, Vec3::ZERO)];
+        let key = renderer.pipeline_key(&renderer.asset.mesh(mesh).unwrap().primitives[1]);
+
+        renderer.ensure_pipeline(key);
+        assert!(renderer.pipeline_cache.dirty);
+        renderer.ensure_pipelines_for(&commands);
+        assert!(!renderer.pipeline_cache.dirty);
+
+        renderer.set_cull_mode(Some(wgpu::Face::Back), wgpu::FrontFace::Ccw);
+        assert_eq!(renderer.pipeline_variants().len(), 2);
+        assert!(!renderer.pipeline_cache.dirty);
+    }
+
+    #[test]
+    fn a_broken_variant_leaves_the_current_shader_in_place() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let dir = assets::temp_dir("set-shader");
+        let mesh = lit_and_unlit(&mut renderer, &dir);
+        renderer.ensure_pipelines_for(&[draw(mesh, Vec3::ZERO)]);
+        assert_eq!(renderer.pipeline_variants().len(), 2);
+
+        let forward = include_str!("../../shaders/forward.wgsl");
+        // the default variant compiles, only the cached unlit one fails validation
+        let broken = format!("{}\n#ifdef UNLIT\nnot wgsl\n#endif\n", forward);
+        assert!(renderer.set_shader(&broken).is_err());
+        let unbalanced = format!("{}\n#ifdef UNLIT\n", forward);
+        assert!(renderer.set_shader(&unbalanced).is_err());
+        assert!(renderer.forward_shader.is_none());
+        assert_eq!(renderer.pipeline_variants().len(), 2);
+
+        renderer.set_shader(forward).unwrap();
+        assert_eq!(renderer.forward_shader.as_deref(), Some(forward));
+        assert_eq!(renderer.pipeline_variants().len(), 2);
+    }
+}
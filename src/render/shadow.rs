@@ -0,0 +1,442 @@
+use glam::{Mat4, Vec3};
+use std::num::NonZeroU64;
+
+use super::ForwardRenderer;
+use crate::asset_manager::light::{Light, LightKind};
+use crate::asset_manager::mesh::{PrimitiveRange, Vertex};
+use crate::asset_manager::packing::{PackedVertex, VertexFormat};
+
+pub const SHADOW_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+pub const POINT_SHADOW_SIZE: u32 = 512;
+pub const DEFAULT_MAX_SHADOW_CASTERS: usize = 4;
+pub const POINT_SHADOW_NEAR: f32 = 0.05;
+// the left-handed cube face transforms mirror the scene, so faces that are
+// counter-clockwise in the main pass arrive in the shadow pass clockwise
+pub const SHADOW_FRONT_FACE: wgpu::FrontFace = wgpu::FrontFace::Cw;
+
+// +X, -X, +Y, -Y, +Z, -Z with the up vectors matching the cube sampling convention
+const CUBE_FACES: [(Vec3, Vec3); 6] = [
+    (Vec3::X, Vec3::Y),
+    (Vec3::NEG_X, Vec3::Y),
+    (Vec3::Y, Vec3::NEG_Z),
+    (Vec3::NEG_Y, Vec3::Z),
+    (Vec3::Z, Vec3::Y),
+    (Vec3::NEG_Z, Vec3::Y),
+];
+
+#[repr(C, align(16))]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShadowFaceUniform {
+    pub view_proj: [[f32; 4]; 4],
+    pub light_pos: [f32; 3],
+    pub range: f32,
+    pub _pad: [f32; 44],
+}
+
+pub struct PointShadowMaps {
+    pub max_casters: usize,
+    pub tex: wgpu::Texture,
+    // one view per cube face, 6 per caster
+    pub face_views: Vec<wgpu::TextureView>,
+    pub cube_view: wgpu::TextureView,
+    pub sampler: wgpu::Sampler,
+
+    pub face_buffer: wgpu::Buffer,
+    pub face_bgl: wgpu::BindGroupLayout,
+    pub face_bg: wgpu::BindGroup,
+    pub pipeline: wgpu::RenderPipeline,
+    // same, for triangle strip primitives
+    pub pipeline_strip: wgpu::RenderPipeline,
+    // both topologies again for meshes in the packed vertex format
+    pub pipeline_packed: wgpu::RenderPipeline,
+    pub pipeline_packed_strip: wgpu::RenderPipeline,
+    // independent of the main pass; None draws both sides into the maps
+    pub cull_mode: Option<wgpu::Face>,
+}
+
+pub fn cube_face_view_projs(position: Vec3, range: f32) -> [Mat4; 6] {
+    let proj = Mat4::perspective_lh(std::f32::consts::FRAC_PI_2, 1.0, POINT_SHADOW_NEAR, range);
+    CUBE_FACES.map(|(dir, up)| proj * Mat4::look_to_lh(position, dir, up))
+}
+
+// Returns the indices into `lights` that get a shadow slot, in slot order.
+pub fn shadow_casters(lights: &[Light], max_casters: usize) -> Vec<usize> {
+    lights
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| l.enabled && matches!(l.kind, LightKind::Point) && l.range.is_finite())
+        .map(|(i, _)| i)
+        .take(max_casters)
+        .collect()
+}
+
+impl PointShadowMaps {
+    pub fn new(
+        device: &wgpu::Device,
+        vertex_layout: wgpu::VertexBufferLayout,
+        object_bgl: &wgpu::BindGroupLayout,
+        max_casters: usize,
+    ) -> Self {
+        let max_casters = max_casters.min(device.limits().max_texture_array_layers as usize / 6);
+
+        let (tex, face_views, cube_view) = Self::create_maps(device, max_casters);
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Point Shadow Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let face_buffer = Self::create_face_buffer(device, max_casters);
+
+        let face_bgl = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Face BGL"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: NonZeroU64::new(80), // must match WGSL struct size
+                },
+                count: None,
+            }],
+        });
+        let face_bg = Self::create_face_bind_group(device, &face_bgl, &face_buffer);
+
+        let cull_mode = Some(wgpu::Face::Front);
+        let (pipeline, pipeline_strip) =
+            Self::create_pipelines(device, &face_bgl, object_bgl, &vertex_layout, cull_mode);
+        let (pipeline_packed, pipeline_packed_strip) = Self::create_pipelines(
+            device,
+            &face_bgl,
+            object_bgl,
+            &PackedVertex::buffer_layout(),
+            cull_mode,
+        );
+
+        Self {
+            max_casters,
+            tex,
+            face_views,
+            cube_view,
+            sampler,
+            face_buffer,
+            face_bgl,
+            face_bg,
+            pipeline,
+            pipeline_strip,
+            pipeline_packed,
+            pipeline_packed_strip,
+            cull_mode,
+        }
+    }
+
+    // Rendering back faces only moves the depth a surface's thickness away from the light,
+    // which hides acne without the peter-panning of a depth bias.
+    pub fn create_pipelines(
+        device: &wgpu::Device,
+        face_bgl: &wgpu::BindGroupLayout,
+        object_bgl: &wgpu::BindGroupLayout,
+        vertex_layout: &wgpu::VertexBufferLayout,
+        cull_mode: Option<wgpu::Face>,
+    ) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Point Shadow Shader"),
+            source: wgpu::ShaderSource::Wgsl(std::borrow::Cow::Borrowed(include_str!(
+                "../../shaders/shadow_point.wgsl"
+            ))),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Point Shadow Pipeline Layout"),
+            bind_group_layouts: &[face_bgl, object_bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline_for = |topology| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Point Shadow Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: std::slice::from_ref(vertex_layout),
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology,
+                    front_face: SHADOW_FRONT_FACE,
+                    cull_mode,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: SHADOW_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::Less,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let pipeline = pipeline_for(wgpu::PrimitiveTopology::TriangleList);
+        let pipeline_strip = pipeline_for(wgpu::PrimitiveTopology::TriangleStrip);
+        (pipeline, pipeline_strip)
+    }
+
+    pub fn pipeline_for(&self, p: &PrimitiveRange) -> &wgpu::RenderPipeline {
+        let strip = p.topology == wgpu::PrimitiveTopology::TriangleStrip;
+        match (p.vertex_format, strip) {
+            (VertexFormat::Full, false) => &self.pipeline,
+            (VertexFormat::Full, true) => &self.pipeline_strip,
+            (VertexFormat::Packed, false) => &self.pipeline_packed,
+            (VertexFormat::Packed, true) => &self.pipeline_packed_strip,
+        }
+    }
+
+    pub fn create_maps(
+        device: &wgpu::Device,
+        max_casters: usize,
+    ) -> (wgpu::Texture, Vec<wgpu::TextureView>, wgpu::TextureView) {
+        // a cube array can't be empty, so keep at least one cube around
+        let cubes = max_casters.max(1) as u32;
+
+        let tex = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Point Shadow Maps"),
+            size: wgpu::Extent3d {
+                width: POINT_SHADOW_SIZE,
+                height: POINT_SHADOW_SIZE,
+                depth_or_array_layers: 6 * cubes,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: SHADOW_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+
+        let face_views = (0..6 * cubes)
+            .map(|layer| {
+                tex.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Point Shadow Face"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        let cube_view = tex.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Point Shadow Cube Array"),
+            dimension: Some(wgpu::TextureViewDimension::CubeArray),
+            array_layer_count: Some(6 * cubes),
+            ..Default::default()
+        });
+
+        (tex, face_views, cube_view)
+    }
+
+    fn create_face_buffer(device: &wgpu::Device, max_casters: usize) -> wgpu::Buffer {
+        let size = 6 * max_casters.max(1) * std::mem::size_of::<ShadowFaceUniform>();
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Shadow Face Buffer"),
+            size: size as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_face_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Face BG"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer,
+                    offset: 0,
+                    size: NonZeroU64::new(std::mem::size_of::<ShadowFaceUniform>() as u64),
+                }),
+            }],
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, max_casters: usize) {
+        let max_casters = max_casters.min(device.limits().max_texture_array_layers as usize / 6);
+        let (tex, face_views, cube_view) = Self::create_maps(device, max_casters);
+        self.tex = tex;
+        self.face_views = face_views;
+        self.cube_view = cube_view;
+        self.face_buffer = Self::create_face_buffer(device, max_casters);
+        self.face_bg = Self::create_face_bind_group(device, &self.face_bgl, &self.face_buffer);
+        self.max_casters = max_casters;
+    }
+}
+
+impl ForwardRenderer {
+    // Defaults to Some(Front): only back faces are drawn into the shadow maps. Single-sided
+    // geometry such as a plane facing the light casts no shadow that way; use None for it.
+    pub fn set_shadow_cull_mode(&mut self, cull_mode: Option<wgpu::Face>) {
+        let (pipeline, pipeline_strip) = PointShadowMaps::create_pipelines(
+            &self.context.device,
+            &self.shadows.face_bgl,
+            &self.objects.bgl,
+            &Vertex::buffer_layout(),
+            cull_mode,
+        );
+        let (pipeline_packed, pipeline_packed_strip) = PointShadowMaps::create_pipelines(
+            &self.context.device,
+            &self.shadows.face_bgl,
+            &self.objects.bgl,
+            &PackedVertex::buffer_layout(),
+            cull_mode,
+        );
+        self.shadows.pipeline = pipeline;
+        self.shadows.pipeline_strip = pipeline_strip;
+        self.shadows.pipeline_packed = pipeline_packed;
+        self.shadows.pipeline_packed_strip = pipeline_packed_strip;
+        self.shadows.cull_mode = cull_mode;
+    }
+
+    pub fn set_max_shadow_casters(&mut self, max_casters: usize) {
+        self.shadows.resize(&self.context.device, max_casters);
+        self.rebuild_scene_bind_group();
+    }
+
+    pub fn max_shadow_casters(&self) -> usize {
+        self.shadows.max_casters
+    }
+
+    // Renders linear light distance into each caster's 6 cube faces.
+    pub fn render_point_shadows(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        lights: &[Light],
+        casters: &[usize],
+        action: &[super::RenderCommand],
+    ) {
+        if casters.is_empty() {
+            return;
+        }
+
+        let faces: Vec<ShadowFaceUniform> = casters
+            .iter()
+            .flat_map(|&i| {
+                let l = &lights[i];
+                cube_face_view_projs(Vec3::from(l.position), l.range).map(|vp| ShadowFaceUniform {
+                    view_proj: vp.to_cols_array_2d(),
+                    light_pos: l.position,
+                    range: l.range,
+                    _pad: [0.0; 44],
+                })
+            })
+            .collect();
+        self.context
+            .queue
+            .write_buffer(&self.shadows.face_buffer, 0, bytemuck::cast_slice(&faces));
+
+        for (face, view) in self.shadows.face_views.iter().take(faces.len()).enumerate() {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Point Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+
+            let offset = (face * std::mem::size_of::<ShadowFaceUniform>()) as u32;
+            rpass.set_bind_group(0, &self.shadows.face_bg, &[offset]);
+            rpass.set_bind_group(1, &self.objects.bg, &[]);
+
+            for (mesh_id, instances) in super::objects::mesh_runs(action) {
+                let Some(mesh) = self.asset.mesh(mesh_id) else {
+                    continue;
+                };
+                let (Some(index_buf), Some(index_fmt)) =
+                    (mesh.index_buf.as_ref(), mesh.index_format)
+                else {
+                    continue;
+                };
+
+                rpass.set_vertex_buffer(0, mesh.vertex_buf.slice(..));
+                rpass.set_index_buffer(index_buf.slice(..), index_fmt);
+                for p in &mesh.primitives {
+                    rpass.set_pipeline(self.shadows.pipeline_for(p));
+                    rpass.draw_indexed(
+                        p.first_index..p.first_index + p.index_count,
+                        p.base_vertex,
+                        instances.clone(),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::test_support::*;
+
+    #[test]
+    fn cube_maps_have_six_layers_per_caster() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        for casters in [1, 3] {
+            renderer.set_max_shadow_casters(casters);
+            let shadows = &renderer.shadows;
+            assert_eq!(renderer.max_shadow_casters(), casters);
+            assert_eq!(shadows.tex.depth_or_array_layers(), 6 * casters as u32);
+            assert_eq!(shadows.face_views.len(), 6 * casters);
+        }
+
+        // no casters still keeps one cube, since a cube array can't be empty
+        let device = &renderer.context.device;
+        let (tex, face_views, _) = PointShadowMaps::create_maps(device, 0);
+        assert_eq!(tex.depth_or_array_layers(), 6);
+        assert_eq!(face_views.len(), 6);
+    }
+
+    #[test]
+    fn only_enabled_finite_point_lights_cast_shadows() {
+        let spot = Light {
+            kind: LightKind::Spot,
+            ..Default::default()
+        };
+        let disabled = Light {
+            enabled: false,
+            ..Default::default()
+        };
+        let lights = [Light::default(), spot, disabled, Light::default()];
+        assert_eq!(shadow_casters(&lights, 4), vec![0, 3]);
+        assert_eq!(shadow_casters(&lights, 1), vec![0]);
+    }
+}
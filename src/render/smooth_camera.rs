@@ -0,0 +1,45 @@
+use super::Camera;
+
+// Eases a camera toward a target camera instead of snapping to it. Callers move `target`
+// (e.g. from input) and render with `current`.
+#[derive(Clone, Copy)]
+pub struct SmoothCamera {
+    pub target: Camera,
+    pub current: Camera,
+    // per second; about 1 / rate seconds to cover 63% of the remaining distance
+    pub rate: f32,
+}
+
+impl SmoothCamera {
+    pub fn new(camera: Camera, rate: f32) -> Self {
+        Self {
+            target: camera,
+            current: camera,
+            rate,
+        }
+    }
+
+    // Moves `current` toward `target` by an amount that is independent of the frame rate.
+    pub fn update(&mut self, dt: f32) -> &Camera {
+        let t = 1.0 - (-self.rate * dt.max(0.0)).exp();
+        let (cur, tgt) = (&mut self.current, &self.target);
+
+        cur.eye = cur.eye.lerp(tgt.eye, t);
+        cur.target = cur.target.lerp(tgt.target, t);
+        cur.fov_y_radians += (tgt.fov_y_radians - cur.fov_y_radians) * t;
+        // lerping opposite up vectors passes through zero, so fall back to the target's
+        cur.up = cur.up.lerp(tgt.up, t).try_normalize().unwrap_or(tgt.up);
+
+        // projection bounds and aspect follow the window and clip settings, not motion
+        cur.z_near = tgt.z_near;
+        cur.z_far = tgt.z_far;
+        cur.aspect = tgt.aspect;
+
+        &self.current
+    }
+
+    // Jumps straight to the target, e.g. after a teleport or a cut.
+    pub fn snap(&mut self) {
+        self.current = self.target;
+    }
+}
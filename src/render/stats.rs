@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+
+use super::ForwardRenderer;
+use crate::asset_manager::mesh::PrimitiveRange;
+
+// Frames the FPS estimate averages over.
+pub const FPS_WINDOW: usize = 60;
+
+// Counts for the forward pass of the last rendered frame, static bundles included. Shadow,
+// picking, line and grid draws are not counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub draw_calls: usize,
+    pub triangles: usize,
+    pub instances: usize,
+    // instances rejected by frustum culling before the draw list was built
+    pub culled: usize,
+}
+
+impl RenderStats {
+    pub fn record_draw(&mut self, primitive: &PrimitiveRange, instances: u32) {
+        let per_instance = match primitive.topology {
+            wgpu::PrimitiveTopology::TriangleStrip => primitive.index_count.saturating_sub(2),
+            _ => primitive.index_count / 3,
+        };
+        self.draw_calls += 1;
+        self.triangles += per_instance as usize * instances as usize;
+        self.instances += instances as usize;
+    }
+
+    pub fn add(&mut self, other: &RenderStats) {
+        self.draw_calls += other.draw_calls;
+        self.triangles += other.triangles;
+        self.instances += other.instances;
+        self.culled += other.culled;
+    }
+}
+
+// Counts presented frames and keeps the durations between the most recent ones.
+#[derive(Clone, Debug, Default)]
+pub struct FrameTimer {
+    pub frame_count: u64,
+    // seconds, see now_seconds
+    pub last_frame: Option<f64>,
+    pub frame_times: VecDeque<f64>,
+}
+
+impl FrameTimer {
+    pub fn tick(&mut self, now: f64) {
+        self.frame_count += 1;
+        if let Some(last) = self.last_frame {
+            if self.frame_times.len() == FPS_WINDOW {
+                self.frame_times.pop_front();
+            }
+            self.frame_times.push_back((now - last).max(0.0));
+        }
+        self.last_frame = Some(now);
+    }
+
+    // 0 until two frames have been timed.
+    pub fn fps(&self) -> f32 {
+        let total: f64 = self.frame_times.iter().sum();
+        if total <= 0.0 {
+            return 0.0;
+        }
+        (self.frame_times.len() as f64 / total) as f32
+    }
+}
+
+// Seconds since an arbitrary fixed point. std::time::Instant isn't available in the browser.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_seconds() -> f64 {
+    static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+    START
+        .get_or_init(std::time::Instant::now)
+        .elapsed()
+        .as_secs_f64()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn now_seconds() -> f64 {
+    web_sys::window()
+        .and_then(|w| w.performance())
+        .map_or(0.0, |p| p.now() / 1000.0)
+}
+
+impl ForwardRenderer {
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    // Frames presented by render so far; offscreen renders are not counted.
+    pub fn frame_count(&self) -> u64 {
+        self.frames.frame_count
+    }
+
+    // Averaged over the last FPS_WINDOW presented frames.
+    pub fn fps(&self) -> f32 {
+        self.frames.fps()
+    }
+
+    // AssetManager::estimated_gpu_memory_bytes plus the light buffers. Render targets,
+    // shadow maps and per-frame buffers aren't counted.
+    pub fn estimated_gpu_memory_bytes(&self) -> u64 {
+        self.asset.estimated_gpu_memory_bytes() + self.light_ssbo.size() + self.light_params.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asset_manager::mesh::Primitive;
+    use crate::render::test_support::*;
+    use glam::{Mat4, Vec3};
+
+    #[test]
+    fn counts_each_primitive_of_a_mesh() {
+        let Some(mut renderer) = renderer(64, 64) else {
+            return;
+        };
+        let mut top = Primitive::cube(0.25);
+        for v in &mut top.vertex {
+            v.position[1] += 0.75;
+        }
+        let mesh = renderer
+            .asset
+            .set_mesh(&[Primitive::cube(0.5), top], "two cubes")
+            .unwrap();
+        let cam = camera(1.0);
+
+        renderer.render(&[light()], &cam, &[draw(mesh, Vec3::ZERO)]);
+        let one = renderer.stats;
+        assert_eq!(one.draw_calls, 2);
+        assert_eq!(one.triangles, 24);
+        assert_eq!(one.culled, 0);
+
+        // one visible instance and one behind the camera
+        let transforms = [Mat4::IDENTITY, Mat4::from_translation(Vec3::Z * 10.0)];
+        renderer.render_instanced_culled(&[light()], &cam, mesh, &transforms);
+        assert_eq!(renderer.stats.triangles, 24);
+        assert_eq!(renderer.stats.culled, 1);
+    }
+}
@@ -0,0 +1,45 @@
+use super::ForwardRenderer;
+use crate::asset_manager::mesh::Primitive;
+use crate::asset_manager::{AssetError, MeshId};
+
+// Staging chunks are reused across frames; uploads larger than this get their own chunk.
+pub const STREAM_CHUNK_SIZE: wgpu::BufferAddress = 4 << 20;
+
+impl ForwardRenderer {
+    // Like AssetManager::rewrite_mesh, but stages the data through the renderer's staging
+    // belt and records buffer copies instead of going through queue.write_buffer. The copies
+    // are submitted together with the next `render` call. Fails like rewrite_mesh.
+    pub fn stream_mesh(
+        &mut self,
+        mesh_id: MeshId,
+        primitives: &[Primitive],
+    ) -> Result<(), AssetError> {
+        let device = &self.context.device;
+        let belt = &mut self.stream_belt;
+        let encoder = self.stream_encoder.get_or_insert_with(|| {
+            device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Mesh Stream Encoder"),
+            })
+        });
+
+        self.asset
+            .rewrite_mesh_with(mesh_id, primitives, |buf, data| {
+                // copies must be 4 byte aligned; mesh buffers are padded to match
+                let size = (data.len() as wgpu::BufferAddress)
+                    .next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT);
+                let Some(size) = wgpu::BufferSize::new(size) else {
+                    return;
+                };
+                let mut view = belt.write_buffer(encoder, buf, 0, size, device);
+                view[..data.len()].copy_from_slice(data);
+                view[data.len()..].fill(0);
+            })
+    }
+
+    // Closes the staging belt and returns the recorded copies, if any were streamed.
+    pub fn take_stream_commands(&mut self) -> Option<wgpu::CommandBuffer> {
+        let encoder = self.stream_encoder.take()?;
+        self.stream_belt.finish();
+        Some(encoder.finish())
+    }
+}